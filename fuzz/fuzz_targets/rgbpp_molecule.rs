@@ -0,0 +1,58 @@
+//! Feeds arbitrary bytes through the decoders the RGBPP parser runs on lock args and
+//! unlock witnesses.
+//!
+//! Run with `cargo fuzz run rgbpp_molecule` from the repository root. Seeds live in
+//! `fuzz/corpus/rgbpp_molecule`; a golden run with `fuzz_corpus` set writes the lock
+//! args and witnesses of its captured transactions there.
+#![no_main]
+
+use ckb_jsonrpc_types::{CellOutput, JsonBytes, Script, ScriptHashType};
+use ckb_types::H256;
+use libfuzzer_sys::fuzz_target;
+use tokio::sync::mpsc;
+use unistate_ckb::{
+    config::RgbppScriptConfig,
+    rgbpp::{self, BtcConfirmations, RgbppContext},
+};
+
+const CODE_HASH: H256 = H256([1u8; 32]);
+
+fuzz_target!(|data: &[u8]| {
+    let _ = rgbpp::decode_lock_id(data);
+    let _ = rgbpp::decode_btc_tx_proof(data);
+    let _ = rgbpp::decode_btc_timelocks(data);
+
+    // The witness path of an RGBPP input, with every optional decode of the unlock on.
+    let ctx = RgbppContext::new(vec![RgbppScriptConfig {
+        version: 1,
+        code_hash: CODE_HASH,
+        hash_type: ScriptHashType::Type,
+    }])
+    .with_btc_tx_proof_json(true)
+    .with_btc_timelocks(true)
+    .with_btc_confirmations(Some(BtcConfirmations {
+        tip: 800_000,
+        required: 6,
+    }));
+    let pre_output = CellOutput {
+        capacity: 0.into(),
+        lock: Script {
+            code_hash: CODE_HASH,
+            hash_type: ScriptHashType::Type,
+            args: JsonBytes::default(),
+        },
+        type_: None,
+    };
+
+    let (op_sender, mut op_recv) = mpsc::unbounded_channel();
+    rgbpp::index_unlocks(
+        &[JsonBytes::from_vec(data.to_vec())],
+        &[Some(pre_output)],
+        &ctx,
+        &H256::default(),
+        None,
+        &op_sender,
+    )
+    .unwrap();
+    while op_recv.try_recv().is_ok() {}
+});
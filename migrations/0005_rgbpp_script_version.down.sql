@@ -0,0 +1,5 @@
+ALTER TABLE rgbpp_locks
+DROP COLUMN IF EXISTS script_version;
+
+ALTER TABLE rgbpp_unlocks
+DROP COLUMN IF EXISTS script_version;
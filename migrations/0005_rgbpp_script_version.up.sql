@@ -0,0 +1,6 @@
+-- 记录匹配到的 RGBPP lock 脚本版本
+ALTER TABLE rgbpp_locks
+ADD COLUMN script_version SMALLINT NOT NULL DEFAULT 1;
+
+ALTER TABLE rgbpp_unlocks
+ADD COLUMN script_version SMALLINT NOT NULL DEFAULT 1;
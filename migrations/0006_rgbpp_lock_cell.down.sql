@@ -0,0 +1,6 @@
+DROP INDEX IF EXISTS idx_rgbpp_locks_spent_tx;
+
+ALTER TABLE rgbpp_locks
+DROP COLUMN IF EXISTS output_index,
+DROP COLUMN IF EXISTS capacity,
+DROP COLUMN IF EXISTS spent_tx;
@@ -0,0 +1,7 @@
+-- 记录 RGBPP lock 所在的 CKB cell 以及消费它的交易
+ALTER TABLE rgbpp_locks
+ADD COLUMN output_index INTEGER,
+ADD COLUMN capacity BIGINT,
+ADD COLUMN spent_tx BYTEA;
+
+CREATE INDEX idx_rgbpp_locks_spent_tx ON rgbpp_locks (spent_tx);
@@ -0,0 +1,8 @@
+-- 已提交事件的追加日志, 供晚到的订阅者重放
+CREATE TABLE indexed_events (
+    seq BIGINT PRIMARY KEY,
+    height BIGINT NOT NULL,
+    payload JSONB NOT NULL
+);
+
+CREATE INDEX idx_indexed_events_height ON indexed_events (height);
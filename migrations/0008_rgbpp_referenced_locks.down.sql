@@ -0,0 +1 @@
+DROP TABLE IF EXISTS rgbpp_referenced_locks;
@@ -0,0 +1,11 @@
+-- 通过 cell dep 引用的 RGBPP lock
+CREATE TABLE rgbpp_referenced_locks (
+    lock_id BYTEA NOT NULL,
+    tx BYTEA NOT NULL,
+    dep_tx BYTEA NOT NULL,
+    dep_index INTEGER NOT NULL,
+    script_version SMALLINT NOT NULL,
+    PRIMARY KEY (lock_id, tx)
+);
+
+CREATE INDEX idx_rgbpp_referenced_locks_tx ON rgbpp_referenced_locks (tx);
@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS idx_rgbpp_locks_btc_txid;
+
+ALTER TABLE rgbpp_locks
+DROP COLUMN IF EXISTS btc_replaced;
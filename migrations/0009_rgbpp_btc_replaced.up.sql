@@ -0,0 +1,5 @@
+-- 标记 btc_txid 已被 RBF 替换的 RGBPP lock
+ALTER TABLE rgbpp_locks
+ADD COLUMN btc_replaced BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX idx_rgbpp_locks_btc_txid ON rgbpp_locks (btc_txid);
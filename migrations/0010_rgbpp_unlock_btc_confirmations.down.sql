@@ -0,0 +1,3 @@
+ALTER TABLE rgbpp_unlocks
+DROP COLUMN IF EXISTS btc_block_height,
+DROP COLUMN IF EXISTS btc_confirmed;
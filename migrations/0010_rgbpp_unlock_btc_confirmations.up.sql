@@ -0,0 +1,4 @@
+-- 记录 unlock 引用的 BTC 交易所在高度以及是否已有足够确认
+ALTER TABLE rgbpp_unlocks
+ADD COLUMN btc_block_height INTEGER,
+ADD COLUMN btc_confirmed BOOLEAN;
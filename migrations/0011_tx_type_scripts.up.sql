@@ -0,0 +1,8 @@
+-- 交易输出中去重后的 type script hash
+CREATE TABLE tx_type_scripts (
+    tx BYTEA NOT NULL,
+    type_script_hash BYTEA NOT NULL,
+    PRIMARY KEY (tx, type_script_hash)
+);
+
+CREATE INDEX idx_tx_type_scripts_type_script_hash ON tx_type_scripts (type_script_hash);
@@ -0,0 +1,2 @@
+ALTER TABLE rgbpp_unlocks
+DROP COLUMN IF EXISTS btc_tx_proof_json;
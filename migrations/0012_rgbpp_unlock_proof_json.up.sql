@@ -0,0 +1,3 @@
+-- 可选：以 JSON 形式额外保存解析后的 btc_tx_proof，便于用 SQL 查询
+ALTER TABLE rgbpp_unlocks
+ADD COLUMN btc_tx_proof_json JSONB;
@@ -0,0 +1,11 @@
+-- 发送到不可花费 lock 的输出，用于统计销毁量
+CREATE TABLE burns (
+    tx BYTEA NOT NULL,
+    output_index INTEGER NOT NULL,
+    capacity BIGINT NOT NULL,
+    type_script_hash BYTEA,
+    amount NUMERIC(39, 0),
+    PRIMARY KEY (tx, output_index)
+);
+
+CREATE INDEX idx_burns_type_script_hash ON burns (type_script_hash);
@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS idx_rgbpp_unlocks_btc_txid;
+
+ALTER TABLE rgbpp_unlocks
+DROP COLUMN IF EXISTS btc_txid;
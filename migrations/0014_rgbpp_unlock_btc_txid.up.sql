@@ -0,0 +1,5 @@
+-- 由 btc_tx 计算出的 BTC 交易 id（显示字节序），用于按 BTC 交易查询 unlock
+ALTER TABLE rgbpp_unlocks
+ADD COLUMN btc_txid BYTEA;
+
+CREATE INDEX idx_rgbpp_unlocks_btc_txid ON rgbpp_unlocks (btc_txid);
@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS idx_rgbpp_locks_owner_lock_hash;
+
+ALTER TABLE rgbpp_locks
+DROP COLUMN IF EXISTS owner_lock_hash;
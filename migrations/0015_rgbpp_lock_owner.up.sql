@@ -0,0 +1,5 @@
+-- RGBPP cell 的 type script args 中携带的 owner lock hash（如 xUDT）
+ALTER TABLE rgbpp_locks
+ADD COLUMN owner_lock_hash BYTEA;
+
+CREATE INDEX idx_rgbpp_locks_owner_lock_hash ON rgbpp_locks (owner_lock_hash);
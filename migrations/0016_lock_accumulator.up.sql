@@ -0,0 +1,7 @@
+-- 每个高度已索引 lock_id 的默克尔累加器，frontier 为各峰值拼接
+CREATE TABLE lock_accumulator (
+    height BIGINT PRIMARY KEY,
+    leaves BIGINT NOT NULL,
+    frontier BYTEA NOT NULL,
+    root BYTEA NOT NULL
+);
@@ -0,0 +1,8 @@
+DROP INDEX IF EXISTS idx_rgbpp_unlocks_block_number;
+DROP INDEX IF EXISTS idx_rgbpp_locks_block_number;
+
+ALTER TABLE rgbpp_unlocks
+DROP COLUMN IF EXISTS block_number;
+
+ALTER TABLE rgbpp_locks
+DROP COLUMN IF EXISTS block_number;
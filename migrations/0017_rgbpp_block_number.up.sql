@@ -0,0 +1,9 @@
+-- 记录 RGBPP lock 创建和 unlock 所在的 CKB 区块高度，用于按区块范围统计
+ALTER TABLE rgbpp_locks
+ADD COLUMN block_number BIGINT;
+
+ALTER TABLE rgbpp_unlocks
+ADD COLUMN block_number BIGINT;
+
+CREATE INDEX idx_rgbpp_locks_block_number ON rgbpp_locks (block_number);
+CREATE INDEX idx_rgbpp_unlocks_block_number ON rgbpp_unlocks (block_number);
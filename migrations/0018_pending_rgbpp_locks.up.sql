@@ -0,0 +1,11 @@
+-- 区块模板（未确认）交易创建的 RGBPP lock，与已确认的 rgbpp_locks 分开存放
+CREATE TABLE pending_rgbpp_locks (
+    lock_id BYTEA PRIMARY KEY,
+    tx BYTEA NOT NULL,
+    out_index INTEGER NOT NULL,
+    btc_txid BYTEA NOT NULL,
+    script_version SMALLINT NOT NULL,
+    output_index INTEGER,
+    capacity BIGINT,
+    template_number BIGINT NOT NULL
+);
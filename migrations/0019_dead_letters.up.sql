@@ -0,0 +1,6 @@
+-- 解析时 panic 的交易，记录 panic 信息后跳过，不影响同批次其他交易
+CREATE TABLE dead_letters (
+    tx BYTEA PRIMARY KEY,
+    block_number BIGINT,
+    reason TEXT NOT NULL
+);
@@ -0,0 +1,2 @@
+ALTER TABLE rgbpp_locks
+DROP COLUMN IF EXISTS raw_args;
@@ -0,0 +1,3 @@
+-- 可选保存 RGBPP lock 原始的 lock args，包括 molecule 解析时忽略的尾部数据
+ALTER TABLE rgbpp_locks
+ADD COLUMN raw_args BYTEA;
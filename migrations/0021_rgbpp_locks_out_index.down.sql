@@ -0,0 +1 @@
+DROP INDEX IF EXISTS idx_rgbpp_locks_out_index;
@@ -0,0 +1,2 @@
+-- 按 BTC out_index 查询所有 lock（不区分 btc_txid）
+CREATE INDEX idx_rgbpp_locks_out_index ON rgbpp_locks (out_index);
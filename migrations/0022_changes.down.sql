@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS idx_changes_table_name_key;
+DROP INDEX IF EXISTS idx_changes_height;
+DROP TABLE IF EXISTS changes;
@@ -0,0 +1,10 @@
+-- 追加写入的变更流，记录每次写入的表名和主键，供下游按 id 轮询
+CREATE TABLE changes (
+    id BIGINT PRIMARY KEY,
+    height BIGINT NOT NULL,
+    table_name TEXT NOT NULL,
+    key BYTEA NOT NULL
+);
+
+CREATE INDEX idx_changes_height ON changes (height);
+CREATE INDEX idx_changes_table_name_key ON changes (table_name, key);
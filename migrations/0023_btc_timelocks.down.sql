@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS btc_tx_inputs;
+DROP TABLE IF EXISTS btc_txs;
@@ -0,0 +1,12 @@
+-- unlock 中 btc_tx 的 locktime 与各输入 sequence，用于查询 RBF 信号与时间锁
+CREATE TABLE btc_txs (
+    btc_txid BYTEA PRIMARY KEY,
+    lock_time BIGINT NOT NULL
+);
+
+CREATE TABLE btc_tx_inputs (
+    btc_txid BYTEA NOT NULL,
+    input_index INTEGER NOT NULL,
+    sequence BIGINT NOT NULL,
+    PRIMARY KEY (btc_txid, input_index)
+);
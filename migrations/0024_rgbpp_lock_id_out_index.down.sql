@@ -0,0 +1,11 @@
+-- Several out_index of one BTC tx share the old 32 byte id, keep one of them.
+DELETE FROM rgbpp_locks AS a
+USING rgbpp_locks AS b
+WHERE octet_length(a.lock_id) = 36
+    AND octet_length(b.lock_id) = 36
+    AND substring(a.lock_id FROM 5) = substring(b.lock_id FROM 5)
+    AND a.lock_id > b.lock_id;
+
+UPDATE rgbpp_locks
+SET lock_id = substring(lock_id FROM 5)
+WHERE octet_length(lock_id) = 36;
@@ -0,0 +1,18 @@
+-- 旧版本的 lock_id 只有 32 字节的 btc_txid，改写为完整的 RGBPPLock（小端 out_index || btc_txid）
+CREATE FUNCTION pg_temp.rgbpp_lock_id(out_index INTEGER, btc_txid BYTEA) RETURNS BYTEA AS $$
+    SELECT set_byte(set_byte(set_byte(set_byte('\x00000000'::BYTEA,
+        0, out_index & 255),
+        1, (out_index >> 8) & 255),
+        2, (out_index >> 16) & 255),
+        3, (out_index >> 24) & 255) || btc_txid
+$$ LANGUAGE SQL IMMUTABLE;
+
+-- 同一个 lock 已经以新格式写入时，删除旧格式的那一行
+DELETE FROM rgbpp_locks AS old
+USING rgbpp_locks AS new
+WHERE octet_length(old.lock_id) = 32
+    AND new.lock_id = pg_temp.rgbpp_lock_id(old.out_index, old.lock_id);
+
+UPDATE rgbpp_locks
+SET lock_id = pg_temp.rgbpp_lock_id(out_index, lock_id)
+WHERE octet_length(lock_id) = 32;
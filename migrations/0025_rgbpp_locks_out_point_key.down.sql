@@ -0,0 +1,12 @@
+DROP INDEX IF EXISTS idx_rgbpp_locks_lock_id;
+
+-- Only one cell per lock id fits the old key, keep the first by out point.
+DELETE FROM rgbpp_locks AS a
+USING rgbpp_locks AS b
+WHERE a.lock_id = b.lock_id
+    AND (a.tx, a.output_index) > (b.tx, b.output_index);
+
+ALTER TABLE rgbpp_locks
+DROP CONSTRAINT rgbpp_locks_pkey,
+ADD PRIMARY KEY (lock_id),
+ALTER COLUMN output_index DROP NOT NULL;
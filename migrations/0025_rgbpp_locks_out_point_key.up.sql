@@ -0,0 +1,25 @@
+-- 一个 BTC UTXO 可以绑定多个 CKB cell，rgbpp_locks 改为以 cell 的 out point (tx, output_index) 为主键
+-- 0006 之前写入的行没有 output_index，无法从数据库内的数据补齐，只能重新索引这些区块。
+-- 存在这样的行时迁移默认失败，不会删除任何数据。确认要重新索引后，在迁移会话中设置
+-- unistate.reindex_locks = on（例如 PGOPTIONS='-c unistate.reindex_locks=on'）再执行迁移，
+-- 这些行会被删除；之后以 start_block 设为其中最小的 block_number（未知时设为 initial_height）
+-- 并打开 confirm_reindex 启动索引器，重新写入它们。
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM rgbpp_locks WHERE output_index IS NULL)
+        AND current_setting('unistate.reindex_locks', true) IS DISTINCT FROM 'on' THEN
+        RAISE EXCEPTION 'rgbpp_locks has rows without output_index, they can only be restored by a reindex'
+            USING HINT = 'Set unistate.reindex_locks = on to drop them, then reindex with start_block and confirm_reindex.';
+    END IF;
+END
+$$;
+
+DELETE FROM rgbpp_locks
+WHERE output_index IS NULL;
+
+ALTER TABLE rgbpp_locks
+ALTER COLUMN output_index SET NOT NULL,
+DROP CONSTRAINT rgbpp_locks_pkey,
+ADD PRIMARY KEY (tx, output_index);
+
+CREATE INDEX idx_rgbpp_locks_lock_id ON rgbpp_locks (lock_id);
@@ -0,0 +1,6 @@
+DELETE FROM pending_rgbpp_locks;
+
+ALTER TABLE pending_rgbpp_locks
+DROP CONSTRAINT pending_rgbpp_locks_pkey,
+ADD PRIMARY KEY (lock_id),
+ALTER COLUMN output_index DROP NOT NULL;
@@ -0,0 +1,8 @@
+-- 与 rgbpp_locks 一致，pending_rgbpp_locks 改为以 cell 的 out point (tx, output_index) 为主键
+-- 表中只是最近一个区块模板的快照，下次刷新会重新写入，直接清空即可
+DELETE FROM pending_rgbpp_locks;
+
+ALTER TABLE pending_rgbpp_locks
+ALTER COLUMN output_index SET NOT NULL,
+DROP CONSTRAINT pending_rgbpp_locks_pkey,
+ADD PRIMARY KEY (tx, output_index);
@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS idx_rgbpp_locks_spent_block_number;
+
+ALTER TABLE rgbpp_locks
+DROP COLUMN spent_block_number;
@@ -0,0 +1,14 @@
+-- 记录花费 lock 的交易所在区块，按花费区块统计时不再依赖 rgbpp_unlocks
+ALTER TABLE rgbpp_locks
+ADD COLUMN spent_block_number BIGINT;
+
+-- 已有的花费只能从花费交易的 unlock 补齐，没有 unlock 记录的保持 NULL
+UPDATE rgbpp_locks AS locks
+SET spent_block_number = spends.block_number
+FROM (
+    SELECT DISTINCT tx, block_number FROM rgbpp_unlocks
+    WHERE block_number IS NOT NULL
+) AS spends
+WHERE spends.tx = locks.spent_tx;
+
+CREATE INDEX idx_rgbpp_locks_spent_block_number ON rgbpp_locks (spent_block_number);
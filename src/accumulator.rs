@@ -0,0 +1,291 @@
+use std::collections::HashSet;
+
+use sea_orm::{
+    sea_query::OnConflict, ColumnTrait as _, ConnectionTrait, DbErr, EntityTrait as _,
+    QueryFilter as _, QueryOrder as _, QuerySelect as _, Set,
+};
+use sha2::{Digest as _, Sha256};
+
+use crate::entity::{block_height, lock_accumulator, rgbpp_locks};
+
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+/// Lock ids looked up per statement, well below the bind parameter limit.
+const MAX_LOOKUP: usize = 10_000;
+
+/// Append-only Merkle accumulator over indexed lock ids.
+///
+/// One peak is kept per set bit of `leaves`, like a binary counter, so appending is
+/// `O(log n)` and only the peaks need to be persisted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LockAccumulator {
+    leaves: u64,
+    /// Perfect subtree roots, tallest first.
+    peaks: Vec<[u8; 32]>,
+}
+
+fn leaf_hash(lock_id: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(lock_id);
+    hasher.finalize().into()
+}
+
+fn node_hash(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+impl LockAccumulator {
+    pub fn push(&mut self, lock_id: &[u8]) {
+        let mut node = leaf_hash(lock_id);
+        let mut leaves = self.leaves;
+        while leaves & 1 == 1 {
+            let left = self.peaks.pop().expect("one peak per set bit");
+            node = node_hash(&left, &node);
+            leaves >>= 1;
+        }
+        self.peaks.push(node);
+        self.leaves += 1;
+    }
+
+    /// Peaks folded right to left, all zeros while empty.
+    pub fn root(&self) -> [u8; 32] {
+        self.peaks
+            .iter()
+            .rev()
+            .copied()
+            .reduce(|right, left| node_hash(&left, &right))
+            .unwrap_or_default()
+    }
+
+    pub fn leaves(&self) -> u64 {
+        self.leaves
+    }
+
+    fn frontier(&self) -> Vec<u8> {
+        self.peaks.concat()
+    }
+
+    fn from_model(model: &lock_accumulator::Model) -> Result<Self, DbErr> {
+        let leaves = model.leaves as u64;
+        let peaks = model
+            .frontier
+            .chunks(32)
+            .map(|peak| peak.try_into())
+            .collect::<Result<Vec<[u8; 32]>, _>>()
+            .map_err(|_| {
+                DbErr::Custom("lock accumulator frontier is not 32 byte aligned".into())
+            })?;
+        if peaks.len() != leaves.count_ones() as usize {
+            return Err(DbErr::Custom(format!(
+                "lock accumulator at {} has {} peaks for {leaves} leaves",
+                model.height,
+                peaks.len()
+            )));
+        }
+
+        Ok(Self { leaves, peaks })
+    }
+
+    /// Extend the accumulator with the `lock_ids` not indexed yet inside the commit
+    /// transaction and store the result at `height`.
+    ///
+    /// Runs before the batch's locks are written, so reindexing a range only appends the
+    /// locks it did not see before and the root matches a fresh sync. The newest row is
+    /// the base even after a rewind below it, as it holds every lock appended so far,
+    /// and rows above `height` are dropped. Locks indexed before the accumulator was
+    /// enabled, or removed by a reorg, are not taken out of it.
+    ///
+    /// A batch's locks arrive in no particular order, so they are sorted first to keep
+    /// the root deterministic.
+    pub async fn append<C: ConnectionTrait>(
+        txn: &C,
+        height: u64,
+        mut lock_ids: Vec<Vec<u8>>,
+    ) -> Result<[u8; 32], DbErr> {
+        let mut accumulator = match lock_accumulator::Entity::find()
+            .order_by_desc(lock_accumulator::Column::Height)
+            .one(txn)
+            .await?
+        {
+            Some(model) => Self::from_model(&model)?,
+            None => Self::default(),
+        };
+
+        lock_ids.sort_unstable();
+        lock_ids.dedup();
+        let mut indexed = HashSet::new();
+        for chunk in lock_ids.chunks(MAX_LOOKUP) {
+            indexed.extend(
+                rgbpp_locks::Entity::find()
+                    .select_only()
+                    .column(rgbpp_locks::Column::LockId)
+                    .filter(rgbpp_locks::Column::LockId.is_in(chunk.iter().cloned()))
+                    .into_tuple::<Vec<u8>>()
+                    .all(txn)
+                    .await?,
+            );
+        }
+        lock_ids
+            .iter()
+            .filter(|lock_id| !indexed.contains(*lock_id))
+            .for_each(|lock_id| accumulator.push(lock_id));
+        let root = accumulator.root();
+
+        lock_accumulator::Entity::delete_many()
+            .filter(lock_accumulator::Column::Height.gt(height as i64))
+            .exec(txn)
+            .await?;
+        lock_accumulator::Entity::insert(lock_accumulator::ActiveModel {
+            height: Set(height as i64),
+            leaves: Set(accumulator.leaves as i64),
+            frontier: Set(accumulator.frontier()),
+            root: Set(root.to_vec()),
+        })
+        .on_conflict(
+            OnConflict::column(lock_accumulator::Column::Height)
+                .update_columns([
+                    lock_accumulator::Column::Leaves,
+                    lock_accumulator::Column::Frontier,
+                    lock_accumulator::Column::Root,
+                ])
+                .to_owned(),
+        )
+        .exec_without_returning(txn)
+        .await?;
+
+        Ok(root)
+    }
+}
+
+/// The accumulator root as of the committed `block_height`, `None` before the first
+/// batch with the accumulator enabled.
+pub async fn current_root<C: ConnectionTrait>(db: &C) -> Result<Option<Vec<u8>>, DbErr> {
+    let Some(height) = block_height::Entity::find_by_id(1).one(db).await? else {
+        return Ok(None);
+    };
+
+    Ok(lock_accumulator::Entity::find()
+        .filter(lock_accumulator::Column::Height.lte(height.height))
+        .order_by_desc(lock_accumulator::Column::Height)
+        .one(db)
+        .await?
+        .map(|model| model.root))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_accumulator_root() {
+        let ids = (0u8..5).map(|i| vec![i; 32]).collect::<Vec<_>>();
+
+        let mut accumulator = LockAccumulator::default();
+        assert_eq!(accumulator.root(), [0u8; 32]);
+
+        let mut roots = Vec::new();
+        for id in &ids {
+            accumulator.push(id);
+            roots.push(accumulator.root());
+        }
+
+        let leaves = ids.iter().map(|id| leaf_hash(id)).collect::<Vec<_>>();
+        assert_eq!(roots[0], leaves[0]);
+        assert_eq!(roots[1], node_hash(&leaves[0], &leaves[1]));
+        assert_eq!(
+            roots[2],
+            node_hash(&node_hash(&leaves[0], &leaves[1]), &leaves[2])
+        );
+        assert_eq!(
+            roots[4],
+            node_hash(
+                &node_hash(
+                    &node_hash(&leaves[0], &leaves[1]),
+                    &node_hash(&leaves[2], &leaves[3])
+                ),
+                &leaves[4]
+            )
+        );
+        assert_eq!(accumulator.leaves(), 5);
+
+        let mut replayed = LockAccumulator::default();
+        ids.iter().for_each(|id| replayed.push(id));
+        assert_eq!(replayed, accumulator);
+
+        let restored = LockAccumulator::from_model(&lock_accumulator::Model {
+            height: 1,
+            leaves: accumulator.leaves() as i64,
+            frontier: accumulator.frontier(),
+            root: accumulator.root().to_vec(),
+        })
+        .unwrap();
+        assert_eq!(restored, accumulator);
+    }
+
+    #[tokio::test]
+    async fn test_append_skips_reindexed_locks() {
+        use std::collections::BTreeMap;
+
+        use sea_orm::{DatabaseBackend, MockDatabase, MockExecResult, Transaction, Value};
+
+        let [a, b, c] = [1u8, 2, 3].map(|i| vec![i; 36]);
+        let mut fresh = LockAccumulator::default();
+        let mut rows = Vec::new();
+        for (height, ids) in [(100, vec![&a, &b]), (200, vec![&c])] {
+            ids.into_iter().for_each(|id| fresh.push(id));
+            rows.push(lock_accumulator::Model {
+                height,
+                leaves: fresh.leaves() as i64,
+                frontier: fresh.frontier(),
+                root: fresh.root().to_vec(),
+            });
+        }
+        let indexed = |ids: &[&Vec<u8>]| {
+            ids.iter()
+                .map(|id| BTreeMap::from([("lock_id", Value::from((*id).clone()))]))
+                .collect::<Vec<_>>()
+        };
+        let exec = MockExecResult {
+            last_insert_id: 0,
+            rows_affected: 1,
+        };
+
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([Vec::<lock_accumulator::Model>::new()])
+            .append_query_results([indexed(&[])])
+            .append_query_results([vec![rows[0].clone()]])
+            .append_query_results([indexed(&[])])
+            // Rewound to 50 and reindexed up to 200 in one batch.
+            .append_query_results([vec![rows[1].clone()]])
+            .append_query_results([indexed(&[&a, &b, &c])])
+            .append_exec_results(vec![exec; 6])
+            .into_connection();
+
+        LockAccumulator::append(&db, 100, vec![b.clone(), a.clone()])
+            .await
+            .unwrap();
+        let synced = LockAccumulator::append(&db, 200, vec![c.clone()])
+            .await
+            .unwrap();
+        let reindexed = LockAccumulator::append(&db, 200, vec![c, a, b])
+            .await
+            .unwrap();
+
+        assert_eq!(synced, fresh.root());
+        assert_eq!(reindexed, fresh.root());
+        let log = db.into_transaction_log();
+        assert_eq!(
+            log[log.len() - 2],
+            Transaction::from_sql_and_values(
+                DatabaseBackend::Postgres,
+                r#"DELETE FROM "lock_accumulator" WHERE "lock_accumulator"."height" > $1"#,
+                [200i64.into()]
+            )
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use sea_orm::{ConnectionTrait, DbErr, EntityTrait as _};
+use tokio::sync::oneshot;
+
+use crate::{entity::block_height, error::BackfillError};
+
+/// Tracks a backfill up to a fixed height and notifies once it is committed.
+///
+/// Heights follow the indexer loop: a target is exclusive, and a batch committed as
+/// `height` means every block below `height` is indexed.
+pub struct Backfill {
+    end: u64,
+    done: Option<oneshot::Sender<u64>>,
+}
+
+impl Backfill {
+    /// Backfill up to `end`, or to the `tip` seen at start when `None`.
+    pub fn new(end: Option<u64>, tip: u64) -> (Self, oneshot::Receiver<u64>) {
+        let (done, recv) = oneshot::channel();
+        (
+            Self {
+                end: end.unwrap_or(tip),
+                done: Some(done),
+            },
+            recv,
+        )
+    }
+
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.done.is_some()
+    }
+
+    /// Reject backfilling from `start` when the range exceeds `max_range`, unless
+    /// `allow_long` overrides the guard.
+    pub fn check_range(
+        &self,
+        start: u64,
+        max_range: Option<u64>,
+        allow_long: bool,
+    ) -> Result<(), BackfillError> {
+        let range = self.end.saturating_sub(start);
+        match max_range {
+            Some(max) if range > max && !allow_long => Err(BackfillError::RangeTooLarge {
+                start,
+                end: self.end,
+                range,
+                max,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// The target to index towards: `tip`, capped at the end until it has been reached.
+    pub fn target(&self, tip: u64) -> u64 {
+        if self.is_pending() {
+            tip.min(self.end)
+        } else {
+            tip
+        }
+    }
+
+    /// The notifier for the batch committed as `height`, if that batch completes the
+    /// backfill. Fire it only after the batch commits.
+    pub fn take_notifier(&mut self, height: u64) -> Option<oneshot::Sender<u64>> {
+        if height >= self.end {
+            self.done.take()
+        } else {
+            None
+        }
+    }
+}
+
+/// Blocks to fetch in the next batch from `height` towards `target`, at most `max`.
+///
+/// Zero once `height` reached `target`, including when it started past it, e.g. a
+/// `start_block` above the tip or a `backfill_end` below the resume height.
+pub fn next_batch_size(target: u64, height: u64, max: u64) -> u64 {
+    target.saturating_sub(height).min(max)
+}
+
+/// The height to resume indexing from.
+///
+/// Blocks more than `finality_depth` below the committed height are final and are not
+/// scanned again; only the tail above that watermark is reindexed in case it changed.
+/// An empty database starts at `initial_height`.
+pub async fn resume_height<C: ConnectionTrait>(
+    db: &C,
+    initial_height: u64,
+    finality_depth: u64,
+) -> Result<u64, DbErr> {
+    let committed = block_height::Entity::find_by_id(1)
+        .one(db)
+        .await?
+        .map_or(0, |model| model.height as u64);
+
+    Ok(initial_height.max(committed.saturating_sub(finality_depth)))
+}
+
+/// The height to start indexing from, `start_block` overriding the `resume` height.
+///
+/// A `start_block` ahead of `resume` skips the blocks in between. One behind it reindexes
+/// from there, overwriting what was indexed, and is refused unless `confirm_reindex`.
+pub fn start_height(
+    resume: u64,
+    start_block: Option<u64>,
+    confirm_reindex: bool,
+) -> Result<u64, BackfillError> {
+    match start_block {
+        Some(start_block) if start_block < resume && !confirm_reindex => {
+            Err(BackfillError::ReindexNotConfirmed {
+                start_block,
+                resume,
+            })
+        }
+        Some(start_block) => Ok(start_block),
+        None => Ok(resume),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sea_orm::{DatabaseBackend, MockDatabase};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_resume_height() {
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([[block_height::Model {
+                id: 1,
+                height: 12_000_000,
+            }]])
+            .append_query_results([Vec::<block_height::Model>::new()])
+            .into_connection();
+
+        assert_eq!(resume_height(&db, 1, 24).await.unwrap(), 11_999_976);
+        assert_eq!(resume_height(&db, 100, 24).await.unwrap(), 100);
+    }
+
+    #[tokio::test]
+    async fn test_backfill_notifies_at_end() {
+        let (mut backfill, mut done) = Backfill::new(Some(100), 250);
+        let max_batch_size = 30;
+
+        // Mirrors the indexer loop with the chain tip well past the backfill end, up to
+        // the batch that completes the backfill.
+        let mut height = 0;
+        let mut committed = Vec::new();
+        while backfill.is_pending() {
+            height += next_batch_size(backfill.target(250), height, max_batch_size);
+            committed.push(height);
+
+            if let Some(notifier) = backfill.take_notifier(height) {
+                assert!(done.try_recv().is_err());
+                notifier.send(height).unwrap();
+            }
+        }
+
+        assert_eq!(committed, vec![30, 60, 90, 100]);
+        assert_eq!(done.await.unwrap(), 100);
+        assert!(!backfill.is_pending());
+        assert_eq!(backfill.target(250), 250);
+    }
+
+    #[test]
+    fn test_backfill_end_below_height() {
+        let (mut backfill, mut done) = Backfill::new(Some(100), 250);
+        let max_batch_size = 30;
+
+        // Mirrors the indexer loop resuming above the backfill end: the first batch is
+        // empty, and once the backfill is done the loop follows the tip from `height`.
+        let mut height = 180;
+        let mut target_height = backfill.target(250);
+        let mut batch_size = next_batch_size(target_height, height, max_batch_size);
+        let mut committed = Vec::new();
+        for _ in 0..4 {
+            height += batch_size;
+            if let Some(notifier) = backfill.take_notifier(height) {
+                notifier.send(height).unwrap();
+            }
+            batch_size = next_batch_size(target_height, height, max_batch_size);
+            if batch_size == 0 {
+                target_height = backfill.target(250);
+                batch_size = next_batch_size(target_height, height, max_batch_size);
+            } else {
+                committed.push(height);
+            }
+        }
+
+        assert_eq!(done.try_recv(), Ok(180));
+        assert_eq!(committed, vec![210, 240]);
+        assert_eq!(height, 250);
+    }
+
+    #[tokio::test]
+    async fn test_backfill_defaults_to_tip() {
+        let (mut backfill, done) = Backfill::new(None, 42);
+
+        assert_eq!(backfill.end(), 42);
+        assert!(backfill.take_notifier(41).is_none());
+        backfill.take_notifier(42).unwrap().send(42).unwrap();
+        assert_eq!(done.await.unwrap(), 42);
+    }
+
+    #[test]
+    fn test_backfill_check_range() {
+        let (backfill, _done) = Backfill::new(None, 1_000_000);
+
+        assert_eq!(
+            backfill.check_range(1, Some(10_000), false),
+            Err(BackfillError::RangeTooLarge {
+                start: 1,
+                end: 1_000_000,
+                range: 999_999,
+                max: 10_000,
+            })
+        );
+        assert_eq!(backfill.check_range(1, Some(10_000), true), Ok(()));
+        assert_eq!(backfill.check_range(995_000, Some(10_000), false), Ok(()));
+        assert_eq!(backfill.check_range(1, None, false), Ok(()));
+    }
+
+    #[test]
+    fn test_start_block_seeks_forward() {
+        assert_eq!(start_height(1_000, Some(800_000), false), Ok(800_000));
+        assert_eq!(start_height(1_000, Some(1_000), false), Ok(1_000));
+        assert_eq!(start_height(1_000, None, false), Ok(1_000));
+
+        assert_eq!(
+            start_height(900_000, Some(800_000), false),
+            Err(BackfillError::ReindexNotConfirmed {
+                start_block: 800_000,
+                resume: 900_000,
+            })
+        );
+        assert_eq!(start_height(900_000, Some(800_000), true), Ok(800_000));
+    }
+}
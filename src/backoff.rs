@@ -0,0 +1,102 @@
+use std::{future::Future, time::Duration};
+
+/// Exponential delay between `initial` and `max`, restarting from `initial` on `reset`.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            current: initial,
+        }
+    }
+
+    /// The delay to wait now; each call doubles the next one up to `max`.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+
+    pub async fn wait(&mut self) {
+        tokio::time::sleep(self.next_delay()).await;
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+/// Poll `get_tip` until the chain grows past `current`, backing off while it does not.
+///
+/// The backoff is reset once a new tip is seen, so a caught-up indexer polls quickly
+/// again right after the chain moves.
+pub async fn wait_for_tip<F, Fut>(
+    current: u64,
+    mut get_tip: F,
+    backoff: &mut Backoff,
+) -> anyhow::Result<u64>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<u64>>,
+{
+    loop {
+        let tip = get_tip().await?;
+        if tip > current {
+            backoff.reset();
+            return Ok(tip);
+        }
+        backoff.wait().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use tokio::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn test_backoff_bounded() {
+        let mut backoff = Backoff::new(Duration::from_millis(10), Duration::from_millis(40));
+
+        let delays = (0..4).map(|_| backoff.next_delay()).collect::<Vec<_>>();
+        assert_eq!(delays, [10, 20, 40, 40].map(Duration::from_millis));
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_millis(10));
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_tip_does_not_spin() {
+        // The producer pauses for 200ms before the tip moves.
+        let polls = AtomicU32::new(0);
+        let start = Instant::now();
+        let get_tip = || {
+            polls.fetch_add(1, Ordering::Relaxed);
+            let tip = if start.elapsed() >= Duration::from_millis(200) {
+                11
+            } else {
+                10
+            };
+            async move { Ok(tip) }
+        };
+
+        let mut backoff = Backoff::new(Duration::from_millis(10), Duration::from_millis(40));
+        let tip = wait_for_tip(10, get_tip, &mut backoff).await.unwrap();
+
+        assert_eq!(tip, 11);
+        // 10 + 20 + 40 + 40 + 40 + 40 + 40 ms covers the pause in 8 polls, a spin would
+        // poll thousands of times.
+        assert!(polls.load(Ordering::Relaxed) <= 8, "{polls:?}");
+        assert_eq!(backoff.next_delay(), Duration::from_millis(10));
+    }
+}
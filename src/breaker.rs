@@ -0,0 +1,143 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    /// Requests go through.
+    Closed,
+    /// Requests fail fast until the cooldown is over.
+    Open,
+    /// One trial request is let through to probe whether the node recovered.
+    HalfOpen,
+}
+
+impl BreakerState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BreakerState::Closed => "closed",
+            BreakerState::Open => "open",
+            BreakerState::HalfOpen => "half_open",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    state: BreakerState,
+    failures: u32,
+    opened_at: Instant,
+}
+
+/// Stops sending requests to a node after `threshold` consecutive failures.
+///
+/// Once open, every request fails fast for `cooldown`. After that a single trial is let
+/// through: success closes the breaker again, failure reopens it for another cooldown.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    inner: Mutex<Inner>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            cooldown,
+            inner: Mutex::new(Inner {
+                state: BreakerState::Closed,
+                failures: 0,
+                opened_at: Instant::now(),
+            }),
+        }
+    }
+
+    fn inner(&self) -> std::sync::MutexGuard<'_, Inner> {
+        // The state stays consistent at every step, so a poisoned lock is still usable.
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn state(&self) -> BreakerState {
+        self.inner().state
+    }
+
+    /// Whether a request may be sent now.
+    pub fn allow(&self) -> bool {
+        self.allow_at(Instant::now())
+    }
+
+    fn allow_at(&self, now: Instant) -> bool {
+        let mut inner = self.inner();
+        match inner.state {
+            BreakerState::Closed => true,
+            BreakerState::Open if now.duration_since(inner.opened_at) >= self.cooldown => {
+                inner.state = BreakerState::HalfOpen;
+                true
+            }
+            // The trial is still in flight.
+            BreakerState::Open | BreakerState::HalfOpen => false,
+        }
+    }
+
+    pub fn record(&self, success: bool) {
+        self.record_at(success, Instant::now());
+    }
+
+    fn record_at(&self, success: bool, now: Instant) {
+        let mut inner = self.inner();
+        if success {
+            inner.state = BreakerState::Closed;
+            inner.failures = 0;
+            return;
+        }
+
+        inner.failures += 1;
+        if inner.state == BreakerState::HalfOpen || inner.failures >= self.threshold {
+            inner.state = BreakerState::Open;
+            inner.opened_at = now;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_circuit_breaker() {
+        let cooldown = Duration::from_secs(10);
+        let breaker = CircuitBreaker::new(3, cooldown);
+        let start = Instant::now();
+
+        for _ in 0..2 {
+            assert!(breaker.allow_at(start));
+            breaker.record_at(false, start);
+        }
+        assert_eq!(breaker.state(), BreakerState::Closed);
+
+        assert!(breaker.allow_at(start));
+        breaker.record_at(false, start);
+        assert_eq!(breaker.state(), BreakerState::Open);
+
+        // Open: fail fast without reaching the node.
+        assert!(!breaker.allow_at(start));
+        assert!(!breaker.allow_at(start + cooldown / 2));
+
+        // Half open: one trial, others still fail fast; it fails and reopens.
+        let probe = start + cooldown;
+        assert!(breaker.allow_at(probe));
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        assert!(!breaker.allow_at(probe));
+        breaker.record_at(false, probe);
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert!(!breaker.allow_at(probe + cooldown / 2));
+
+        // The next trial succeeds and closes the breaker.
+        assert!(breaker.allow_at(probe + cooldown));
+        breaker.record_at(true, probe + cooldown);
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        assert!(breaker.allow_at(probe + cooldown));
+    }
+}
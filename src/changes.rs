@@ -0,0 +1,184 @@
+use sea_orm::{
+    ActiveValue, ColumnTrait as _, ConnectionTrait, DbErr, EntityTrait as _, QuerySelect as _, Set,
+    Statement,
+};
+
+use crate::{database::Operations, entity::changes};
+
+/// A row written by a batch, identified by its table and primary key.
+///
+/// Composite keys are concatenated, integers big endian and strings as UTF-8.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub table: &'static str,
+    pub key: Vec<u8>,
+}
+
+fn bytes(value: &ActiveValue<Vec<u8>>) -> Option<Vec<u8>> {
+    value.try_as_ref().cloned()
+}
+
+fn text(value: &ActiveValue<String>) -> Option<Vec<u8>> {
+    value.try_as_ref().map(|value| value.as_bytes().to_vec())
+}
+
+fn pair(hash: &ActiveValue<Vec<u8>>, index: &ActiveValue<i32>) -> Option<Vec<u8>> {
+    let mut key = bytes(hash)?;
+    key.extend(index.try_as_ref()?.to_be_bytes());
+    Some(key)
+}
+
+impl Change {
+    /// The row `op` writes. Spore actions get their key from the database, so they are
+    /// not recorded.
+    pub fn of(op: &Operations) -> Option<Self> {
+        let (table, key) = match op {
+            Operations::UpdateXudtCell(status) => (
+                "transaction_outputs_status",
+                pair(
+                    &status.output_transaction_hash,
+                    &status.output_transaction_index,
+                ),
+            ),
+            Operations::UpsertTokenInfo(info) => ("token_info", text(&info.type_id)),
+            Operations::UpsertXudt(cell) => (
+                "xudt_cell",
+                pair(&cell.transaction_hash, &cell.transaction_index),
+            ),
+            Operations::UpsertAddress(address) => ("addresses", text(&address.id)),
+            Operations::UpsertCluster(cluster) => ("clusters", bytes(&cluster.id)),
+            Operations::UpsertSpores(spore) => ("spores", bytes(&spore.id)),
+            Operations::UpsertLock(lock) | Operations::SpendLock(lock) => {
+                ("rgbpp_locks", pair(&lock.tx, &lock.output_index))
+            }
+            Operations::UpsertReferencedLock(lock) => (
+                "rgbpp_referenced_locks",
+                bytes(&lock.lock_id)
+                    .zip(bytes(&lock.tx))
+                    .map(|(a, b)| [a, b].concat()),
+            ),
+            Operations::UpsertUnlock(unlock) => ("rgbpp_unlocks", bytes(&unlock.unlock_id)),
+            Operations::UpsertTxTypeScript(script) => (
+                "tx_type_scripts",
+                bytes(&script.tx)
+                    .zip(bytes(&script.type_script_hash))
+                    .map(|(a, b)| [a, b].concat()),
+            ),
+            Operations::UpsertBurn(burn) => ("burns", pair(&burn.tx, &burn.output_index)),
+            Operations::UpsertDeadLetter(letter) => ("dead_letters", bytes(&letter.tx)),
+            Operations::UpsertBtcTx(btc_tx) => ("btc_txs", bytes(&btc_tx.btc_txid)),
+            Operations::UpsertBtcTxInput(input) => {
+                ("btc_tx_inputs", pair(&input.btc_txid, &input.input_index))
+            }
+            Operations::UpsertActions(_) | Operations::TxMetrics(_) => return None,
+        };
+
+        Some(Self { table, key: key? })
+    }
+}
+
+/// Change feed rows for the `written` rows of the batch committed as `height`, numbered
+/// on from `last_id` in the order they were received.
+pub fn change_rows(last_id: i64, height: u64, written: Vec<Change>) -> Vec<changes::ActiveModel> {
+    written
+        .into_iter()
+        .zip(last_id + 1..)
+        .map(|(change, id)| changes::ActiveModel {
+            id: Set(id),
+            height: Set(height as i64),
+            table_name: Set(change.table.to_string()),
+            key: Set(change.key),
+        })
+        .collect()
+}
+
+/// Append the `written` rows to the feed inside the commit transaction.
+///
+/// Commits are serialized, so ids are allocated from the current maximum.
+pub async fn append<C: ConnectionTrait>(
+    txn: &C,
+    height: u64,
+    written: Vec<Change>,
+) -> Result<(), DbErr> {
+    if written.is_empty() {
+        return Ok(());
+    }
+
+    let last_id = changes::Entity::find()
+        .select_only()
+        .column_as(changes::Column::Id.max(), "id")
+        .into_tuple::<Option<i64>>()
+        .one(txn)
+        .await?
+        .flatten()
+        .unwrap_or(0);
+
+    let rows = change_rows(last_id, height, written);
+    for batch in rows.chunks((u16::MAX / 4) as usize) {
+        changes::Entity::insert_many(batch.to_vec())
+            .exec_without_returning(txn)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Drop changes superseded by a later change to the same row, among batches committed
+/// at or below `watermark`. Changes above it may still be reorged and are kept.
+pub async fn compact<C: ConnectionTrait>(db: &C, watermark: u64) -> Result<u64, DbErr> {
+    let stmt = Statement::from_sql_and_values(
+        db.get_database_backend(),
+        "DELETE FROM changes c \
+         WHERE c.height <= $1 AND EXISTS ( \
+            SELECT 1 FROM changes n \
+            WHERE n.table_name = c.table_name AND n.key = c.key \
+            AND n.id > c.id AND n.height <= $1 \
+         )",
+        [(watermark as i64).into()],
+    );
+
+    Ok(db.execute(stmt).await?.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::{rgbpp_locks, rgbpp_unlocks};
+
+    #[test]
+    fn test_change_rows_in_order() {
+        let lock = |id: u8| rgbpp_locks::ActiveModel {
+            tx: Set(vec![id]),
+            output_index: Set(3),
+            ..Default::default()
+        };
+        let ops = [
+            Operations::UpsertLock(lock(1)),
+            Operations::UpsertUnlock(rgbpp_unlocks::ActiveModel {
+                unlock_id: Set(vec![2]),
+                ..Default::default()
+            }),
+            Operations::UpsertActions(Default::default()),
+            Operations::SpendLock(lock(1)),
+        ];
+
+        let changes = ops.iter().filter_map(Change::of).collect::<Vec<_>>();
+        let rows = change_rows(5, 100, changes);
+
+        assert_eq!(
+            rows.into_iter()
+                .map(|row| (
+                    row.id.unwrap(),
+                    row.height.unwrap(),
+                    row.table_name.unwrap(),
+                    row.key.unwrap()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (6, 100, "rgbpp_locks".to_string(), vec![1, 0, 0, 0, 3]),
+                (7, 100, "rgbpp_unlocks".to_string(), vec![2]),
+                (8, 100, "rgbpp_locks".to_string(), vec![1, 0, 0, 0, 3]),
+            ]
+        );
+    }
+}
@@ -1,29 +1,186 @@
+use std::path::PathBuf;
+
+use ckb_jsonrpc_types::{OutPoint, Script, ScriptHashType};
 use ckb_sdk::NetworkType;
+use ckb_types::H256;
 use serde::Deserialize;
 
+use crate::{
+    constants::{Constants, BTC_JUMP_CONFIRMATION_BLOCKS},
+    error::ConfigError,
+    export::{ExportFormat, ExportTable},
+    type_id::type_id_code_hash,
+};
+
 #[derive(Debug, PartialEq, Deserialize)]
-pub(crate) struct Config {
-    pub(crate) unistate: UnistateConfig,
+pub struct Config {
+    pub unistate: UnistateConfig,
     #[serde(default)]
-    pub(crate) database_url: String,
+    pub database_url: String,
+}
+
+impl Config {
+    /// Reject setting combinations the indexer cannot run with, before it starts.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let unistate = &self.unistate;
+        let optional = &unistate.optional_config;
+
+        if unistate.url.is_empty() {
+            return Err(ConfigError::EmptyUrl);
+        }
+
+        for (field, value) in [
+            ("batch_size", optional.batch_size),
+            ("fetch_size", optional.fetch_size as u64),
+            ("max_connections", optional.max_connections as u64),
+            ("btc_confirmations", optional.btc_confirmations),
+        ] {
+            if value == 0 {
+                return Err(ConfigError::Zero { field });
+            }
+        }
+
+        if let Some(tip) = optional.btc_tip {
+            if optional.btc_confirmations > tip + 1 {
+                return Err(ConfigError::ConfirmationsBeyondTip {
+                    confirmations: optional.btc_confirmations,
+                    tip,
+                });
+            }
+            // Unlocks are only flagged again while their block is reindexed.
+            if optional.btc_confirmations > optional.finality_depth {
+                return Err(ConfigError::ConfirmationsBeyondFinality {
+                    confirmations: optional.btc_confirmations,
+                    finality_depth: optional.finality_depth,
+                });
+            }
+        }
+
+        if let Some(end) = optional.backfill_end {
+            if end < optional.initial_height {
+                return Err(ConfigError::BackfillEndBeforeStart {
+                    end,
+                    initial_height: optional.initial_height,
+                });
+            }
+            if let Some(start_block) = optional.start_block.filter(|&start| start > end) {
+                return Err(ConfigError::StartBlockAfterBackfillEnd { start_block, end });
+            }
+        }
+
+        let mut versions = std::collections::HashSet::new();
+        for script in &unistate.rgbpp_type_ids {
+            if !versions.insert(script.version) {
+                return Err(ConfigError::DuplicateScriptVersion {
+                    version: script.version,
+                });
+            }
+        }
+        for script in &unistate.rgbpp_scripts {
+            if !versions.insert(script.version) {
+                return Err(ConfigError::DuplicateScriptVersion {
+                    version: script.version,
+                });
+            }
+            if script.code_hash == H256::default() {
+                return Err(ConfigError::ZeroCodeHash {
+                    version: script.version,
+                    network: format!("{:?}", optional.network),
+                });
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Debug, PartialEq, Deserialize)]
-pub(crate) struct UnistateConfig {
-    pub(crate) url: String,
+pub struct UnistateConfig {
+    pub url: String,
     #[serde(flatten, default)]
-    pub(crate) optional_config: UnistateConfigOptional,
+    pub optional_config: UnistateConfigOptional,
+    #[serde(default)]
+    pub featcher: FeatcherConfig,
     #[serde(default)]
-    pub(crate) featcher: FeatcherConfig,
+    pub rgbpp_scripts: Vec<RgbppScriptConfig>,
+    /// RGBPP lock scripts deployed as type-id cells, matched by their type hash.
+    #[serde(default)]
+    pub rgbpp_type_ids: Vec<RgbppTypeIdConfig>,
+}
+
+impl UnistateConfig {
+    /// RGBPP lock scripts to index, falling back to the built-in lock as version 1.
+    ///
+    /// The built-in lock gets the network's cell dep when none is configured. Type-id
+    /// scripts get none here, their deployment is only known once resolved.
+    pub fn rgbpp_scripts(&self, constants: Constants) -> Vec<RgbppScriptConfig> {
+        let built_in = constants.rgbpp_lock_script();
+        if self.rgbpp_scripts.is_empty() && self.rgbpp_type_ids.is_empty() {
+            vec![RgbppScriptConfig {
+                version: 1,
+                code_hash: built_in.code_hash,
+                hash_type: built_in.hash_type,
+                cell_dep: Some(constants.rgbpp_lock_dep().out_point),
+            }]
+        } else {
+            self.rgbpp_scripts
+                .iter()
+                .cloned()
+                .map(|mut script| {
+                    if script.cell_dep.is_none()
+                        && script.code_hash == built_in.code_hash
+                        && script.hash_type == built_in.hash_type
+                    {
+                        script.cell_dep = Some(constants.rgbpp_lock_dep().out_point);
+                    }
+                    script
+                })
+                .chain(self.rgbpp_type_ids.iter().map(|script| RgbppScriptConfig {
+                    version: script.version,
+                    code_hash: type_id_code_hash(&script.type_id),
+                    hash_type: ScriptHashType::Type,
+                    cell_dep: None,
+                }))
+                .collect()
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Deserialize, Clone)]
+pub struct RgbppScriptConfig {
+    pub version: i16,
+    pub code_hash: H256,
+    /// Locks with the same code hash but another hash type are a different script.
+    #[serde(default = "type_hash_type")]
+    pub hash_type: ScriptHashType,
+    /// Cell dep transactions running the script reference, so spends that create no
+    /// lock of it are still picked up.
+    #[serde(default)]
+    pub cell_dep: Option<OutPoint>,
+}
+
+fn type_hash_type() -> ScriptHashType {
+    ScriptHashType::Type
+}
+
+#[derive(Debug, PartialEq, Deserialize, Clone)]
+pub struct RgbppTypeIdConfig {
+    pub version: i16,
+    pub type_id: H256,
 }
 
 #[derive(Debug, PartialEq, Deserialize)]
 #[serde(default)]
-pub(crate) struct FeatcherConfig {
-    pub(crate) retry_interval: u64,
-    pub(crate) max_retries: usize,
-    pub(crate) max_response_size: u32, // 默认是 10485760 即 10mb
-    pub(crate) max_request_size: u32,
+pub struct FeatcherConfig {
+    pub retry_interval: u64,
+    pub max_retries: usize,
+    pub max_response_size: u32, // 默认是 10485760 即 10mb
+    pub max_request_size: u32,
+    /// Consecutive failed attempts, retries included, that open the circuit breaker,
+    /// `None` disables it.
+    pub breaker_threshold: Option<u32>,
+    /// Milliseconds the breaker stays open before probing the node again.
+    pub breaker_cooldown: u64,
 }
 
 impl Default for FeatcherConfig {
@@ -33,23 +190,117 @@ impl Default for FeatcherConfig {
             max_retries: 5,
             max_request_size: 100,
             max_response_size: 100,
+            breaker_threshold: None,
+            breaker_cooldown: 30_000,
         }
     }
 }
 
 #[derive(Debug, PartialEq, Deserialize)]
 #[serde(default)]
-pub(crate) struct UnistateConfigOptional {
-    pub(crate) initial_height: u64,
-    pub(crate) batch_size: u64,
-    pub(crate) fetch_size: usize,
-    pub(crate) interval: f32,
-    pub(crate) level: Level,
-    pub(crate) network: NetworkType,
+pub struct UnistateConfigOptional {
+    pub initial_height: u64,
+    /// Blocks below the committed height that are reindexed on restart, the rest are final.
+    pub finality_depth: u64,
+    /// Height to backfill to before following the tip, `None` is the tip at start.
+    pub backfill_end: Option<u64>,
+    /// Most blocks a backfill may cover before it is refused, `None` is unlimited.
+    pub max_backfill_range: Option<u64>,
+    /// Run a backfill longer than `max_backfill_range` anyway.
+    pub allow_long_backfill: bool,
+    /// Start indexing at this block instead of the resume height, seeking forward when it
+    /// is ahead. Starting below the resume height reindexes and needs `confirm_reindex`.
+    pub start_block: Option<u64>,
+    /// Allow `start_block` to move indexing back. Unset it once the reindex has started,
+    /// or every restart goes back to `start_block` again.
+    pub confirm_reindex: bool,
+    pub batch_size: u64,
+    pub fetch_size: usize,
+    pub interval: f32,
+    /// Blocks behind the tip that count as lagging, `None` disables the lag alert.
+    pub lag_alert_threshold: Option<u64>,
+    /// Seconds the lag must stay above the threshold before alerting.
+    pub lag_alert_after: u64,
+    pub level: Level,
+    pub network: NetworkType,
+    /// Postgres `statement_timeout` in milliseconds for the batch commit transaction.
+    pub statement_timeout: Option<u64>,
+    /// Persist committed rows to `indexed_events` so late subscribers can replay them.
+    pub event_log: bool,
+    /// Resolve cell deps of RGBPP transactions and record the locks they reference.
+    pub index_cell_deps: bool,
+    /// Resolve inputs spending an output of the same block from the block itself.
+    pub resolve_in_block: bool,
+    /// Record the distinct output type script hashes of every RGBPP transaction.
+    pub index_type_scripts: bool,
+    /// Current BTC tip height, enables flagging unlocks by BTC confirmations.
+    pub btc_tip: Option<u64>,
+    pub btc_confirmations: u64,
+    /// Also store each unlock's `btc_tx_proof` decoded into a `jsonb` column.
+    pub btc_tx_proof_json: bool,
+    /// Also decode each unlock's `btc_tx` into `btc_txs` and `btc_tx_inputs`.
+    pub btc_timelocks: bool,
+    /// Unspendable lock scripts, outputs sent to them are recorded in `burns`.
+    pub burn_locks: Vec<Script>,
+    /// Operations a batch may hold in memory while the database is down before failing.
+    pub write_buffer_limit: Option<usize>,
+    /// Times a batch commit is restarted after losing the database connection.
+    pub db_reconnect_retries: u32,
+    /// Database pool size, commit transactions are capped to it.
+    pub max_connections: u32,
+    /// Parse captured transactions against a golden file instead of indexing.
+    pub golden: Option<GoldenConfig>,
+    /// Keep a Merkle accumulator over indexed lock ids in `lock_accumulator`.
+    pub lock_accumulator: bool,
+    /// Export a table to a file, or import it back, instead of indexing.
+    pub export: Option<ExportConfig>,
+    /// Append per-transaction RGBPP metrics to this file as InfluxDB line protocol.
+    pub influx_line_protocol: Option<PathBuf>,
+    /// Stream committed locks and unlocks to stdout as NDJSON.
+    pub stdout_sink: bool,
+    /// Recently emitted locks and unlocks each sink suppresses on re-emission, 0 disables.
+    pub sink_dedup_window: usize,
+    /// While idle at the tip, index the block template into `pending_rgbpp_locks`.
+    pub index_pending: bool,
+    /// Also store each RGBPP lock's args verbatim in `rgbpp_locks.raw_args`.
+    pub store_raw_args: bool,
+    /// Insert latency in milliseconds the insert batch size is tuned towards, `None`
+    /// always fills statements up to the bind parameter limit.
+    pub insert_latency_target: Option<u64>,
+    /// Append the table and key of every written row to the `changes` feed.
+    pub change_feed: bool,
+    /// Delete the RGBPP rows of these transactions and index them again, instead of
+    /// indexing blocks.
+    pub reprocess: Vec<H256>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Clone)]
+pub struct GoldenConfig {
+    /// JSON array of captured transactions with the outputs their inputs spend.
+    pub captured: PathBuf,
+    pub golden: PathBuf,
+    /// Compare against `golden` rather than overwrite it.
+    #[serde(default)]
+    pub compare: bool,
+    /// Also write the RGBPP lock args and witnesses of `captured` to this directory, as
+    /// seeds for the `rgbpp_molecule` fuzz target.
+    #[serde(default)]
+    pub fuzz_corpus: Option<PathBuf>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Clone)]
+pub struct ExportConfig {
+    pub table: ExportTable,
+    pub path: PathBuf,
+    #[serde(default)]
+    pub format: ExportFormat,
+    /// Read `path` into `table` rather than write it.
+    #[serde(default)]
+    pub import: bool,
 }
 
 #[derive(Debug, PartialEq, Deserialize, Clone, Copy)]
-pub(crate) enum Level {
+pub enum Level {
     Trace,
     Debug,
     Info,
@@ -73,11 +324,43 @@ impl Default for UnistateConfigOptional {
     fn default() -> Self {
         Self {
             initial_height: 1,
+            finality_depth: 0,
+            backfill_end: None,
+            max_backfill_range: None,
+            allow_long_backfill: false,
+            start_block: None,
+            confirm_reindex: false,
             batch_size: 200,
             fetch_size: 5,
             interval: 1.0,
+            lag_alert_threshold: None,
+            lag_alert_after: 300,
             level: Level::Info,
             network: NetworkType::Mainnet,
+            statement_timeout: None,
+            event_log: false,
+            index_cell_deps: false,
+            resolve_in_block: true,
+            index_type_scripts: false,
+            btc_tip: None,
+            btc_confirmations: BTC_JUMP_CONFIRMATION_BLOCKS,
+            btc_tx_proof_json: false,
+            btc_timelocks: false,
+            burn_locks: Vec::new(),
+            write_buffer_limit: None,
+            db_reconnect_retries: 5,
+            max_connections: 10,
+            golden: None,
+            lock_accumulator: false,
+            export: None,
+            influx_line_protocol: None,
+            stdout_sink: false,
+            sink_dedup_window: 0,
+            index_pending: false,
+            store_raw_args: false,
+            insert_latency_target: None,
+            change_feed: false,
+            reprocess: Vec::new(),
         }
     }
 }
@@ -122,7 +405,9 @@ mod tests {
                         featcher: FeatcherConfig {
                             max_retries: 3,
                             ..Default::default()
-                        }
+                        },
+                        rgbpp_scripts: Vec::new(),
+                        rgbpp_type_ids: Vec::new(),
                     }
                 }
             );
@@ -130,4 +415,213 @@ mod tests {
             Ok(())
         });
     }
+
+    #[test]
+    fn test_rgbpp_scripts() {
+        figment::Jail::expect_with(|jail| {
+            jail.create_file(
+                "Config.toml",
+                r#"
+                    [unistate]
+                    url = "testurl"
+
+                    [[unistate.rgbpp_scripts]]
+                    version = 1
+                    code_hash = "0xbc6c568a1a0d0a09f6844dc9d74ddb4343c32143ff25f727c59edf4fb72d6936"
+
+                    [[unistate.rgbpp_scripts]]
+                    version = 2
+                    code_hash = "0x0000000000000000000000000000000000000000000000000000000000000002"
+                    hash_type = "data1"
+                    cell_dep = { tx_hash = "0x0404040404040404040404040404040404040404040404040404040404040404", index = "0x1" }
+
+                    [[unistate.rgbpp_type_ids]]
+                    version = 3
+                    type_id = "0x0000000000000000000000000000000000000000000000000000000000000003"
+                "#,
+            )?;
+
+            let config: Config = Figment::new().merge(Toml::file("Config.toml")).extract()?;
+
+            let scripts = config.unistate.rgbpp_scripts(Constants::Mainnet);
+            assert_eq!(scripts.len(), 3);
+            assert_eq!(scripts[0].version, 1);
+            assert_eq!(
+                scripts[0].code_hash,
+                Constants::Mainnet.rgbpp_lock_script().code_hash
+            );
+            assert_eq!(scripts[0].hash_type, ScriptHashType::Type);
+            assert_eq!(
+                scripts[0].cell_dep,
+                Some(Constants::Mainnet.rgbpp_lock_dep().out_point)
+            );
+            assert_eq!(scripts[1].version, 2);
+            assert_eq!(scripts[1].hash_type, ScriptHashType::Data1);
+            assert_eq!(
+                scripts[1].cell_dep,
+                Some(OutPoint {
+                    tx_hash: H256([4u8; 32]),
+                    index: 1.into(),
+                })
+            );
+            assert_eq!(scripts[2].version, 3);
+            assert_eq!(
+                scripts[2].code_hash,
+                type_id_code_hash(&H256::from_trimmed_str("3").unwrap())
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_rgbpp_scripts_default() {
+        let config = UnistateConfig {
+            url: "testurl".into(),
+            optional_config: Default::default(),
+            featcher: Default::default(),
+            rgbpp_scripts: Vec::new(),
+            rgbpp_type_ids: Vec::new(),
+        };
+
+        assert_eq!(
+            config.rgbpp_scripts(Constants::Testnet),
+            vec![RgbppScriptConfig {
+                version: 1,
+                code_hash: Constants::Testnet.rgbpp_lock_script().code_hash,
+                hash_type: Constants::Testnet.rgbpp_lock_script().hash_type,
+                cell_dep: Some(Constants::Testnet.rgbpp_lock_dep().out_point),
+            }]
+        );
+    }
+
+    fn config(optional_config: UnistateConfigOptional) -> Config {
+        Config {
+            database_url: "".into(),
+            unistate: UnistateConfig {
+                url: "testurl".into(),
+                optional_config,
+                featcher: Default::default(),
+                rgbpp_scripts: Vec::new(),
+                rgbpp_type_ids: Vec::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(config(Default::default()).validate(), Ok(()));
+
+        let err = config(UnistateConfigOptional {
+            batch_size: 0,
+            ..Default::default()
+        })
+        .validate()
+        .unwrap_err();
+        assert_eq!(err.to_string(), "`batch_size` must be greater than zero.");
+
+        let err = config(UnistateConfigOptional {
+            btc_tip: Some(3),
+            btc_confirmations: 6,
+            ..Default::default()
+        })
+        .validate()
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`btc_confirmations` (6) is larger than the BTC chain at `btc_tip` (3), \
+             so no unlock could ever be confirmed."
+        );
+
+        let err = config(UnistateConfigOptional {
+            btc_tip: Some(800_000),
+            btc_confirmations: 6,
+            finality_depth: 4,
+            ..Default::default()
+        })
+        .validate()
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`btc_confirmations` (6) is larger than `finality_depth` (4), \
+             so blocks become final before their unlocks can be confirmed."
+        );
+        assert_eq!(
+            config(UnistateConfigOptional {
+                btc_tip: Some(800_000),
+                btc_confirmations: 6,
+                finality_depth: 24,
+                ..Default::default()
+            })
+            .validate(),
+            Ok(())
+        );
+
+        let err = config(UnistateConfigOptional {
+            initial_height: 100,
+            backfill_end: Some(50),
+            ..Default::default()
+        })
+        .validate()
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ConfigError::BackfillEndBeforeStart {
+                end: 50,
+                initial_height: 100
+            }
+        );
+
+        let err = config(UnistateConfigOptional {
+            start_block: Some(200),
+            backfill_end: Some(150),
+            ..Default::default()
+        })
+        .validate()
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ConfigError::StartBlockAfterBackfillEnd {
+                start_block: 200,
+                end: 150
+            }
+        );
+    }
+
+    #[test]
+    fn test_validate_rgbpp_scripts() {
+        let mut config = config(Default::default());
+        config.unistate.rgbpp_scripts = vec![
+            RgbppScriptConfig {
+                version: 1,
+                code_hash: Constants::Mainnet.rgbpp_lock_script().code_hash,
+                hash_type: ScriptHashType::Type,
+                cell_dep: None,
+            },
+            RgbppScriptConfig {
+                version: 1,
+                code_hash: H256([2u8; 32]),
+                hash_type: ScriptHashType::Type,
+                cell_dep: None,
+            },
+        ];
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "RGBPP script version 1 is configured more than once in `rgbpp_scripts`."
+        );
+
+        config.unistate.rgbpp_scripts[1] = RgbppScriptConfig {
+            version: 2,
+            code_hash: H256::default(),
+            hash_type: ScriptHashType::Type,
+            cell_dep: None,
+        };
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "RGBPP script version 2 has an all-zero code hash, which matches no lock on Mainnet."
+        );
+
+        config.unistate.url = String::new();
+        assert_eq!(config.validate(), Err(ConfigError::EmptyUrl));
+    }
 }
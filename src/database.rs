@@ -1,21 +1,148 @@
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures::{Stream, TryStreamExt as _};
 use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
 use sea_orm::{
-    sea_query::OnConflict, ActiveModelTrait, DbConn, DbErr, EntityTrait as _, TransactionTrait,
+    sea_query::{Expr, OnConflict},
+    ActiveModelTrait, ColumnTrait as _, ConnectionTrait, DbConn, DbErr, EntityTrait, Iterable as _,
+    PaginatorTrait as _, PrimaryKeyToColumn as _, QueryFilter as _, QueryOrder as _, Select,
+    TransactionTrait,
 };
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::{mpsc, oneshot, AcquireError, OwnedSemaphorePermit, Semaphore};
 
-use crate::entity::{
-    addresses, block_height, clusters, rgbpp_locks, rgbpp_unlocks, spore_actions, spores,
-    token_info, transaction_outputs_status, xudt_cell,
+use crate::{
+    accumulator::LockAccumulator,
+    backoff::Backoff,
+    changes::{self, Change},
+    entity::{
+        addresses, block_height, btc_tx_inputs, btc_txs, burns, clusters, dead_letters,
+        rgbpp_locks, rgbpp_referenced_locks, rgbpp_unlocks, spore_actions, spores, token_info,
+        transaction_outputs_status, tx_type_scripts, xudt_cell,
+    },
+    events::{EventLog, IndexedEvent},
+    sink::{Sink, TxMetrics},
 };
 
 pub struct DatabaseProcessor {
     pub recv: mpsc::UnboundedReceiver<Operations>,
     pub height: u64,
     pub commited: oneshot::Receiver<()>,
-    pub db: DbConn,
+    pub db: Arc<DbConn>,
+    pub statement_timeout: Option<Duration>,
+    pub event_log: Option<EventLog>,
+    pub write_buffer_limit: Option<usize>,
+    pub reconnect_retries: u32,
+    pub stats: Arc<DbStats>,
+    pub txn_limiter: Option<TxnLimiter>,
+    pub sinks: Vec<Arc<dyn Sink>>,
+    pub lock_accumulator: bool,
+    pub batch_tuner: Option<Arc<BatchTuner>>,
+    pub change_feed: bool,
+}
+
+/// Counters shared by every batch commit.
+#[derive(Debug, Default)]
+pub struct DbStats {
+    /// Commit transactions restarted after losing the database connection.
+    pub reconnects: AtomicU64,
+}
+
+/// Caps how many commit transactions are open at once so they never exhaust the pool.
+#[derive(Debug, Clone)]
+pub struct TxnLimiter {
+    permits: Arc<Semaphore>,
+    in_flight: Arc<AtomicUsize>,
+    peak: Arc<AtomicUsize>,
+}
+
+/// Held for as long as a commit transaction is open.
+pub struct TxnPermit {
+    _permit: OwnedSemaphorePermit,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl TxnLimiter {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(limit)),
+            in_flight: Default::default(),
+            peak: Default::default(),
+        }
+    }
+
+    pub async fn acquire(&self) -> Result<TxnPermit, AcquireError> {
+        let permit = self.permits.clone().acquire_owned().await?;
+        let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.peak.fetch_max(in_flight, Ordering::SeqCst);
+        Ok(TxnPermit {
+            _permit: permit,
+            in_flight: self.in_flight.clone(),
+        })
+    }
+
+    /// The most transactions ever open at the same time.
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for TxnPermit {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Adjusts how many rows go into one `insert_many` from the latency of the last ones.
+///
+/// Inserts well under `target` grow the batch by a quarter, inserts over it halve the
+/// batch, so a loaded database quickly gets smaller statements.
+#[derive(Debug)]
+pub struct BatchTuner {
+    size: AtomicUsize,
+    min: usize,
+    max: usize,
+    target: Duration,
+}
+
+impl BatchTuner {
+    pub fn new(initial: usize, min: usize, max: usize, target: Duration) -> Self {
+        let min = min.max(1);
+        Self {
+            size: AtomicUsize::new(initial.clamp(min, max.max(min))),
+            min,
+            max: max.max(min),
+            target,
+        }
+    }
+
+    /// Rows per insert statement, before the per entity bind parameter limit.
+    pub fn current(&self) -> usize {
+        self.size.load(Ordering::Relaxed)
+    }
+
+    pub fn observe(&self, latency: Duration) {
+        let _ = self
+            .size
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |size| {
+                if latency > self.target {
+                    Some((size / 2).max(self.min))
+                } else if latency < self.target / 2 {
+                    Some((size + size / 4).max(size + 1).min(self.max))
+                } else {
+                    None
+                }
+            });
+    }
 }
 
+#[derive(Clone)]
 pub enum Operations {
     UpdateXudtCell(transaction_outputs_status::ActiveModel),
     UpsertTokenInfo(token_info::ActiveModel),
@@ -25,7 +152,17 @@ pub enum Operations {
     UpsertSpores(spores::ActiveModel),
     UpsertActions(spore_actions::ActiveModel),
     UpsertLock(rgbpp_locks::ActiveModel),
+    SpendLock(rgbpp_locks::ActiveModel),
+    UpsertReferencedLock(rgbpp_referenced_locks::ActiveModel),
     UpsertUnlock(rgbpp_unlocks::ActiveModel),
+    UpsertTxTypeScript(tx_type_scripts::ActiveModel),
+    UpsertBurn(burns::ActiveModel),
+    /// A transaction whose parsing panicked, recorded instead of its rows.
+    UpsertDeadLetter(dead_letters::ActiveModel),
+    UpsertBtcTx(btc_txs::ActiveModel),
+    UpsertBtcTxInput(btc_tx_inputs::ActiveModel),
+    /// Not written to the database, only handed to sinks.
+    TxMetrics(TxMetrics),
 }
 
 macro_rules! define_conflict {
@@ -39,20 +176,25 @@ macro_rules! define_conflict {
 
 const MAX_RETRIES: u32 = 5;
 const RETRY_DELAY_MS: u64 = 500;
+const MAX_HEALTH_BACKOFF: Duration = Duration::from_secs(30);
 
 macro_rules! define_upsert_function {
     ($fn_name:ident, $entity:ident, $filed_count:expr, $conflict:expr $(,$merge:ident)?) => {
         async fn $fn_name(
             buffer: Vec<$entity::ActiveModel>,
             db: &sea_orm::DatabaseTransaction,
+            tuner: Option<&BatchTuner>,
         ) -> Result<(), anyhow::Error> {
             $(let buffer = $merge(buffer);)?
+            let max_chunk = (u16::MAX / $filed_count) as usize;
+            let chunk = tuner.map_or(max_chunk, |tuner| tuner.current().min(max_chunk));
             let futs = buffer
                 .into_par_iter()
-                .chunks((u16::MAX / $filed_count) as usize)
+                .chunks(chunk)
                 .map(|batch| async move {
+                    let start = std::time::Instant::now();
                     let mut retry_count = 0;
-                    loop {
+                    let result = loop {
                         match $entity::Entity::insert_many(batch.clone())
                             .on_conflict($conflict)
                             .exec_without_returning(db)
@@ -68,7 +210,11 @@ macro_rules! define_upsert_function {
                             }
                             Err(e) => break Err(e),
                         }
+                    };
+                    if let (Ok(_), Some(tuner)) = (&result, tuner) {
+                        tuner.observe(start.elapsed());
                     }
+                    result
                 })
                 .collect::<Vec<_>>();
 
@@ -167,23 +313,93 @@ define_upsert_functions! {
 
     upsert_many_locks => (
         rgbpp_locks,
-        4,
+        13,
+        define_conflict!(
+            rgbpp_locks::Column::Tx,
+            rgbpp_locks::Column::OutputIndex
+        ),
+        merge_locks
+    ),
+
+    upsert_many_spent_locks => (
+        rgbpp_locks,
+        13,
+        define_conflict!(
+            rgbpp_locks::Column::Tx,
+            rgbpp_locks::Column::OutputIndex => [
+                rgbpp_locks::Column::SpentTx,
+                rgbpp_locks::Column::SpentBlockNumber
+            ]
+        ),
+        merge_locks
+    ),
+
+    upsert_many_referenced_locks => (
+        rgbpp_referenced_locks,
+        5,
         define_conflict!(
-            rgbpp_locks::Column::LockId
+            rgbpp_referenced_locks::Column::LockId,
+            rgbpp_referenced_locks::Column::Tx
         )
     ),
 
     upsert_many_unlocks => (
         rgbpp_unlocks,
-        7,
+        13,
         define_conflict!(
             rgbpp_unlocks::Column::UnlockId
         )
     ),
+
+    upsert_many_tx_type_scripts => (
+        tx_type_scripts,
+        2,
+        define_conflict!(
+            tx_type_scripts::Column::Tx,
+            tx_type_scripts::Column::TypeScriptHash
+        )
+    ),
+
+    upsert_many_burns => (
+        burns,
+        5,
+        define_conflict!(
+            burns::Column::Tx,
+            burns::Column::OutputIndex
+        )
+    ),
+
+    upsert_many_dead_letters => (
+        dead_letters,
+        3,
+        define_conflict!(
+            dead_letters::Column::Tx => [
+                dead_letters::Column::BlockNumber,
+                dead_letters::Column::Reason
+            ]
+        )
+    ),
+
+    upsert_many_btc_txs => (
+        btc_txs,
+        2,
+        define_conflict!(
+            btc_txs::Column::BtcTxid
+        )
+    ),
+
+    upsert_many_btc_tx_inputs => (
+        btc_tx_inputs,
+        3,
+        define_conflict!(
+            btc_tx_inputs::Column::BtcTxid,
+            btc_tx_inputs::Column::InputIndex
+        )
+    ),
 }
 
 macro_rules! process_operations {
-    ($commited:expr, $height:expr, $db:expr, $recv:expr, $( $stage:expr => { $( $variant:ident => ($vec:ident, $upsert_fn:ident) ),* } ),*) => {
+    ($commited:expr, $height:expr, $db:expr, $statement_timeout:expr, $event_log:expr, $write_buffer_limit:expr, $reconnect_retries:expr, $stats:expr, $txn_limiter:expr, $sinks:expr, $lock_accumulator:expr, $batch_tuner:expr, $change_feed:expr, $recv:expr, $( $stage:expr => { $( $variant:ident => ($vec:ident, $upsert_fn:ident) ),* } ),*) => {
         {
             use std::time::Instant;
             use futures::StreamExt;
@@ -196,9 +412,28 @@ macro_rules! process_operations {
 
             let recv_start = Instant::now();
             let mut sum = 0;
+            let mut events = Vec::new();
+            let mut sink_ops = Vec::new();
+            let mut lock_ids = Vec::new();
+            let mut written = Vec::new();
             while let Some(op) = $recv.recv().await {
                 sum += 1;
+                if $event_log.is_some() {
+                    events.extend(IndexedEvent::from_operation(&op));
+                }
+                if $lock_accumulator {
+                    if let Operations::UpsertLock(lock) = &op {
+                        lock_ids.extend(lock.lock_id.try_as_ref().cloned());
+                    }
+                }
+                if $change_feed {
+                    written.extend(Change::of(&op));
+                }
+                if !$sinks.is_empty() {
+                    sink_ops.push(op.clone());
+                }
                 match op {
+                    Operations::TxMetrics(_) => {}
                     $(
                         $(
                             Operations::$variant(data) => $vec.push(data),
@@ -211,50 +446,114 @@ macro_rules! process_operations {
 
             $commited.await?;
 
+            if let Some(limit) = $write_buffer_limit {
+                wait_until_healthy(|| $db.ping(), sum, limit, Duration::from_millis(RETRY_DELAY_MS))
+                    .await?;
+            }
+
             let handle_start = Instant::now();
 
-            let txn = $db.begin().await?;
+            let mut attempt = 0;
+            let mut backoff = Backoff::new(Duration::from_millis(RETRY_DELAY_MS), MAX_HEALTH_BACKOFF);
+            let events = loop {
+                let result: anyhow::Result<_> = async {
+                    let _permit = match &$txn_limiter {
+                        Some(limiter) => Some(limiter.acquire().await?),
+                        None => None,
+                    };
+                    let txn = $db.begin().await?;
+                    set_statement_timeout(&txn, $statement_timeout).await?;
 
-            $(
-                let stage_start = Instant::now();
+                    // Before the stages, so locks indexed by an earlier run are told apart.
+                    if $lock_accumulator {
+                        LockAccumulator::append(&txn, $height, lock_ids.clone()).await?;
+                    }
 
-                let (mut scope,_) =  unsafe {
-                    async_scoped::TokioScope::scope(|scope| {
-                        let txnr = &txn;
-                        $(
-                            if !$vec.is_empty() {
-                                scope.spawn(async move {
-                                    $upsert_fn($vec, &txnr).await.map_err(|e| {
-                                        tracing::error!("Failed to upsert {}: {:?}", stringify!($variant), e);
-                                        e
-                                    })
-                                });
-                            }
-                        )*
-                    })
-                };
+                    $(
+                        let stage_start = Instant::now();
 
+                        let (mut scope,_) =  unsafe {
+                            async_scoped::TokioScope::scope(|scope| {
+                                let txnr = &txn;
+                                let tuner = $batch_tuner.as_deref();
+                                $(
+                                    if !$vec.is_empty() {
+                                        // Cloned so the batch can be replayed after a reconnect.
+                                        let $vec = $vec.clone();
+                                        scope.spawn(async move {
+                                            $upsert_fn($vec, &txnr, tuner).await.map_err(|e| {
+                                                tracing::error!("Failed to upsert {}: {:?}", stringify!($variant), e);
+                                                e
+                                            })
+                                        });
+                                    }
+                                )*
+                            })
+                        };
 
-                while let Some(result) = scope.next().await {
-                    result??;
-                }
 
-                drop(scope);
+                        while let Some(result) = scope.next().await {
+                            result??;
+                        }
 
-                let stage_duration = stage_start.elapsed();
-                tracing::debug!("Stage {} took: {:?}", $stage, stage_duration);
-            )*
+                        drop(scope);
+
+                        let stage_duration = stage_start.elapsed();
+                        tracing::debug!("Stage {} took: {:?}", $stage, stage_duration);
+                    )*
+
+                    if $change_feed {
+                        changes::append(&txn, $height, written.clone()).await?;
+                    }
+
+                    // 更新区块高度
+                    block_height::ActiveModel {
+                        id: sea_orm::Set(1),
+                        height: sea_orm::Set($height as i64),
+                    }
+                    .update(&txn)
+                    .await?;
+
+                    let events = match &$event_log {
+                        Some(log) => log.append(&txn, $height, events.clone()).await?,
+                        None => Vec::new(),
+                    };
+
+                    tracing::debug!("committing {} ...", $height);
+                    txn.commit().await?;
+
+                    Ok(events)
+                }
+                .await;
+
+                match result {
+                    Ok(events) => break events,
+                    Err(e) if attempt < $reconnect_retries && is_connection_error(&e) => {
+                        attempt += 1;
+                        $stats.reconnects.fetch_add(1, Ordering::Relaxed);
+                        tracing::warn!(
+                            "Lost database connection committing {}, retry {attempt}/{}: {e}",
+                            $height,
+                            $reconnect_retries
+                        );
+                        backoff.wait().await;
+                    }
+                    Err(e) => return Err(e),
+                }
+            };
 
-            // 更新区块高度
-            block_height::ActiveModel {
-                id: sea_orm::Set(1),
-                height: sea_orm::Set($height as i64),
+            if let Some(log) = &$event_log {
+                log.publish(events);
             }
-            .update(&txn)
-            .await?;
 
-            tracing::debug!("committing {} ...", $height);
-            txn.commit().await?;
+            // The rows are committed already, a failing sink must not fail the batch.
+            for op in &sink_ops {
+                for sink in &$sinks {
+                    if let Err(e) = sink.emit(op) {
+                        tracing::warn!("Sink failed at {}: {e:?}", $height);
+                    }
+                }
+            }
 
             let db_duration = Instant::now().duration_since(handle_start);
             tracing::info!("Processed a total of {sum} database operations. Execution time: {:?}", db_duration);
@@ -264,7 +563,7 @@ macro_rules! process_operations {
 
 impl DatabaseProcessor {
     pub fn new(
-        db: DbConn,
+        db: Arc<DbConn>,
         height: u64,
     ) -> (Self, mpsc::UnboundedSender<Operations>, oneshot::Sender<()>) {
         let (tx, rx) = mpsc::unbounded_channel();
@@ -275,29 +574,126 @@ impl DatabaseProcessor {
                 height,
                 db,
                 commited: commit_rx,
+                statement_timeout: None,
+                event_log: None,
+                write_buffer_limit: None,
+                reconnect_retries: MAX_RETRIES,
+                stats: Default::default(),
+                txn_limiter: None,
+                sinks: Vec::new(),
+                lock_accumulator: false,
+                batch_tuner: None,
+                change_feed: false,
             },
             tx,
             commit_tx,
         )
     }
 
+    /// Abort any statement of the commit transaction running longer than `timeout`.
+    pub fn with_statement_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.statement_timeout = timeout;
+        self
+    }
+
+    /// Persist and broadcast an [`IndexedEvent`] for every committed row.
+    pub fn with_event_log(mut self, event_log: Option<EventLog>) -> Self {
+        self.event_log = event_log;
+        self
+    }
+
+    /// Hold up to `limit` received operations in memory while the database is
+    /// unreachable, flushing them once it answers again instead of failing the batch.
+    pub fn with_write_buffer_limit(mut self, limit: Option<usize>) -> Self {
+        self.write_buffer_limit = limit;
+        self
+    }
+
+    /// Restart the commit transaction up to `retries` times when the connection drops.
+    pub fn with_reconnect_retries(mut self, retries: u32) -> Self {
+        self.reconnect_retries = retries;
+        self
+    }
+
+    pub fn with_stats(mut self, stats: Arc<DbStats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Wait for a permit from `limiter` before opening the commit transaction.
+    pub fn with_txn_limiter(mut self, limiter: Option<TxnLimiter>) -> Self {
+        self.txn_limiter = limiter;
+        self
+    }
+
+    /// Hand every committed operation to `sinks` as well.
+    pub fn with_sinks(mut self, sinks: Vec<Arc<dyn Sink>>) -> Self {
+        self.sinks = sinks;
+        self
+    }
+
+    /// Extend the persisted lock id Merkle accumulator with every committed lock.
+    pub fn with_lock_accumulator(mut self, lock_accumulator: bool) -> Self {
+        self.lock_accumulator = lock_accumulator;
+        self
+    }
+
+    /// Size insert statements with `tuner` instead of only the bind parameter limit.
+    pub fn with_batch_tuner(mut self, tuner: Option<Arc<BatchTuner>>) -> Self {
+        self.batch_tuner = tuner;
+        self
+    }
+
+    /// Record every written row's table and key in the `changes` feed.
+    pub fn with_change_feed(mut self, change_feed: bool) -> Self {
+        self.change_feed = change_feed;
+        self
+    }
+
     pub async fn handle(self) -> anyhow::Result<()> {
         let Self {
             mut recv,
             db,
             height,
             commited,
+            statement_timeout,
+            event_log,
+            write_buffer_limit,
+            reconnect_retries,
+            stats,
+            txn_limiter,
+            sinks,
+            lock_accumulator,
+            batch_tuner,
+            change_feed,
         } = self;
 
         process_operations! {
             commited,
             height,
             db,
+            statement_timeout,
+            event_log,
+            write_buffer_limit,
+            reconnect_retries,
+            stats,
+            txn_limiter,
+            sinks,
+            lock_accumulator,
+            batch_tuner,
+            change_feed,
             recv,
             0 => {
                 UpsertAddress => (address_vec, upsert_many_addresses),
                 UpsertLock => (lock_vec, upsert_many_locks),
-                UpsertUnlock => (unlock_vec, upsert_many_unlocks)
+                SpendLock => (spent_lock_vec, upsert_many_spent_locks),
+                UpsertReferencedLock => (referenced_lock_vec, upsert_many_referenced_locks),
+                UpsertUnlock => (unlock_vec, upsert_many_unlocks),
+                UpsertTxTypeScript => (tx_type_script_vec, upsert_many_tx_type_scripts),
+                UpsertBurn => (burn_vec, upsert_many_burns),
+                UpsertDeadLetter => (dead_letter_vec, upsert_many_dead_letters),
+                UpsertBtcTx => (btc_tx_vec, upsert_many_btc_txs),
+                UpsertBtcTxInput => (btc_tx_input_vec, upsert_many_btc_tx_inputs)
             },
             1 => {
                 UpsertTokenInfo => (token_info_vec, upsert_many_info),
@@ -350,3 +746,519 @@ macro_rules! merge_models {
 
 merge_models!(merge_clusters, clusters);
 merge_models!(merge_spores, spores);
+
+/// Keep one lock per cell, so a batch never updates the same row twice.
+///
+/// A spent copy of a cell wins over an unspent one.
+fn merge_locks(items: Vec<rgbpp_locks::ActiveModel>) -> Vec<rgbpp_locks::ActiveModel> {
+    use dashmap::mapref::entry::Entry;
+
+    let unique_items = dashmap::DashMap::<(Vec<u8>, i32), rgbpp_locks::ActiveModel>::new();
+
+    items.into_par_iter().for_each(|item| {
+        let (Some(tx), Some(output_index)) = (item.tx.try_as_ref(), item.output_index.try_as_ref())
+        else {
+            return;
+        };
+        let spent = matches!(item.spent_tx.try_as_ref(), Some(Some(_)));
+        match unique_items.entry((tx.clone(), *output_index)) {
+            Entry::Occupied(mut entry) if spent => {
+                entry.insert(item);
+            }
+            Entry::Occupied(_) => {}
+            Entry::Vacant(entry) => {
+                entry.insert(item);
+            }
+        }
+    });
+
+    let (_, results) = unique_items.into_par_iter().unzip::<_, _, Vec<_>, Vec<_>>();
+    results
+}
+
+/// Whether `e` means the connection was lost rather than the statements being rejected.
+fn is_connection_error(e: &anyhow::Error) -> bool {
+    use sea_orm::{RuntimeErr, SqlxError};
+
+    match e.downcast_ref::<DbErr>() {
+        Some(DbErr::Conn(_) | DbErr::ConnectionAcquire(_)) => true,
+        Some(DbErr::Exec(RuntimeErr::SqlxError(e)) | DbErr::Query(RuntimeErr::SqlxError(e))) => {
+            matches!(
+                e,
+                SqlxError::Io(_) | SqlxError::PoolTimedOut | SqlxError::PoolClosed
+            )
+        }
+        _ => false,
+    }
+}
+
+/// Poll `ping` with exponential backoff until the database is reachable again.
+///
+/// Fails right away if the `buffered` operations already exceed `limit`, rather than
+/// holding more in memory during the outage.
+async fn wait_until_healthy<F, Fut>(
+    mut ping: F,
+    buffered: usize,
+    limit: usize,
+    backoff: Duration,
+) -> anyhow::Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), DbErr>>,
+{
+    let mut backoff = Backoff::new(backoff, MAX_HEALTH_BACKOFF);
+    loop {
+        match ping().await {
+            Ok(()) => return Ok(()),
+            Err(e) if buffered > limit => anyhow::bail!(
+                "Database unavailable with {buffered} buffered operations over the limit of {limit}: {e}"
+            ),
+            Err(e) => {
+                tracing::warn!(
+                    "Database unavailable, holding {buffered} operations until it recovers: {e}"
+                );
+                backoff.wait().await;
+            }
+        }
+    }
+}
+
+/// Apply a Postgres `statement_timeout` scoped to the current transaction.
+pub async fn set_statement_timeout<C: ConnectionTrait>(
+    txn: &C,
+    timeout: Option<Duration>,
+) -> Result<(), DbErr> {
+    if let Some(timeout) = timeout {
+        txn.execute_unprepared(&format!(
+            "SET LOCAL statement_timeout = {}",
+            timeout.as_millis()
+        ))
+        .await?;
+    }
+    Ok(())
+}
+
+/// Flag every lock bound to one of the RBF-replaced BTC transactions `btc_txids`.
+///
+/// `btc_txids` are in display order, as stored in `rgbpp_locks.btc_txid`.
+pub async fn mark_btc_replaced(db: &DbConn, btc_txids: Vec<Vec<u8>>) -> Result<u64, DbErr> {
+    if btc_txids.is_empty() {
+        return Ok(0);
+    }
+
+    let res = rgbpp_locks::Entity::update_many()
+        .col_expr(rgbpp_locks::Column::BtcReplaced, Expr::value(true))
+        .filter(rgbpp_locks::Column::BtcTxid.is_in(btc_txids))
+        .exec(db)
+        .await?;
+
+    Ok(res.rows_affected)
+}
+
+/// Remove the RGBPP rows a previous run produced for `tx_hash` so it can be reprocessed cleanly.
+///
+/// Locks created by the transaction and its unlocks are deleted, and locks it spent are
+/// marked live again, within `statement_timeout`. Returns the number of affected rows.
+pub async fn delete_rows_for_tx(
+    db: &DbConn,
+    tx_hash: &[u8],
+    statement_timeout: Option<Duration>,
+) -> Result<u64, DbErr> {
+    let txn = db.begin().await?;
+    set_statement_timeout(&txn, statement_timeout).await?;
+
+    let locks = rgbpp_locks::Entity::delete_many()
+        .filter(rgbpp_locks::Column::Tx.eq(tx_hash))
+        .exec(&txn)
+        .await?;
+
+    let spent_locks = rgbpp_locks::Entity::update_many()
+        .col_expr(
+            rgbpp_locks::Column::SpentTx,
+            Expr::value(Option::<Vec<u8>>::None),
+        )
+        .col_expr(
+            rgbpp_locks::Column::SpentBlockNumber,
+            Expr::value(Option::<i64>::None),
+        )
+        .filter(rgbpp_locks::Column::SpentTx.eq(tx_hash))
+        .exec(&txn)
+        .await?;
+
+    let referenced_locks = rgbpp_referenced_locks::Entity::delete_many()
+        .filter(rgbpp_referenced_locks::Column::Tx.eq(tx_hash))
+        .exec(&txn)
+        .await?;
+
+    let unlocks = rgbpp_unlocks::Entity::delete_many()
+        .filter(rgbpp_unlocks::Column::Tx.eq(tx_hash))
+        .exec(&txn)
+        .await?;
+
+    txn.commit().await?;
+
+    Ok(locks.rows_affected
+        + spent_locks.rows_affected
+        + referenced_locks.rows_affected
+        + unlocks.rows_affected)
+}
+
+/// Stream every row of `E` ordered by primary key, holding at most one page in memory.
+pub fn stream_table<'db, E>(
+    db: &'db DbConn,
+    page_size: u64,
+) -> impl Stream<Item = Result<E::Model, DbErr>> + 'db
+where
+    E: EntityTrait,
+    E::Model: Send + Sync + 'db,
+{
+    let mut select = E::find();
+    for key in E::PrimaryKey::iter() {
+        select = select.order_by_asc(key.into_column());
+    }
+
+    stream_select(select, db, page_size)
+}
+
+/// Stream the rows of an ordered `select`, holding at most one page in memory.
+pub fn stream_select<'db, E>(
+    select: Select<E>,
+    db: &'db DbConn,
+    page_size: u64,
+) -> impl Stream<Item = Result<E::Model, DbErr>> + 'db
+where
+    E: EntityTrait,
+    E::Model: Send + Sync + 'db,
+{
+    select
+        .paginate(db, page_size)
+        .into_stream()
+        .map_ok(|page| futures::stream::iter(page.into_iter().map(Ok)))
+        .try_flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt as _;
+    use sea_orm::{DatabaseBackend, MockDatabase, MockExecResult, Statement, Transaction};
+
+    use super::*;
+
+    fn lock(i: u8) -> rgbpp_locks::Model {
+        rgbpp_locks::Model {
+            lock_id: vec![i],
+            tx: vec![i],
+            out_index: i as i32,
+            btc_txid: vec![i],
+            script_version: 1,
+            output_index: 0,
+            capacity: None,
+            spent_tx: None,
+            btc_replaced: false,
+            owner_lock_hash: None,
+            block_number: None,
+            raw_args: None,
+            spent_block_number: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_table() {
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([
+                vec![lock(0), lock(1)],
+                vec![lock(2), lock(3)],
+                vec![lock(4)],
+            ])
+            .append_query_results([Vec::<rgbpp_locks::Model>::new()])
+            .into_connection();
+
+        let rows = stream_table::<rgbpp_locks::Entity>(&db, 2)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        assert_eq!(rows, (0..5).map(lock).collect::<Vec<_>>());
+        // one query per page plus the terminating empty page
+        assert_eq!(db.into_transaction_log().len(), 4);
+    }
+
+    #[test]
+    fn test_merge_locks() {
+        use sea_orm::IntoActiveModel as _;
+
+        // Two cells bound to the same BTC UTXO, the second one seen twice.
+        let cell = |output_index, spent_tx: Option<Vec<u8>>| {
+            rgbpp_locks::Model {
+                tx: vec![1],
+                output_index,
+                spent_tx,
+                ..lock(0)
+            }
+            .into_active_model()
+        };
+        let mut merged = merge_locks(vec![cell(0, None), cell(1, Some(vec![2])), cell(1, None)]);
+        merged.sort_by_key(|lock| lock.output_index.clone().unwrap());
+
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].spent_tx.try_as_ref(), Some(&None));
+        assert_eq!(merged[1].spent_tx.try_as_ref(), Some(&Some(vec![2])));
+    }
+
+    #[tokio::test]
+    async fn test_mark_btc_replaced() {
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_exec_results([MockExecResult {
+                last_insert_id: 0,
+                rows_affected: 1,
+            }])
+            .into_connection();
+
+        assert_eq!(mark_btc_replaced(&db, Vec::new()).await.unwrap(), 0);
+        assert_eq!(
+            mark_btc_replaced(&db, vec![vec![7u8; 32]]).await.unwrap(),
+            1
+        );
+
+        assert_eq!(
+            db.into_transaction_log(),
+            [Transaction::from_sql_and_values(
+                DatabaseBackend::Postgres,
+                r#"UPDATE "rgbpp_locks" SET "btc_replaced" = $1 WHERE "rgbpp_locks"."btc_txid" IN ($2)"#,
+                [true.into(), vec![7u8; 32].into()]
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete_rows_for_tx() {
+        let affected = |rows_affected| MockExecResult {
+            last_insert_id: 0,
+            rows_affected,
+        };
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_exec_results([
+                affected(0),
+                affected(2),
+                affected(1),
+                affected(0),
+                affected(3),
+            ])
+            .into_connection();
+
+        assert_eq!(
+            delete_rows_for_tx(&db, &[1u8; 32], Some(Duration::from_millis(500)))
+                .await
+                .unwrap(),
+            6
+        );
+
+        let tx = || sea_orm::Value::from(vec![1u8; 32]);
+        let statement = |sql: &str, values| {
+            Statement::from_sql_and_values(DatabaseBackend::Postgres, sql, values)
+        };
+        assert_eq!(
+            db.into_transaction_log(),
+            [Transaction::many([
+                Statement::from_string(DatabaseBackend::Postgres, "BEGIN"),
+                Statement::from_string(
+                    DatabaseBackend::Postgres,
+                    "SET LOCAL statement_timeout = 500"
+                ),
+                statement(
+                    r#"DELETE FROM "rgbpp_locks" WHERE "rgbpp_locks"."tx" = $1"#,
+                    vec![tx()]
+                ),
+                statement(
+                    r#"UPDATE "rgbpp_locks" SET "spent_tx" = $1, "spent_block_number" = $2 WHERE "rgbpp_locks"."spent_tx" = $3"#,
+                    vec![
+                        Option::<Vec<u8>>::None.into(),
+                        Option::<i64>::None.into(),
+                        tx()
+                    ]
+                ),
+                statement(
+                    r#"DELETE FROM "rgbpp_referenced_locks" WHERE "rgbpp_referenced_locks"."tx" = $1"#,
+                    vec![tx()]
+                ),
+                statement(
+                    r#"DELETE FROM "rgbpp_unlocks" WHERE "rgbpp_unlocks"."tx" = $1"#,
+                    vec![tx()]
+                ),
+                Statement::from_string(DatabaseBackend::Postgres, "COMMIT"),
+            ])]
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated Postgres database in DATABASE_URL"]
+    async fn test_statement_timeout_aborts_rollback() {
+        let url = std::env::var("DATABASE_URL").unwrap();
+        let db = sea_orm::Database::connect(&url).await.unwrap();
+
+        // Another session holds the table, so the rollback waits until it times out.
+        let holder = db.begin().await.unwrap();
+        holder
+            .execute_unprepared("LOCK TABLE rgbpp_locks IN ACCESS EXCLUSIVE MODE")
+            .await
+            .unwrap();
+
+        let err = delete_rows_for_tx(&db, &[1u8; 32], Some(Duration::from_millis(50)))
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("canceling statement due to statement timeout"),
+            "{err}"
+        );
+
+        holder.rollback().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_set_statement_timeout() {
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_exec_results([MockExecResult::default()])
+            .into_connection();
+
+        set_statement_timeout(&db, None).await.unwrap();
+        set_statement_timeout(&db, Some(Duration::from_millis(1)))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            db.into_transaction_log(),
+            [Transaction::one(Statement::from_string(
+                DatabaseBackend::Postgres,
+                "SET LOCAL statement_timeout = 1"
+            ))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wait_until_healthy() {
+        use std::sync::atomic::AtomicU32;
+
+        // The database is down for the first two pings, then recovers.
+        let pings = AtomicU32::new(0);
+        let ping = || async {
+            match pings.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err(DbErr::Conn(sea_orm::RuntimeErr::Internal("down".into()))),
+                _ => Ok(()),
+            }
+        };
+
+        wait_until_healthy(ping, 10, 100, Duration::from_millis(1))
+            .await
+            .unwrap();
+        assert_eq!(pings.load(Ordering::SeqCst), 3);
+
+        pings.store(0, Ordering::SeqCst);
+        assert!(wait_until_healthy(ping, 101, 100, Duration::from_millis(1))
+            .await
+            .is_err());
+        assert_eq!(pings.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_after_connection_loss() {
+        // The first insert hits a dropped connection, the retried transaction succeeds.
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_exec_errors([DbErr::Conn(sea_orm::RuntimeErr::Internal(
+                "connection reset".into(),
+            ))])
+            .append_exec_results([MockExecResult {
+                rows_affected: 1,
+                ..Default::default()
+            }])
+            .append_query_results([[block_height::Model { id: 1, height: 10 }]])
+            .into_connection();
+
+        let stats = Arc::new(DbStats::default());
+        let (processor, op_sender, commited) = DatabaseProcessor::new(Arc::new(db), 10);
+        let processor = processor.with_stats(stats.clone());
+
+        op_sender
+            .send(Operations::UpsertLock(lock(1).into()))
+            .unwrap();
+        drop(op_sender);
+        commited.send(()).unwrap();
+
+        processor.handle().await.unwrap();
+
+        assert_eq!(stats.reconnects.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_batch_tuner() {
+        let tuner = BatchTuner::new(1000, 100, 4000, Duration::from_millis(100));
+
+        for _ in 0..3 {
+            tuner.observe(Duration::from_millis(10));
+        }
+        assert_eq!(tuner.current(), 1952);
+
+        // Within the target band the size holds.
+        tuner.observe(Duration::from_millis(70));
+        assert_eq!(tuner.current(), 1952);
+
+        tuner.observe(Duration::from_millis(500));
+        assert_eq!(tuner.current(), 976);
+        for _ in 0..10 {
+            tuner.observe(Duration::from_millis(500));
+        }
+        assert_eq!(tuner.current(), 100);
+
+        for _ in 0..50 {
+            tuner.observe(Duration::ZERO);
+        }
+        assert_eq!(tuner.current(), 4000);
+    }
+
+    #[tokio::test]
+    async fn test_txn_limiter() {
+        let limiter = TxnLimiter::new(2);
+
+        let tasks = (0..8).map(|_| {
+            let limiter = limiter.clone();
+            tokio::spawn(async move {
+                let _permit = limiter.acquire().await.unwrap();
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            })
+        });
+        futures::future::try_join_all(tasks).await.unwrap();
+
+        assert_eq!(limiter.peak(), 2);
+        assert_eq!(limiter.in_flight.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_processors_share_txn_limiter() {
+        let limiter = TxnLimiter::new(1);
+
+        // More processors than permits, each releasing its permit once committed.
+        let tasks = (0..4).map(|_| {
+            let db = MockDatabase::new(DatabaseBackend::Postgres)
+                .append_exec_results([MockExecResult {
+                    rows_affected: 1,
+                    ..Default::default()
+                }])
+                .append_query_results([[block_height::Model { id: 1, height: 10 }]])
+                .into_connection();
+            let (processor, op_sender, commited) = DatabaseProcessor::new(Arc::new(db), 10);
+            let processor = processor.with_txn_limiter(Some(limiter.clone()));
+            op_sender
+                .send(Operations::UpsertLock(lock(1).into()))
+                .unwrap();
+            drop(op_sender);
+            commited.send(()).unwrap();
+            tokio::spawn(processor.handle())
+        });
+        for result in futures::future::try_join_all(tasks).await.unwrap() {
+            result.unwrap();
+        }
+
+        assert_eq!(limiter.peak(), 1);
+        assert_eq!(limiter.in_flight.load(Ordering::SeqCst), 0);
+    }
+}
@@ -4,12 +4,22 @@ pub mod prelude;
 
 pub mod addresses;
 pub mod block_height;
+pub mod btc_tx_inputs;
+pub mod btc_txs;
+pub mod burns;
+pub mod changes;
 pub mod clusters;
+pub mod dead_letters;
+pub mod indexed_events;
+pub mod lock_accumulator;
+pub mod pending_rgbpp_locks;
 pub mod rgbpp_locks;
+pub mod rgbpp_referenced_locks;
 pub mod rgbpp_unlocks;
 pub mod sea_orm_active_enums;
 pub mod spore_actions;
 pub mod spores;
 pub mod token_info;
 pub mod transaction_outputs_status;
+pub mod tx_type_scripts;
 pub mod xudt_cell;
@@ -2,11 +2,21 @@
 
 pub use super::addresses::Entity as Addresses;
 pub use super::block_height::Entity as BlockHeight;
+pub use super::btc_tx_inputs::Entity as BtcTxInputs;
+pub use super::btc_txs::Entity as BtcTxs;
+pub use super::burns::Entity as Burns;
+pub use super::changes::Entity as Changes;
 pub use super::clusters::Entity as Clusters;
+pub use super::dead_letters::Entity as DeadLetters;
+pub use super::indexed_events::Entity as IndexedEvents;
+pub use super::lock_accumulator::Entity as LockAccumulator;
+pub use super::pending_rgbpp_locks::Entity as PendingRgbppLocks;
 pub use super::rgbpp_locks::Entity as RgbppLocks;
+pub use super::rgbpp_referenced_locks::Entity as RgbppReferencedLocks;
 pub use super::rgbpp_unlocks::Entity as RgbppUnlocks;
 pub use super::spore_actions::Entity as SporeActions;
 pub use super::spores::Entity as Spores;
 pub use super::token_info::Entity as TokenInfo;
 pub use super::transaction_outputs_status::Entity as TransactionOutputsStatus;
+pub use super::tx_type_scripts::Entity as TxTypeScripts;
 pub use super::xudt_cell::Entity as XudtCell;
@@ -1,24 +1,53 @@
 //! `SeaORM` Entity, @generated by sea-orm-codegen 1.0.0-rc.5
 
 use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Serialize, Deserialize)]
 #[sea_orm(table_name = "rgbpp_locks")]
 pub struct Model {
+    #[sea_orm(column_type = "VarBinary(StringLen::None)")]
+    pub lock_id: Vec<u8>,
     #[sea_orm(
         primary_key,
         auto_increment = false,
         column_type = "VarBinary(StringLen::None)"
     )]
-    pub lock_id: Vec<u8>,
-    #[sea_orm(column_type = "VarBinary(StringLen::None)")]
     pub tx: Vec<u8>,
     pub out_index: i32,
     #[sea_orm(column_type = "VarBinary(StringLen::None)")]
     pub btc_txid: Vec<u8>,
+    pub script_version: i16,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub output_index: i32,
+    pub capacity: Option<i64>,
+    #[sea_orm(column_type = "VarBinary(StringLen::None)", nullable)]
+    pub spent_tx: Option<Vec<u8>>,
+    pub btc_replaced: bool,
+    #[sea_orm(column_type = "VarBinary(StringLen::None)", nullable)]
+    pub owner_lock_hash: Option<Vec<u8>>,
+    pub block_number: Option<i64>,
+    #[sea_orm(column_type = "VarBinary(StringLen::None)", nullable)]
+    pub raw_args: Option<Vec<u8>>,
+    pub spent_block_number: Option<i64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
-pub enum Relation {}
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::xudt_cell::Entity",
+        from = "(Column::Tx, Column::OutputIndex)",
+        to = "(super::xudt_cell::Column::TransactionHash, super::xudt_cell::Column::TransactionIndex)",
+        on_update = "NoAction",
+        on_delete = "NoAction"
+    )]
+    XudtCell,
+}
+
+impl Related<super::xudt_cell::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::XudtCell.def()
+    }
+}
 
 impl ActiveModelBehavior for ActiveModel {}
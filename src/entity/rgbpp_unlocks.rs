@@ -1,8 +1,9 @@
 //! `SeaORM` Entity, @generated by sea-orm-codegen 1.0.0-rc.5
 
 use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Serialize, Deserialize)]
 #[sea_orm(table_name = "rgbpp_unlocks")]
 pub struct Model {
     #[sea_orm(
@@ -20,6 +21,14 @@ pub struct Model {
     pub btc_tx: Vec<u8>,
     #[sea_orm(column_type = "VarBinary(StringLen::None)")]
     pub btc_tx_proof: Vec<u8>,
+    pub script_version: i16,
+    pub btc_block_height: Option<i32>,
+    pub btc_confirmed: Option<bool>,
+    #[sea_orm(column_type = "JsonBinary", nullable)]
+    pub btc_tx_proof_json: Option<Json>,
+    #[sea_orm(column_type = "VarBinary(StringLen::None)", nullable)]
+    pub btc_txid: Option<Vec<u8>>,
+    pub block_number: Option<i64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
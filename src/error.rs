@@ -17,4 +17,42 @@ pub enum Error {
     PreviousOutputNotFound { tx_hash: H256, index: u32 },
     #[error("The data for the previous output of the transaction '{tx_hash:?}' at index {index} could not be found. Please verify your inputs.")]
     PreviousOutputDataNotFound { tx_hash: H256, index: u32 },
+    #[error("The CKB node failed repeatedly, so the circuit breaker is open and the request was not sent.")]
+    CircuitOpen,
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    #[error("`unistate.url` is empty. Please set the CKB node RPC endpoint.")]
+    EmptyUrl,
+    #[error("`{field}` must be greater than zero.")]
+    Zero { field: &'static str },
+    #[error("`btc_confirmations` ({confirmations}) is larger than the BTC chain at `btc_tip` ({tip}), so no unlock could ever be confirmed.")]
+    ConfirmationsBeyondTip { confirmations: u64, tip: u64 },
+    #[error("`btc_confirmations` ({confirmations}) is larger than `finality_depth` ({finality_depth}), so blocks become final before their unlocks can be confirmed.")]
+    ConfirmationsBeyondFinality {
+        confirmations: u64,
+        finality_depth: u64,
+    },
+    #[error("`backfill_end` ({end}) is below `initial_height` ({initial_height}), so there is nothing to backfill.")]
+    BackfillEndBeforeStart { end: u64, initial_height: u64 },
+    #[error("`start_block` ({start_block}) is above `backfill_end` ({end}), so there is nothing to backfill.")]
+    StartBlockAfterBackfillEnd { start_block: u64, end: u64 },
+    #[error("RGBPP script version {version} is configured more than once in `rgbpp_scripts`.")]
+    DuplicateScriptVersion { version: i16 },
+    #[error("RGBPP script version {version} has an all-zero code hash, which matches no lock on {network}.")]
+    ZeroCodeHash { version: i16, network: String },
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum BackfillError {
+    #[error("Backfilling {start}..{end} covers {range} blocks, more than `max_backfill_range` ({max}). Set `allow_long_backfill` to run it anyway.")]
+    RangeTooLarge {
+        start: u64,
+        end: u64,
+        range: u64,
+        max: u64,
+    },
+    #[error("`start_block` {start_block} is below the resume height {resume}, which reindexes every block in between. Set `confirm_reindex` to run it anyway.")]
+    ReindexNotConfirmed { start_block: u64, resume: u64 },
 }
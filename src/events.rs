@@ -0,0 +1,198 @@
+use futures::{future, stream, Stream, StreamExt as _, TryStreamExt as _};
+use sea_orm::{
+    ColumnTrait as _, ConnectionTrait, DbConn, DbErr, EntityTrait as _, QueryFilter as _,
+    QueryOrder as _, QuerySelect as _, Set,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::{
+    database::{stream_select, Operations},
+    entity::indexed_events,
+};
+
+const REPLAY_PAGE_SIZE: u64 = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum IndexedEvent {
+    Lock { lock_id: Vec<u8>, tx: Vec<u8> },
+    LockSpent { lock_id: Vec<u8>, spent_tx: Vec<u8> },
+    Unlock { unlock_id: Vec<u8>, tx: Vec<u8> },
+    Committed { height: u64 },
+}
+
+impl IndexedEvent {
+    pub fn from_operation(op: &Operations) -> Option<Self> {
+        match op {
+            Operations::UpsertLock(lock) => Some(Self::Lock {
+                lock_id: lock.lock_id.try_as_ref()?.clone(),
+                tx: lock.tx.try_as_ref()?.clone(),
+            }),
+            Operations::SpendLock(lock) => Some(Self::LockSpent {
+                lock_id: lock.lock_id.try_as_ref()?.clone(),
+                spent_tx: lock.spent_tx.try_as_ref()?.clone()?,
+            }),
+            Operations::UpsertUnlock(unlock) => Some(Self::Unlock {
+                unlock_id: unlock.unlock_id.try_as_ref()?.clone(),
+                tx: unlock.tx.try_as_ref()?.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Append-only log of committed [`IndexedEvent`]s, broadcast live once persisted.
+#[derive(Debug, Clone)]
+pub struct EventLog {
+    sender: broadcast::Sender<(i64, IndexedEvent)>,
+}
+
+impl EventLog {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self { sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<(i64, IndexedEvent)> {
+        self.sender.subscribe()
+    }
+
+    /// Persist `events` followed by a `Committed` marker inside the commit transaction.
+    ///
+    /// Commits are serialized, so sequence numbers are allocated from the current maximum.
+    pub async fn append<C: ConnectionTrait>(
+        &self,
+        txn: &C,
+        height: u64,
+        mut events: Vec<IndexedEvent>,
+    ) -> Result<Vec<(i64, IndexedEvent)>, DbErr> {
+        events.push(IndexedEvent::Committed { height });
+
+        let last_seq = indexed_events::Entity::find()
+            .select_only()
+            .column_as(indexed_events::Column::Seq.max(), "seq")
+            .into_tuple::<Option<i64>>()
+            .one(txn)
+            .await?
+            .flatten()
+            .unwrap_or(0);
+
+        let events = events
+            .into_iter()
+            .zip(last_seq + 1..)
+            .map(|(event, seq)| (seq, event))
+            .collect::<Vec<_>>();
+
+        for batch in events.chunks((u16::MAX / 3) as usize) {
+            let models = batch
+                .iter()
+                .map(|(seq, event)| {
+                    Ok(indexed_events::ActiveModel {
+                        seq: Set(*seq),
+                        height: Set(height as i64),
+                        payload: Set(serde_json::to_value(event)
+                            .map_err(|e| DbErr::Custom(e.to_string()))?),
+                    })
+                })
+                .collect::<Result<Vec<_>, DbErr>>()?;
+
+            indexed_events::Entity::insert_many(models)
+                .exec_without_returning(txn)
+                .await?;
+        }
+
+        Ok(events)
+    }
+
+    pub fn publish(&self, events: Vec<(i64, IndexedEvent)>) {
+        for event in events {
+            // No live subscribers is fine, the log can still be replayed.
+            let _ = self.sender.send(event);
+        }
+    }
+
+    /// Stream persisted events from `from` onwards, then continue with live ones.
+    ///
+    /// The live subscription is taken before reading history, so nothing committed in
+    /// between is missed; events seen in both are yielded once.
+    pub fn replay_from<'db>(
+        &self,
+        db: &'db DbConn,
+        from: i64,
+    ) -> impl Stream<Item = anyhow::Result<(i64, IndexedEvent)>> + 'db {
+        let live = stream::unfold(self.subscribe(), |mut recv| async move {
+            match recv.recv().await {
+                Ok(event) => Some((Ok(event), recv)),
+                Err(broadcast::error::RecvError::Lagged(n)) => Some((
+                    Err(anyhow::anyhow!("event subscriber lagged by {n} events")),
+                    recv,
+                )),
+                Err(broadcast::error::RecvError::Closed) => None,
+            }
+        });
+
+        let history = stream_select(
+            indexed_events::Entity::find()
+                .filter(indexed_events::Column::Seq.gte(from))
+                .order_by_asc(indexed_events::Column::Seq),
+            db,
+            REPLAY_PAGE_SIZE,
+        )
+        .map(|row| -> anyhow::Result<_> {
+            let row = row?;
+            Ok((
+                row.seq,
+                serde_json::from_value::<IndexedEvent>(row.payload)?,
+            ))
+        });
+
+        let mut last_seq = from - 1;
+        history.chain(live).try_filter(move |(seq, _)| {
+            let fresh = *seq > last_seq;
+            if fresh {
+                last_seq = *seq;
+            }
+            future::ready(fresh)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sea_orm::{DatabaseBackend, MockDatabase};
+
+    use super::*;
+
+    fn row(seq: i64) -> indexed_events::Model {
+        indexed_events::Model {
+            seq,
+            height: 10,
+            payload: serde_json::to_value(IndexedEvent::Committed { height: seq as u64 }).unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_from() {
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![row(2), row(3)], vec![]])
+            .into_connection();
+
+        let log = EventLog::new(16);
+        let replay = log.replay_from(&db, 2);
+
+        // seq 3 is both persisted and broadcast, so it must be yielded once.
+        log.publish(vec![
+            (3, IndexedEvent::Committed { height: 3 }),
+            (4, IndexedEvent::Committed { height: 4 }),
+        ]);
+
+        let events = replay.take(3).try_collect::<Vec<_>>().await.unwrap();
+
+        assert_eq!(
+            events.iter().map(|(seq, _)| *seq).collect::<Vec<_>>(),
+            vec![2, 3, 4]
+        );
+        assert_eq!(events[2].1, IndexedEvent::Committed { height: 4 });
+    }
+}
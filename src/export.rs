@@ -0,0 +1,331 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+};
+
+use anyhow::Context as _;
+use futures::TryStreamExt as _;
+use sea_orm::{
+    sea_query::OnConflict, ActiveModelTrait, DbConn, EntityTrait, IntoActiveModel, Iterable as _,
+    PrimaryKeyToColumn as _,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    config::ExportConfig,
+    database::stream_table,
+    entity::{rgbpp_locks, rgbpp_unlocks},
+    seed::{self, LockRow, SeedRow, UnlockRow},
+};
+
+const EXPORT_PAGE_SIZE: u64 = 1000;
+const IMPORT_BATCH_SIZE: usize = 1000;
+
+/// Encodes exported rows one after another and reads them back.
+pub trait Codec {
+    fn encode<T: Serialize, W: Write>(&self, value: &T, writer: &mut W) -> anyhow::Result<()>;
+
+    /// The next row, `None` once `reader` is exhausted.
+    fn decode<T: DeserializeOwned, R: BufRead>(&self, reader: &mut R) -> anyhow::Result<Option<T>>;
+}
+
+/// One JSON object per line.
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    fn encode<T: Serialize, W: Write>(&self, value: &T, writer: &mut W) -> anyhow::Result<()> {
+        serde_json::to_writer(&mut *writer, value)?;
+        writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn decode<T: DeserializeOwned, R: BufRead>(&self, reader: &mut R) -> anyhow::Result<Option<T>> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            if !line.trim().is_empty() {
+                return Ok(Some(serde_json::from_str(&line)?));
+            }
+        }
+    }
+}
+
+/// Concatenated CBOR items.
+pub struct CborCodec;
+
+impl Codec for CborCodec {
+    fn encode<T: Serialize, W: Write>(&self, value: &T, writer: &mut W) -> anyhow::Result<()> {
+        ciborium::into_writer(value, writer)?;
+        Ok(())
+    }
+
+    fn decode<T: DeserializeOwned, R: BufRead>(&self, reader: &mut R) -> anyhow::Result<Option<T>> {
+        if reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(ciborium::from_reader(reader)?))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Clone, Copy, Default)]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Cbor,
+    /// A header and one flat row per line, see [`SeedRow`].
+    Csv,
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Clone, Copy)]
+pub enum ExportTable {
+    RgbppLocks,
+    RgbppUnlocks,
+}
+
+/// Write every row of `E` with `codec`, paging through the table so memory stays bounded.
+pub async fn export_table<E, C, W>(
+    db: &DbConn,
+    codec: &C,
+    writer: &mut W,
+    page_size: u64,
+) -> anyhow::Result<u64>
+where
+    E: EntityTrait,
+    E::Model: Serialize + Send + Sync,
+    C: Codec,
+    W: Write,
+{
+    let mut rows = std::pin::pin!(stream_table::<E>(db, page_size));
+    let mut count = 0;
+    while let Some(row) = rows.try_next().await? {
+        codec.encode(&row, writer)?;
+        count += 1;
+    }
+    writer.flush()?;
+
+    Ok(count)
+}
+
+/// Insert `rows` into `E` in statements of up to `batch_size` rows, keeping rows that
+/// already exist. Stops at the first error `rows` yields.
+///
+/// Returns the rows actually inserted, rows skipped as already existing are not counted.
+pub async fn insert_batches<E, I>(db: &DbConn, rows: I, batch_size: usize) -> anyhow::Result<u64>
+where
+    E: EntityTrait,
+    E::Model: IntoActiveModel<E::ActiveModel>,
+    E::ActiveModel: ActiveModelTrait<Entity = E> + Send,
+    I: IntoIterator<Item = anyhow::Result<E::ActiveModel>>,
+{
+    let conflict = OnConflict::columns(E::PrimaryKey::iter().map(|key| key.into_column()))
+        .do_nothing()
+        .to_owned();
+
+    let mut inserted = 0;
+    let mut rows = rows.into_iter();
+    let mut batch = Vec::with_capacity(batch_size);
+    loop {
+        let row = rows.next().transpose()?;
+        let done = row.is_none();
+        batch.extend(row);
+
+        if !batch.is_empty() && (done || batch.len() >= batch_size) {
+            inserted += E::insert_many(std::mem::take(&mut batch))
+                .on_conflict(conflict.clone())
+                .exec_without_returning(db)
+                .await?;
+        }
+        if done {
+            return Ok(inserted);
+        }
+    }
+}
+
+/// Insert the rows `codec` reads from `reader` into `E`, keeping rows that already exist.
+pub async fn import_table<E, C, R>(
+    db: &DbConn,
+    codec: &C,
+    reader: &mut R,
+    batch_size: usize,
+) -> anyhow::Result<u64>
+where
+    E: EntityTrait,
+    E::Model: DeserializeOwned + IntoActiveModel<E::ActiveModel>,
+    E::ActiveModel: ActiveModelTrait<Entity = E> + Send,
+    C: Codec,
+    R: BufRead,
+{
+    let rows = std::iter::from_fn(|| codec.decode::<E::Model, _>(reader).transpose())
+        .map(|row| row.map(IntoActiveModel::into_active_model));
+    insert_batches::<E, _>(db, rows, batch_size).await
+}
+
+async fn transfer<E, C>(db: &DbConn, codec: &C, config: &ExportConfig) -> anyhow::Result<u64>
+where
+    E: EntityTrait,
+    E::Model: Serialize + DeserializeOwned + IntoActiveModel<E::ActiveModel> + Send + Sync,
+    E::ActiveModel: ActiveModelTrait<Entity = E> + Send,
+    C: Codec,
+{
+    let path = &config.path;
+    if config.import {
+        let file = File::open(path).with_context(|| format!("reading {}", path.display()))?;
+        import_table::<E, _, _>(db, codec, &mut BufReader::new(file), IMPORT_BATCH_SIZE).await
+    } else {
+        let file = File::create(path).with_context(|| format!("writing {}", path.display()))?;
+        export_table::<E, _, _>(db, codec, &mut BufWriter::new(file), EXPORT_PAGE_SIZE).await
+    }
+}
+
+async fn transfer_csv<E, R>(db: &DbConn, config: &ExportConfig) -> anyhow::Result<u64>
+where
+    E: EntityTrait,
+    E::Model: IntoActiveModel<E::ActiveModel> + Send + Sync,
+    E::ActiveModel: ActiveModelTrait<Entity = E> + Send,
+    R: SeedRow<Model = E::Model>,
+{
+    let path = &config.path;
+    if config.import {
+        let file = File::open(path).with_context(|| format!("reading {}", path.display()))?;
+        let report =
+            seed::import_csv::<E, R, _>(db, BufReader::new(file), IMPORT_BATCH_SIZE).await?;
+        for error in &report.errors {
+            warn!(
+                "skipped line {} of {}: {}",
+                error.line,
+                path.display(),
+                error.message
+            );
+        }
+        Ok(report.imported)
+    } else {
+        let file = File::create(path).with_context(|| format!("writing {}", path.display()))?;
+        seed::export_csv::<E, R, _>(db, BufWriter::new(file), EXPORT_PAGE_SIZE).await
+    }
+}
+
+/// Export `config.table` to `config.path`, or import it back when `config.import` is set.
+pub async fn run(db: &DbConn, config: &ExportConfig) -> anyhow::Result<()> {
+    let rows = match (config.table, config.format) {
+        (ExportTable::RgbppLocks, ExportFormat::Json) => {
+            transfer::<rgbpp_locks::Entity, _>(db, &JsonCodec, config).await?
+        }
+        (ExportTable::RgbppLocks, ExportFormat::Cbor) => {
+            transfer::<rgbpp_locks::Entity, _>(db, &CborCodec, config).await?
+        }
+        (ExportTable::RgbppUnlocks, ExportFormat::Json) => {
+            transfer::<rgbpp_unlocks::Entity, _>(db, &JsonCodec, config).await?
+        }
+        (ExportTable::RgbppUnlocks, ExportFormat::Cbor) => {
+            transfer::<rgbpp_unlocks::Entity, _>(db, &CborCodec, config).await?
+        }
+        (ExportTable::RgbppLocks, ExportFormat::Csv) => {
+            transfer_csv::<rgbpp_locks::Entity, LockRow>(db, config).await?
+        }
+        (ExportTable::RgbppUnlocks, ExportFormat::Csv) => {
+            transfer_csv::<rgbpp_unlocks::Entity, UnlockRow>(db, config).await?
+        }
+    };
+
+    info!(
+        "{} {rows} {:?} rows {} {}",
+        if config.import {
+            "imported"
+        } else {
+            "exported"
+        },
+        config.table,
+        if config.import { "from" } else { "to" },
+        config.path.display()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use sea_orm::{DatabaseBackend, MockDatabase, MockExecResult};
+
+    use super::*;
+
+    pub(crate) fn unlocks() -> Vec<rgbpp_unlocks::Model> {
+        (0u8..3)
+            .map(|i| rgbpp_unlocks::Model {
+                unlock_id: vec![i; 32],
+                tx: vec![i; 32],
+                version: 0,
+                input_len: 1,
+                output_len: i as i16,
+                btc_tx: vec![0x02, i],
+                btc_tx_proof: Vec::new(),
+                script_version: 1,
+                btc_block_height: Some(840_000 + i as i32),
+                btc_confirmed: (i > 0).then_some(true),
+                btc_tx_proof_json: Some(serde_json::json!({ "tx_index": i })),
+                btc_txid: None,
+                block_number: Some(12_000_000),
+            })
+            .collect()
+    }
+
+    fn round_trip(codec: &impl Codec) {
+        let rows = unlocks();
+
+        let mut buf = Vec::new();
+        for row in &rows {
+            codec.encode(row, &mut buf).unwrap();
+        }
+
+        let mut reader = buf.as_slice();
+        let decoded = std::iter::from_fn(|| {
+            codec
+                .decode::<rgbpp_unlocks::Model, _>(&mut reader)
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+        assert_eq!(decoded, rows);
+    }
+
+    #[test]
+    fn test_json_codec_round_trip() {
+        round_trip(&JsonCodec);
+    }
+
+    #[test]
+    fn test_cbor_codec_round_trip() {
+        round_trip(&CborCodec);
+    }
+
+    #[tokio::test]
+    async fn test_import_counts_inserted_rows() {
+        let mut buf = Vec::new();
+        for row in unlocks() {
+            JsonCodec.encode(&row, &mut buf).unwrap();
+        }
+
+        // Two statements of two and one rows, one row of the first already exists.
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_exec_results([
+                MockExecResult {
+                    last_insert_id: 0,
+                    rows_affected: 1,
+                },
+                MockExecResult {
+                    last_insert_id: 0,
+                    rows_affected: 1,
+                },
+            ])
+            .into_connection();
+        let imported =
+            import_table::<rgbpp_unlocks::Entity, _, _>(&db, &JsonCodec, &mut buf.as_slice(), 2)
+                .await
+                .unwrap();
+
+        assert_eq!(imported, 2);
+        assert_eq!(db.into_transaction_log().len(), 2);
+    }
+}
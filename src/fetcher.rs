@@ -1,8 +1,12 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
 
 use ckb_jsonrpc_types::{
-    BlockNumber, BlockView, CellInput, CellOutput, JsonBytes, OutPoint, Transaction,
-    TransactionWithStatusResponse,
+    BlockNumber, BlockTemplate, BlockView, CellInput, CellOutput, JsonBytes, OutPoint, Script,
+    Transaction, TransactionWithStatusResponse, Uint32,
 };
 use ckb_sdk::rpc::ResponseFormatGetter;
 use ckb_types::H256;
@@ -15,38 +19,53 @@ use jsonrpsee::{
     rpc_params,
 };
 use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use serde::Deserialize;
 use tracing::debug;
 
-use crate::{error::Error, MB};
+use crate::{breaker::CircuitBreaker, error::Error, MB};
+
+/// The part of an indexer `get_cells` page that is read here.
+#[derive(Debug, Deserialize)]
+struct IndexerCells {
+    objects: Vec<IndexerCell>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IndexerCell {
+    out_point: OutPoint,
+}
 
 #[derive(Debug, Clone)]
 pub struct Fetcher<C> {
     client: C,
     retry_interval: u64,
     max_retries: usize,
+    breaker: Option<Arc<CircuitBreaker>>,
 }
 
 macro_rules! retry {
-    ($call:expr, $max_retries:expr, $retry_interval:expr) => {
-        {
-            let mut retries = 0;
-            let max_retries = $max_retries; // adjust this value according to your needs
-            let retry_interval = std::time::Duration::from_millis($retry_interval); // adjust this value according to your needs
-
-            loop {
-                match $call {
-                    Ok(res) => break Ok(res),
-                    Err(err) => {
-                        retries += 1;
-                        if retries > max_retries {
-                            break Err(err);
-                        }
-                        tokio::time::sleep(retry_interval).await;
+    ($fetcher:expr, $call:expr) => {{
+        let mut retries = 0;
+        let max_retries = $fetcher.max_retries;
+        let retry_interval = std::time::Duration::from_millis($fetcher.retry_interval);
+
+        loop {
+            // Every attempt counts towards the breaker, and an open one ends the retries.
+            $fetcher.check_breaker()?;
+            let res = $call.map_err(Error::from);
+            $fetcher.record(&res);
+            match res {
+                Ok(res) => break Ok(res),
+                Err(err) => {
+                    retries += 1;
+                    if retries > max_retries {
+                        break Err(err);
                     }
+                    tokio::time::sleep(retry_interval).await;
                 }
             }
         }
-    };
+    }};
 }
 
 impl<C> Fetcher<C>
@@ -58,6 +77,30 @@ where
             client,
             retry_interval,
             max_retries,
+            breaker: None,
+        }
+    }
+
+    /// Fail fast with [`Error::CircuitOpen`] while `breaker` is open. Clones share it.
+    pub fn with_circuit_breaker(mut self, breaker: Option<Arc<CircuitBreaker>>) -> Self {
+        self.breaker = breaker;
+        self
+    }
+
+    pub fn breaker(&self) -> Option<&CircuitBreaker> {
+        self.breaker.as_deref()
+    }
+
+    fn check_breaker(&self) -> Result<(), Error> {
+        match &self.breaker {
+            Some(breaker) if !breaker.allow() => Err(Error::CircuitOpen),
+            _ => Ok(()),
+        }
+    }
+
+    fn record<T>(&self, result: &Result<T, Error>) {
+        if let Some(breaker) = &self.breaker {
+            breaker.record(result.is_ok());
         }
     }
 
@@ -67,15 +110,12 @@ where
         Params: jsonrpsee::core::traits::ToRpcParams + Send + Clone,
         R: jsonrpsee::core::DeserializeOwned,
     {
-        let res = retry!(
+        retry!(
+            self,
             self.client
                 .request::<R, Params>(method, params.clone())
-                .await,
-            self.max_retries,
-            self.retry_interval
-        )?;
-
-        Ok(res)
+                .await
+        )
     }
 
     #[inline]
@@ -98,11 +138,8 @@ where
             handle_item(item, &mut batch_request)?;
         }
 
-        let results: BatchResponse<'_, R> = retry!(
-            self.client.batch_request(batch_request.clone()).await,
-            self.max_retries,
-            self.retry_interval
-        )?;
+        let results: BatchResponse<'_, R> =
+            retry!(self, self.client.batch_request(batch_request.clone()).await)?;
 
         let results = results
             .into_iter()
@@ -122,13 +159,20 @@ impl Fetcher<HttpClient> {
     pub fn from_config(config: &crate::config::UnistateConfig) -> Result<Self, Error> {
         let fetcher = &config.featcher;
         let url = &config.url;
-        Self::http_client(
+        let breaker = fetcher.breaker_threshold.map(|threshold| {
+            Arc::new(CircuitBreaker::new(
+                threshold,
+                Duration::from_millis(fetcher.breaker_cooldown),
+            ))
+        });
+        Ok(Self::http_client(
             url,
             fetcher.retry_interval,
             fetcher.max_retries,
             fetcher.max_response_size * MB,
             fetcher.max_request_size * MB,
-        )
+        )?
+        .with_circuit_breaker(breaker))
     }
     pub fn http_client(
         url: impl AsRef<str>,
@@ -157,6 +201,11 @@ where
         self.call("get_tip_block_number", rpc_params!()).await
     }
 
+    /// The template the node would mine next, whose transactions are not confirmed yet.
+    pub async fn get_block_template(&self) -> Result<BlockTemplate, Error> {
+        self.call("get_block_template", rpc_params!()).await
+    }
+
     // pub async fn get_block_by_number(
     //     &self,
     //     number: BlockNumber,
@@ -212,10 +261,44 @@ where
         Ok(txs)
     }
 
-    pub async fn get_outputs(&self, inputs: Vec<CellInput>) -> Result<Vec<CellOutput>, Error> {
+    /// The newest live cell whose type script is exactly `type_script`, from the
+    /// node's built-in indexer.
+    pub async fn get_live_cell_by_type(
+        &self,
+        type_script: &Script,
+    ) -> Result<Option<OutPoint>, Error> {
+        let search_key = serde_json::json!({
+            "script": type_script,
+            "script_type": "type",
+            "script_search_mode": "exact",
+        });
+        let cells: IndexerCells = self
+            .call(
+                "get_cells",
+                rpc_params!(search_key, "desc", Uint32::from(1)),
+            )
+            .await?;
+
+        Ok(cells.objects.into_iter().next().map(|cell| cell.out_point))
+    }
+
+    /// Resolve the cells consumed by `inputs`.
+    ///
+    /// The outputs are read from `get_transaction`, which always returns `outputs_data`
+    /// alongside the outputs. `get_live_cell` can skip the data, but it only serves live
+    /// cells and inputs of committed transactions are already dead, so there is no
+    /// data-less request to switch to here.
+    ///
+    /// The result is aligned with `inputs`, holding `None` for the cellbase input and
+    /// for cells that could not be found.
+    pub async fn get_outputs(
+        &self,
+        inputs: Vec<CellInput>,
+    ) -> Result<Vec<Option<CellOutput>>, Error> {
         debug!("Getting outputs for inputs: {:?}", inputs);
         let hashs = inputs
             .par_iter()
+            .filter(|input| !is_cellbase_input(input))
             .map(|input| input.previous_output.tx_hash.clone())
             .collect::<HashSet<_>>()
             .into_par_iter()
@@ -224,21 +307,7 @@ where
         debug!("Getting transactions by hashes: {:?}", hashs);
         let txs = self.get_txs_by_hashes(hashs).await?;
 
-        let res = inputs
-            .into_par_iter()
-            .map(|input| {
-                let idx = input.previous_output.index.value();
-                let tx = txs.get(&input.previous_output.tx_hash);
-                let output = tx
-                    .and_then(|tx| tx.outputs.get(idx as usize).cloned())
-                    .ok_or_else(|| Error::PreviousOutputNotFound {
-                        tx_hash: input.previous_output.tx_hash.clone(),
-                        index: idx,
-                    });
-                debug!("Got output for input: {:?} -> {:?}", input, output);
-                output
-            })
-            .collect::<Result<Vec<_>, _>>()?;
+        let res = align_outputs(inputs, &txs);
 
         debug!("Got outputs: {:?}", res);
         Ok(res)
@@ -292,6 +361,32 @@ where
     }
 }
 
+/// The cellbase spends the null out point, which has no cell behind it.
+fn is_cellbase_input(input: &CellInput) -> bool {
+    input.previous_output.tx_hash == H256::default()
+        && input.previous_output.index.value() == u32::MAX
+}
+
+fn align_outputs(
+    inputs: Vec<CellInput>,
+    txs: &HashMap<H256, Transaction>,
+) -> Vec<Option<CellOutput>> {
+    inputs
+        .into_par_iter()
+        .map(|input| {
+            if is_cellbase_input(&input) {
+                return None;
+            }
+            let idx = input.previous_output.index.value();
+            let output = txs
+                .get(&input.previous_output.tx_hash)
+                .and_then(|tx| tx.outputs.get(idx as usize).cloned());
+            debug!("Got output for input: {:?} -> {:?}", input, output);
+            output
+        })
+        .collect()
+}
+
 #[tokio::test]
 async fn test_get_tip_block_number() {
     let fetcher =
@@ -317,3 +412,128 @@ async fn test_get_blocks() {
         .await;
     println!("{res:?}");
 }
+
+#[cfg(test)]
+mod tests {
+    use ckb_types::{packed, prelude::*};
+
+    use super::*;
+    use crate::breaker::BreakerState;
+
+    #[test]
+    fn test_align_outputs_cellbase() {
+        let mut tx: ckb_jsonrpc_types::TransactionView =
+            packed::Transaction::default().into_view().into();
+        tx.inner.outputs.push(CellOutput {
+            capacity: 100.into(),
+            lock: Default::default(),
+            type_: None,
+        });
+        let input = |tx_hash: H256, index: u32| CellInput {
+            previous_output: OutPoint {
+                tx_hash,
+                index: index.into(),
+            },
+            since: 0.into(),
+        };
+        let txs = HashMap::from([(H256([1u8; 32]), tx.inner)]);
+
+        let outputs = align_outputs(
+            vec![
+                input(H256::default(), u32::MAX),
+                input(H256([1u8; 32]), 0),
+                input(H256([1u8; 32]), 1),
+                input(H256([2u8; 32]), 0),
+            ],
+            &txs,
+        );
+
+        // One slot per input: the cellbase and cells the node did not return are `None`.
+        assert_eq!(outputs.len(), 4);
+        assert!(outputs[0].is_none());
+        assert_eq!(outputs[1].as_ref().unwrap().capacity.value(), 100);
+        assert!(outputs[2].is_none());
+        assert!(outputs[3].is_none());
+    }
+
+    /// A node that times out on every request.
+    #[derive(Default)]
+    struct UnreachableNode {
+        requests: std::sync::atomic::AtomicUsize,
+    }
+
+    #[jsonrpsee::core::async_trait]
+    impl ClientT for UnreachableNode {
+        async fn notification<Params>(
+            &self,
+            _method: &str,
+            _params: Params,
+        ) -> Result<(), jsonrpsee::core::client::Error>
+        where
+            Params: jsonrpsee::core::traits::ToRpcParams + Send,
+        {
+            self.requests
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Err(jsonrpsee::core::client::Error::RequestTimeout)
+        }
+
+        async fn request<R, Params>(
+            &self,
+            _method: &str,
+            _params: Params,
+        ) -> Result<R, jsonrpsee::core::client::Error>
+        where
+            R: jsonrpsee::core::DeserializeOwned,
+            Params: jsonrpsee::core::traits::ToRpcParams + Send,
+        {
+            self.requests
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Err(jsonrpsee::core::client::Error::RequestTimeout)
+        }
+
+        async fn batch_request<'a, R>(
+            &self,
+            _batch: BatchRequestBuilder<'a>,
+        ) -> Result<BatchResponse<'a, R>, jsonrpsee::core::client::Error>
+        where
+            R: jsonrpsee::core::DeserializeOwned + std::fmt::Debug + 'a,
+        {
+            self.requests
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Err(jsonrpsee::core::client::Error::RequestTimeout)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_opens_within_retries() {
+        let requests = |fetcher: &Fetcher<UnreachableNode>| {
+            fetcher
+                .client
+                .requests
+                .load(std::sync::atomic::Ordering::SeqCst)
+        };
+        let fetcher = Fetcher::new(UnreachableNode::default(), 0, 5).with_circuit_breaker(Some(
+            Arc::new(CircuitBreaker::new(3, Duration::from_secs(60))),
+        ));
+
+        // The third failed attempt opens the breaker, which then stops the retries early.
+        let res = fetcher.get_tip_block_number().await;
+        assert!(matches!(res, Err(Error::CircuitOpen)), "{res:?}");
+        assert_eq!(requests(&fetcher), 3);
+        assert_eq!(fetcher.breaker().unwrap().state(), BreakerState::Open);
+
+        // While open, requests fail fast without reaching the node.
+        let res = fetcher
+            .get_blocks(vec![1.into(), 2.into()])
+            .await
+            .map(|blocks| blocks.len());
+        assert!(matches!(res, Err(Error::CircuitOpen)), "{res:?}");
+        assert_eq!(requests(&fetcher), 3);
+
+        // Without a breaker every retry reaches the node.
+        let fetcher = Fetcher::new(UnreachableNode::default(), 0, 5);
+        let res = fetcher.get_tip_block_number().await;
+        assert!(matches!(res, Err(Error::JsonRpcClientError(_))), "{res:?}");
+        assert_eq!(requests(&fetcher), 6);
+    }
+}
@@ -0,0 +1,351 @@
+use std::{collections::BTreeSet, fs, path::Path};
+
+use anyhow::Context as _;
+use ckb_jsonrpc_types::{CellOutput, TransactionView};
+use sea_orm::{ActiveValue, TryIntoModel as _};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::info;
+
+use crate::{
+    config::GoldenConfig,
+    database::Operations,
+    rgbpp::{parse_rgbpp_tx, script_version, RgbppContext, RgbppUnlockJson},
+};
+
+/// A transaction captured together with the outputs its inputs spend, so it can be
+/// parsed again without a node.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CapturedTx {
+    pub tx: TransactionView,
+    /// Aligned with the inputs, `null` for the cellbase input.
+    pub pre_outputs: Vec<Option<CellOutput>>,
+    #[serde(default)]
+    pub block_number: Option<u64>,
+}
+
+/// A lock or unlock as decoded by the parser, hex-encoded for stable diffs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DecodedRecord {
+    Lock {
+        lock_id: String,
+        tx: String,
+        out_index: i32,
+        btc_txid: String,
+        script_version: i16,
+        output_index: i32,
+        capacity: Option<i64>,
+        spent_tx: Option<String>,
+        owner_lock_hash: Option<String>,
+    },
+    Unlock {
+        unlock_id: String,
+        tx: String,
+        script_version: i16,
+        #[serde(flatten)]
+        unlock: RgbppUnlockJson,
+    },
+}
+
+impl DecodedRecord {
+    fn from_operation(op: Operations) -> anyhow::Result<Option<Self>> {
+        let record = match op {
+            Operations::UpsertLock(lock) | Operations::SpendLock(lock) => Some(Self::Lock {
+                lock_id: hex::encode(set(&lock.lock_id)?),
+                tx: hex::encode(set(&lock.tx)?),
+                out_index: set(&lock.out_index)?,
+                btc_txid: hex::encode(set(&lock.btc_txid)?),
+                script_version: set(&lock.script_version)?,
+                output_index: set(&lock.output_index)?,
+                capacity: set(&lock.capacity)?,
+                spent_tx: set(&lock.spent_tx)?.map(hex::encode),
+                owner_lock_hash: set(&lock.owner_lock_hash)?.map(hex::encode),
+            }),
+            Operations::UpsertUnlock(unlock) => {
+                let unlock = unlock.try_into_model()?;
+                Some(Self::Unlock {
+                    unlock_id: hex::encode(&unlock.unlock_id),
+                    tx: hex::encode(&unlock.tx),
+                    script_version: unlock.script_version,
+                    unlock: RgbppUnlockJson::try_from(&unlock)
+                        .map_err(|e| anyhow::anyhow!("malformed unlock: {e}"))?,
+                })
+            }
+            _ => None,
+        };
+
+        Ok(record)
+    }
+
+    fn sort_key(&self) -> (&str, &str, Option<&str>) {
+        match self {
+            Self::Lock {
+                tx,
+                lock_id,
+                spent_tx,
+                ..
+            } => (tx, lock_id, spent_tx.as_deref()),
+            Self::Unlock { tx, unlock_id, .. } => (tx, unlock_id, None),
+        }
+    }
+}
+
+fn set<T>(value: &ActiveValue<T>) -> anyhow::Result<T>
+where
+    T: Into<sea_orm::Value> + Clone,
+{
+    value
+        .try_as_ref()
+        .cloned()
+        .context("parser left a decoded field unset")
+}
+
+/// Run the parse path over `captured`, returning what it would write in a stable order.
+pub fn decode_captured(
+    captured: &[CapturedTx],
+    ctx: &RgbppContext,
+) -> anyhow::Result<Vec<DecodedRecord>> {
+    let (op_sender, mut op_recv) = mpsc::unbounded_channel();
+    for CapturedTx {
+        tx,
+        pre_outputs,
+        block_number,
+    } in captured
+    {
+        parse_rgbpp_tx(tx, pre_outputs, *block_number, ctx, &op_sender)?;
+    }
+    drop(op_sender);
+
+    let mut records = Vec::new();
+    while let Ok(op) = op_recv.try_recv() {
+        records.extend(DecodedRecord::from_operation(op)?);
+    }
+    records.sort_by(|a, b| a.sort_key().cmp(&b.sort_key()));
+
+    Ok(records)
+}
+
+pub fn write_golden(path: &Path, records: &[DecodedRecord]) -> anyhow::Result<()> {
+    fs::write(path, serde_json::to_string_pretty(records)?)
+        .with_context(|| format!("writing {}", path.display()))
+}
+
+/// Fails listing the first record that differs from the golden file at `path`.
+pub fn compare_golden(path: &Path, records: &[DecodedRecord]) -> anyhow::Result<()> {
+    let raw = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+    let golden: Vec<DecodedRecord> = serde_json::from_slice(&raw)?;
+
+    if let Some((index, (expected, found))) = golden
+        .iter()
+        .zip(records)
+        .enumerate()
+        .find(|(_, (expected, found))| expected != found)
+    {
+        anyhow::bail!("record {index} differs from golden: expected {expected:?}, found {found:?}");
+    }
+    if golden.len() != records.len() {
+        anyhow::bail!(
+            "decoded {} records, golden file has {}",
+            records.len(),
+            golden.len()
+        );
+    }
+
+    Ok(())
+}
+
+/// Write the lock args of the RGBPP cells in `captured` and the witnesses unlocking
+/// them to `dir`, one file per distinct seed named by its hash. Returns the seed count.
+pub fn write_fuzz_seeds(
+    captured: &[CapturedTx],
+    ctx: &RgbppContext,
+    dir: &Path,
+) -> anyhow::Result<usize> {
+    let is_rgbpp = |output: &CellOutput| script_version(&ctx.scripts, &output.lock).is_some();
+
+    let mut seeds = BTreeSet::new();
+    for CapturedTx {
+        tx, pre_outputs, ..
+    } in captured
+    {
+        seeds.extend(
+            tx.inner
+                .outputs
+                .iter()
+                .chain(pre_outputs.iter().flatten())
+                .filter(|output| is_rgbpp(output))
+                .map(|output| output.lock.args.as_bytes().to_vec()),
+        );
+        seeds.extend(
+            tx.inner
+                .witnesses
+                .iter()
+                .zip(pre_outputs)
+                .filter(|(witness, pre_output)| {
+                    !witness.is_empty() && pre_output.as_ref().is_some_and(is_rgbpp)
+                })
+                .map(|(witness, _)| witness.as_bytes().to_vec()),
+        );
+    }
+
+    fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    for seed in &seeds {
+        let path = dir.join(hex::encode(ckb_hash::blake2b_256(seed)));
+        fs::write(&path, seed).with_context(|| format!("writing {}", path.display()))?;
+    }
+
+    Ok(seeds.len())
+}
+
+/// Decode the captured transactions, then write or compare against the golden file.
+pub fn run(config: &GoldenConfig, ctx: &RgbppContext) -> anyhow::Result<()> {
+    let raw = fs::read(&config.captured)
+        .with_context(|| format!("reading {}", config.captured.display()))?;
+    let captured: Vec<CapturedTx> = serde_json::from_slice(&raw)?;
+
+    let records = decode_captured(&captured, ctx)?;
+    if let Some(dir) = &config.fuzz_corpus {
+        let seeds = write_fuzz_seeds(&captured, ctx, dir)?;
+        info!("wrote {seeds} fuzz seeds to {}", dir.display());
+    }
+
+    if config.compare {
+        compare_golden(&config.golden, &records)?;
+        info!(
+            "{} records match {}",
+            records.len(),
+            config.golden.display()
+        );
+    } else {
+        write_golden(&config.golden, &records)?;
+        info!(
+            "wrote {} records to {}",
+            records.len(),
+            config.golden.display()
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ckb_jsonrpc_types::{JsonBytes, Script, ScriptHashType};
+    use ckb_types::{packed, prelude::*, H256};
+    use molecule::prelude::{Builder as _, Entity as _};
+
+    use super::*;
+    use crate::{
+        config::RgbppScriptConfig,
+        schemas::{blockchain, rgbpp},
+    };
+
+    fn captured_tx(code_hash: H256) -> CapturedTx {
+        let lock = rgbpp::RGBPPLock::new_builder()
+            .out_index(blockchain::Uint32::new_unchecked(
+                1u32.to_le_bytes().to_vec().into(),
+            ))
+            .btc_txid(blockchain::Byte32::new_unchecked(vec![7u8; 32].into()))
+            .build();
+
+        let mut tx: TransactionView = packed::Transaction::default().into_view().into();
+        tx.inner.outputs.push(CellOutput {
+            capacity: 100.into(),
+            lock: Script {
+                code_hash,
+                hash_type: ScriptHashType::Type,
+                args: JsonBytes::from_bytes(lock.as_bytes()),
+            },
+            type_: None,
+        });
+
+        CapturedTx {
+            tx,
+            pre_outputs: Vec::new(),
+            block_number: None,
+        }
+    }
+
+    #[test]
+    fn test_golden_round_trip() {
+        let code_hash = H256([1u8; 32]);
+        let ctx = RgbppContext::new(vec![RgbppScriptConfig {
+            version: 1,
+            code_hash: code_hash.clone(),
+            hash_type: ScriptHashType::Type,
+            cell_dep: None,
+        }]);
+        let captured = vec![captured_tx(code_hash)];
+
+        let records = decode_captured(&captured, &ctx).unwrap();
+        assert_eq!(records.len(), 1);
+        assert!(matches!(
+            &records[0],
+            DecodedRecord::Lock {
+                out_index: 1,
+                capacity: Some(100),
+                spent_tx: None,
+                ..
+            }
+        ));
+
+        let path =
+            std::env::temp_dir().join(format!("unistate-golden-{}.json", std::process::id()));
+        write_golden(&path, &records).unwrap();
+        compare_golden(&path, &records).unwrap();
+
+        let mut changed = records.clone();
+        if let DecodedRecord::Lock { out_index, .. } = &mut changed[0] {
+            *out_index = 2;
+        }
+        assert!(compare_golden(&path, &changed).is_err());
+        assert!(compare_golden(&path, &[]).is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_write_fuzz_seeds() {
+        let code_hash = H256([1u8; 32]);
+        let ctx = RgbppContext::new(vec![RgbppScriptConfig {
+            version: 1,
+            code_hash: code_hash.clone(),
+            hash_type: ScriptHashType::Type,
+            cell_dep: None,
+        }]);
+        let mut other = captured_tx(H256([2u8; 32]));
+        other
+            .tx
+            .inner
+            .witnesses
+            .push(JsonBytes::from_vec(vec![1, 2, 3]));
+        other.pre_outputs.push(Some(
+            captured_tx(code_hash.clone()).tx.inner.outputs[0].clone(),
+        ));
+        // The same lock args in two transactions are a single seed.
+        let captured = vec![
+            captured_tx(code_hash.clone()),
+            captured_tx(code_hash),
+            other,
+        ];
+
+        let dir = std::env::temp_dir().join(format!("unistate-fuzz-{}", std::process::id()));
+        let seeds = write_fuzz_seeds(&captured, &ctx, &dir).unwrap();
+
+        let mut written = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| fs::read(entry.unwrap().path()).unwrap())
+            .collect::<Vec<_>>();
+        written.sort();
+        let args = captured[0].tx.inner.outputs[0]
+            .lock
+            .args
+            .as_bytes()
+            .to_vec();
+        assert_eq!(seeds, 2);
+        assert_eq!(written, vec![args, vec![1, 2, 3]]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
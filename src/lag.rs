@@ -0,0 +1,177 @@
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use futures::future::BoxFuture;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+/// Called with the current lag once it stayed above the threshold for long enough.
+pub type LagAlert = Box<dyn Fn(u64) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// Raises an alert when `tip - indexed` stays above `threshold` for at least `sustain`.
+///
+/// The alert fires once per episode: it is armed again only after the lag recovered.
+pub struct LagMonitor {
+    threshold: u64,
+    sustain: Duration,
+    alert: LagAlert,
+    tip: AtomicU64,
+    indexed: AtomicU64,
+    /// Alerts raised so far.
+    pub alerts: AtomicU64,
+    lagging_since: Mutex<Option<Instant>>,
+    fired: AtomicBool,
+}
+
+impl LagMonitor {
+    pub fn new(threshold: u64, sustain: Duration, alert: LagAlert) -> Self {
+        Self {
+            threshold,
+            sustain,
+            alert,
+            tip: Default::default(),
+            indexed: Default::default(),
+            alerts: Default::default(),
+            lagging_since: Mutex::new(None),
+            fired: AtomicBool::new(false),
+        }
+    }
+
+    pub fn set_tip(&self, tip: u64) {
+        self.tip.store(tip, Ordering::Relaxed);
+    }
+
+    /// Record the height of the latest committed batch.
+    pub fn set_indexed(&self, indexed: u64) {
+        self.indexed.store(indexed, Ordering::Relaxed);
+    }
+
+    pub fn lag(&self) -> u64 {
+        self.tip
+            .load(Ordering::Relaxed)
+            .saturating_sub(self.indexed.load(Ordering::Relaxed))
+    }
+
+    pub async fn check(&self) {
+        self.check_at(Instant::now()).await;
+    }
+
+    async fn check_at(&self, now: Instant) {
+        let lag = self.lag();
+        let since = {
+            let mut lagging_since = self.lagging_since.lock().unwrap_or_else(|e| e.into_inner());
+            if lag <= self.threshold {
+                *lagging_since = None;
+                self.fired.store(false, Ordering::Relaxed);
+                return;
+            }
+            *lagging_since.get_or_insert(now)
+        };
+
+        if now.duration_since(since) >= self.sustain && !self.fired.swap(true, Ordering::Relaxed) {
+            self.alerts.fetch_add(1, Ordering::Relaxed);
+            (self.alert)(lag).await;
+        }
+    }
+
+    /// Refresh the tip from `tip` and check the lag every `period` in the background.
+    ///
+    /// The tip is polled here rather than fed by the indexing loop, so the lag keeps
+    /// growing while that loop is stalled.
+    pub fn spawn<F, Fut>(self: Arc<Self>, period: Duration, tip: F) -> JoinHandle<()>
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<u64>> + Send,
+    {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                match tip().await {
+                    Ok(tip) => self.set_tip(tip),
+                    Err(e) => warn!("Failed to refresh the tip for the lag monitor: {e}"),
+                }
+                self.check().await;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_lag_alert_fires_once() {
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        let alert: LagAlert = {
+            let fired = fired.clone();
+            Box::new(move |lag| {
+                let fired = fired.clone();
+                Box::pin(async move { fired.lock().unwrap().push(lag) })
+            })
+        };
+        let sustain = Duration::from_secs(60);
+        let monitor = LagMonitor::new(100, sustain, alert);
+        let start = Instant::now();
+
+        monitor.set_tip(1_000);
+        monitor.set_indexed(500);
+        monitor.check_at(start).await;
+        monitor.check_at(start + sustain / 2).await;
+        assert!(fired.lock().unwrap().is_empty());
+
+        // Sustained lag alerts once, however often it is checked.
+        monitor.check_at(start + sustain).await;
+        monitor.set_indexed(600);
+        monitor.check_at(start + sustain * 2).await;
+        monitor.check_at(start + sustain * 3).await;
+        assert_eq!(*fired.lock().unwrap(), vec![500]);
+        assert_eq!(monitor.alerts.load(Ordering::Relaxed), 1);
+
+        // Recovering rearms the alert, and a new episode waits out `sustain` again.
+        monitor.set_indexed(950);
+        monitor.check_at(start + sustain * 4).await;
+        monitor.set_tip(2_000);
+        monitor.check_at(start + sustain * 5).await;
+        assert_eq!(monitor.alerts.load(Ordering::Relaxed), 1);
+        monitor.check_at(start + sustain * 6).await;
+        assert_eq!(*fired.lock().unwrap(), vec![500, 1_050]);
+        assert_eq!(monitor.alerts.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_lag_monitor_polls_tip() {
+        let alert: LagAlert = Box::new(|_| Box::pin(async {}));
+        let monitor = Arc::new(LagMonitor::new(100, Duration::ZERO, alert));
+        monitor.set_indexed(500);
+
+        // Nothing but the monitor itself moves the tip, as with a stalled indexer.
+        let tips = Arc::new(AtomicU64::new(500));
+        let handle = monitor.clone().spawn(Duration::from_millis(1), {
+            let tips = tips.clone();
+            move || {
+                let tip = tips.fetch_add(50, Ordering::Relaxed);
+                async move { Ok(tip) }
+            }
+        });
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while monitor.alerts.load(Ordering::Relaxed) == 0 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .unwrap();
+        handle.abort();
+
+        assert!(monitor.lag() > 100);
+        assert_eq!(monitor.alerts.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,29 @@
+pub mod accumulator;
+pub mod backfill;
+pub mod backoff;
+pub mod breaker;
+pub mod changes;
+pub mod config;
+pub mod constants;
+pub mod database;
+pub mod entity;
+pub mod error;
+pub mod events;
+pub mod export;
+pub mod fetcher;
+pub mod golden;
+pub mod lag;
+pub mod pending;
+pub mod queries;
+pub mod reprocess;
+pub mod rgbpp;
+pub mod schema_check;
+pub mod schemas;
+pub mod seed;
+pub mod sink;
+pub mod spore;
+pub mod type_id;
+pub mod unique;
+pub mod xudt;
+
+pub const MB: u32 = 1048576;
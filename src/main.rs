@@ -1,9 +1,8 @@
 use core::time::Duration;
+use std::sync::Arc;
 
-use ckb_jsonrpc_types::{BlockNumber, TransactionView};
+use ckb_jsonrpc_types::BlockNumber;
 
-use config::Config;
-use database::DatabaseProcessor;
 use figment::{
     providers::{Format as _, Toml},
     Figment,
@@ -11,36 +10,38 @@ use figment::{
 use rayon::iter::{
     IntoParallelIterator, IntoParallelRefIterator, ParallelExtend, ParallelIterator,
 };
-use sea_orm::{ConnectOptions, Database, EntityTrait};
+use sea_orm::{ConnectOptions, Database, TransactionTrait as _};
 
-use spore::SporeTx;
 use tokio::task::JoinSet;
-use tracing::info;
+use tracing::{info, warn};
 use tracing_subscriber::{
-    filter::FilterFn, layer::SubscriberExt as _, util::SubscriberInitExt as _, Layer as _,
+    filter::FilterFn, fmt::writer::BoxMakeWriter, layer::SubscriberExt as _,
+    util::SubscriberInitExt as _, Layer as _,
+};
+use unistate_ckb::{
+    backfill, backoff, changes,
+    config::Config,
+    constants,
+    database::{self, DatabaseProcessor},
+    events, export, fetcher, golden, lag, pending, queries, reprocess,
+    rgbpp::{self, RgbppTx},
+    schema_check, sink,
+    spore::{self, SporeTx},
+    type_id,
+    xudt::{self, XudtTx},
 };
-use xudt::XudtTx;
-
-use entity::block_height;
-
-mod config;
-mod constants;
-mod database;
-mod entity;
-mod error;
-mod fetcher;
-mod rgbpp;
-mod schemas;
-mod spore;
-mod unique;
-mod xudt;
 
-const MB: u32 = 1048576;
+const IDLE_BACKOFF_INITIAL: Duration = Duration::from_millis(500);
+const IDLE_BACKOFF_MAX: Duration = Duration::from_secs(5);
+const EVENT_LOG_CAPACITY: usize = 4096;
+const INSERT_BATCH_INITIAL: usize = 1000;
+const INSERT_BATCH_MIN: usize = 50;
+const LAG_CHECK_INTERVAL: Duration = Duration::from_secs(10);
 
 struct CategorizedTxs {
     spore_txs: Vec<SporeTx>,
     xudt_txs: Vec<XudtTx>,
-    rgbpp_txs: Vec<TransactionView>,
+    rgbpp_txs: Vec<RgbppTx>,
 }
 
 impl CategorizedTxs {
@@ -69,13 +70,22 @@ async fn main() -> anyhow::Result<()> {
         .merge(figment::providers::Env::raw().only(&["DATABASE_URL"]))
         .extract()?;
 
+    config.validate()?;
+
     let config_level: tracing::Level = config.unistate.optional_config.level.into();
 
     let filter = FilterFn::new(move |metadata| metadata.level() <= &config_level);
 
+    // Keep stdout for the NDJSON rows when they are streamed there.
+    let writer = if config.unistate.optional_config.stdout_sink {
+        BoxMakeWriter::new(std::io::stderr)
+    } else {
+        BoxMakeWriter::new(std::io::stdout)
+    };
     let layer = tracing_subscriber::fmt::layer()
         .without_time()
-        .with_level(true);
+        .with_level(true)
+        .with_writer(writer);
 
     tracing_subscriber::registry()
         .with(layer.with_filter(filter))
@@ -83,29 +93,235 @@ async fn main() -> anyhow::Result<()> {
 
     info!("config: {config:#?}");
 
-    let opt = ConnectOptions::new(&config.database_url);
-    let db = Database::connect(opt).await?;
+    if let Some(golden) = &config.unistate.optional_config.golden {
+        let constants = constants::Constants::from_config(config.unistate.optional_config.network);
+        let ctx = rgbpp::RgbppContext::new(config.unistate.rgbpp_scripts(constants))
+            .with_constants(Some(constants));
+        return golden::run(golden, &ctx);
+    }
+
+    let max_connections = config.unistate.optional_config.max_connections;
+    let mut opt = ConnectOptions::new(&config.database_url);
+    opt.max_connections(max_connections);
+    let db = Arc::new(Database::connect(opt).await?);
+
+    let schema = schema_check::verify_schema(&db).await?;
+    if !schema.is_clean() {
+        anyhow::bail!("database schema does not match the migrations, run them first: {schema:?}");
+    }
+
+    if let Some(export) = &config.unistate.optional_config.export {
+        return export::run(&db, export).await;
+    }
 
     let client = fetcher::Fetcher::from_config(&config.unistate)?;
 
-    let block_height_value = block_height::Entity::find().one(&db).await?.unwrap().height as u64;
     let network = config.unistate.optional_config.network;
     let constants = constants::Constants::from_config(network);
+    let mut rgbpp_scripts = config.unistate.rgbpp_scripts(constants);
+
+    let type_ids = type_id::TypeIdResolver::default();
+    for script in &config.unistate.rgbpp_type_ids {
+        let client = &client;
+        match type_ids
+            .resolve(&script.type_id, |type_script| async move {
+                client.get_live_cell_by_type(&type_script).await
+            })
+            .await?
+        {
+            Some(out_point) => {
+                info!(
+                    "RGBPP script version {} is deployed at {:#x}:{}",
+                    script.version,
+                    out_point.tx_hash,
+                    out_point.index.value()
+                );
+                // Transactions spending its locks reference the code cell as a dep.
+                for rgbpp_script in &mut rgbpp_scripts {
+                    if rgbpp_script.version == script.version {
+                        rgbpp_script.cell_dep = Some(out_point.clone());
+                    }
+                }
+            }
+            None => warn!(
+                "No live code cell for RGBPP script version {} with type id {:#x}",
+                script.version, script.type_id
+            ),
+        }
+    }
 
-    let mut height = config
+    let mut sinks: Vec<Arc<dyn sink::Sink>> = Vec::new();
+    if let Some(path) = &config.unistate.optional_config.influx_line_protocol {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        sinks.push(Arc::new(sink::LineProtocolSink::new(file)));
+    }
+    if config.unistate.optional_config.stdout_sink {
+        sinks.push(Arc::new(sink::JsonLinesSink::stdout()));
+    }
+    let dedup_window = config.unistate.optional_config.sink_dedup_window;
+    if dedup_window > 0 {
+        sinks = sinks
+            .into_iter()
+            .map(|inner| Arc::new(sink::DedupSink::new(inner, dedup_window)) as Arc<dyn sink::Sink>)
+            .collect();
+    }
+    let rgbpp_ctx = Arc::new(
+        rgbpp::RgbppContext::new(rgbpp_scripts.clone())
+            .with_constants(Some(constants))
+            .with_tx_metrics(
+                config
+                    .unistate
+                    .optional_config
+                    .influx_line_protocol
+                    .is_some(),
+            )
+            .with_cell_deps(config.unistate.optional_config.index_cell_deps)
+            .with_type_scripts(config.unistate.optional_config.index_type_scripts)
+            .with_btc_tx_proof_json(config.unistate.optional_config.btc_tx_proof_json)
+            .with_btc_timelocks(config.unistate.optional_config.btc_timelocks)
+            .with_raw_args(config.unistate.optional_config.store_raw_args)
+            .with_burn_locks(config.unistate.optional_config.burn_locks.clone())
+            .with_btc_confirmations(config.unistate.optional_config.btc_tip.map(|tip| {
+                rgbpp::BtcConfirmations {
+                    tip,
+                    required: config.unistate.optional_config.btc_confirmations,
+                }
+            })),
+    );
+
+    let pending_ctx = config
         .unistate
         .optional_config
-        .initial_height
-        .max(block_height_value);
+        .index_pending
+        .then(|| rgbpp::RgbppContext::new(rgbpp_scripts).with_constants(Some(constants)));
+
+    let mut height = backfill::resume_height(
+        &*db,
+        config.unistate.optional_config.initial_height,
+        config.unistate.optional_config.finality_depth,
+    )
+    .await?;
+    let resume = height;
+    height = backfill::start_height(
+        resume,
+        config.unistate.optional_config.start_block,
+        config.unistate.optional_config.confirm_reindex,
+    )?;
+    if height > resume {
+        info!("Seeking forward from {resume} to start block {height}");
+    } else if height < resume {
+        warn!("Reindexing from start block {height}, below the resume height {resume}");
+    }
+    info!("Resuming at {height}");
+    match queries::summary_stats(&db).await {
+        Ok(stats) => info!(
+            "Indexed so far: {} locks ({} live), {} unlocks, {} BTC txids",
+            stats.total_locks, stats.live_locks, stats.total_unlocks, stats.distinct_btc_txids
+        ),
+        Err(e) => warn!("Failed to load the summary stats: {e}"),
+    }
 
     let initial_target = client.get_tip_block_number().await?.value();
     let max_batch_size = config.unistate.optional_config.batch_size;
     let interval = config.unistate.optional_config.interval;
-    let mut batch_size = (initial_target - height).min(max_batch_size);
-    let mut target_height = initial_target;
+    let (mut backfill, backfill_done) =
+        backfill::Backfill::new(config.unistate.optional_config.backfill_end, initial_target);
+    backfill.check_range(
+        height,
+        config.unistate.optional_config.max_backfill_range,
+        config.unistate.optional_config.allow_long_backfill,
+    )?;
+    let mut target_height = backfill.target(initial_target);
+    let mut batch_size = backfill::next_batch_size(target_height, height, max_batch_size);
+    if let Some(notifier) = backfill.take_notifier(height) {
+        let _ = notifier.send(height);
+    }
+    tokio::spawn(async move {
+        if let Ok(height) = backfill_done.await {
+            info!("Backfill reached {height}, following the chain tip");
+        }
+    });
     let mut pre_handle = None;
     let mut handles = JoinSet::new();
     let fetch_size = config.unistate.optional_config.fetch_size;
+    let statement_timeout = config
+        .unistate
+        .optional_config
+        .statement_timeout
+        .map(Duration::from_millis);
+    let event_log = config
+        .unistate
+        .optional_config
+        .event_log
+        .then(|| events::EventLog::new(EVENT_LOG_CAPACITY));
+    let db_stats = Arc::new(database::DbStats::default());
+    let txn_limiter = database::TxnLimiter::new(max_connections as usize);
+    let batch_tuner = config
+        .unistate
+        .optional_config
+        .insert_latency_target
+        .map(|target| {
+            Arc::new(database::BatchTuner::new(
+                INSERT_BATCH_INITIAL,
+                INSERT_BATCH_MIN,
+                u16::MAX as usize,
+                Duration::from_millis(target),
+            ))
+        });
+    let lag_monitor = config
+        .unistate
+        .optional_config
+        .lag_alert_threshold
+        .map(|threshold| {
+            let alert: lag::LagAlert = Box::new(move |lag| {
+                Box::pin(async move {
+                    tracing::error!(
+                        "Indexing is {lag} blocks behind the tip, more than {threshold}"
+                    );
+                })
+            });
+            let monitor = Arc::new(lag::LagMonitor::new(
+                threshold,
+                Duration::from_secs(config.unistate.optional_config.lag_alert_after),
+                alert,
+            ));
+            monitor.set_tip(initial_target);
+            monitor.set_indexed(height);
+            let client = client.clone();
+            monitor.clone().spawn(LAG_CHECK_INTERVAL, move || {
+                let client = client.clone();
+                async move { Ok(client.get_tip_block_number().await?.value()) }
+            });
+            monitor
+        });
+    let new_processor = |height| {
+        let (database_processor, op_sender, commited) = DatabaseProcessor::new(db.clone(), height);
+        let database_processor = database_processor
+            .with_statement_timeout(statement_timeout)
+            .with_event_log(event_log.clone())
+            .with_write_buffer_limit(config.unistate.optional_config.write_buffer_limit)
+            .with_reconnect_retries(config.unistate.optional_config.db_reconnect_retries)
+            .with_stats(db_stats.clone())
+            .with_txn_limiter(Some(txn_limiter.clone()))
+            .with_sinks(sinks.clone())
+            .with_lock_accumulator(config.unistate.optional_config.lock_accumulator)
+            .with_batch_tuner(batch_tuner.clone())
+            .with_change_feed(config.unistate.optional_config.change_feed);
+        (database_processor, op_sender, commited)
+    };
+
+    let reprocess = &config.unistate.optional_config.reprocess;
+    if !reprocess.is_empty() {
+        let reprocessed =
+            reprocess::reprocess_txs(client, rgbpp_ctx, reprocess, new_processor(height)).await?;
+        info!("Reprocessed {reprocessed} transactions");
+        return Ok(());
+    }
+
+    let mut idle_backoff = backoff::Backoff::new(IDLE_BACKOFF_INITIAL, IDLE_BACKOFF_MAX);
     loop {
         info!("Fetching batch: {batch_size} items | Progress: {height}/{target_height}");
 
@@ -115,58 +331,77 @@ async fn main() -> anyhow::Result<()> {
             .collect::<Vec<_>>();
 
         height += batch_size;
-        batch_size = (target_height - height).min(max_batch_size);
+        batch_size = backfill::next_batch_size(target_height, height, max_batch_size);
 
         let blocks = client.get_blocks(numbers).await?;
 
-        let (database_processor, op_sender, commited) = DatabaseProcessor::new(db.clone(), height);
+        let (database_processor, op_sender, commited) = new_processor(height);
 
-        let processor_handle = tokio::spawn(database_processor.handle());
+        let backfill_notifier = backfill.take_notifier(height);
+        let committed_lag_monitor = lag_monitor.clone();
+        let processor_handle = tokio::spawn(async move {
+            database_processor.handle().await?;
+            if let Some(monitor) = committed_lag_monitor {
+                monitor.set_indexed(height);
+            }
+            if let Some(notifier) = backfill_notifier {
+                let _ = notifier.send(height);
+            }
+            anyhow::Ok(())
+        });
 
         let fetcher = client.clone();
+        let rgbpp_ctx = rgbpp_ctx.clone();
+        let resolve_in_block = config.unistate.optional_config.resolve_in_block;
 
         let pre_handle_take = pre_handle.take();
         handles.spawn(async move {
+            let scripts = &rgbpp_ctx.scripts;
             let categorized_txs = blocks
                 .into_par_iter()
                 .fold(CategorizedTxs::new, |acc, block| {
-                    let new =
-                        block
-                            .transactions
-                            .into_par_iter()
-                            .fold(CategorizedTxs::new, move |mut categorized, tx| {
-                                let rgbpp = tx.inner.cell_deps.par_iter().any(|cd| {
-                                    constants.rgbpp_lock_dep().out_point.eq(&cd.out_point)
+                    let outputs = Arc::new(rgbpp::BlockOutputs::new(&block.transactions));
+                    let block_outputs = resolve_in_block.then(|| outputs.clone());
+                    let new = block
+                        .transactions
+                        .into_par_iter()
+                        .fold(CategorizedTxs::new, move |mut categorized, tx| {
+                            let rgbpp = rgbpp::is_rgbpp_tx(scripts, &tx, Some(&*outputs));
+
+                            let spore = tx.inner.cell_deps.par_iter().any(|cd| {
+                                constants.spore_type_dep().out_point.eq(&cd.out_point)
+                                    || constants.cluster_type_dep().out_point.eq(&cd.out_point)
+                            });
+
+                            let xudt = tx
+                                .inner
+                                .cell_deps
+                                .par_iter()
+                                .any(|cd| constants.xudttype_dep().out_point.eq(&cd.out_point));
+
+                            if spore {
+                                categorized.spore_txs.push(SporeTx {
+                                    tx: tx.clone(),
+                                    timestamp: block.header.inner.timestamp.value(),
                                 });
-
-                                let spore = tx.inner.cell_deps.par_iter().any(|cd| {
-                                    constants.spore_type_dep().out_point.eq(&cd.out_point)
-                                        || constants.cluster_type_dep().out_point.eq(&cd.out_point)
+                            }
+
+                            if xudt {
+                                categorized.xudt_txs.push(XudtTx { tx: tx.clone() });
+                            }
+
+                            if rgbpp {
+                                categorized.rgbpp_txs.push(RgbppTx {
+                                    tx,
+                                    block_number: block.header.inner.number.value(),
+                                    timestamp: block.header.inner.timestamp.value(),
+                                    block_outputs: block_outputs.clone(),
                                 });
+                            }
 
-                                let xudt =
-                                    tx.inner.cell_deps.par_iter().any(|cd| {
-                                        constants.xudttype_dep().out_point.eq(&cd.out_point)
-                                    });
-
-                                if spore {
-                                    categorized.spore_txs.push(SporeTx {
-                                        tx: tx.clone(),
-                                        timestamp: block.header.inner.timestamp.value(),
-                                    });
-                                }
-
-                                if xudt {
-                                    categorized.xudt_txs.push(XudtTx { tx: tx.clone() });
-                                }
-
-                                if rgbpp {
-                                    categorized.rgbpp_txs.push(tx);
-                                }
-
-                                categorized
-                            })
-                            .reduce(CategorizedTxs::new, |pre, next| pre.merge(next));
+                            categorized
+                        })
+                        .reduce(CategorizedTxs::new, |pre, next| pre.merge(next));
                     acc.merge(new)
                 })
                 .reduce(CategorizedTxs::new, |acc, txs| acc.merge(txs));
@@ -189,7 +424,8 @@ async fn main() -> anyhow::Result<()> {
 
             tracing::debug!("drop xudt idxer");
 
-            let rgbpp_idxer = rgbpp::RgbppIndexer::new(rgbpp_txs, fetcher, op_sender);
+            let rgbpp_idxer =
+                rgbpp::RgbppIndexer::new(rgbpp_txs, fetcher, rgbpp_ctx.clone(), op_sender);
 
             rgbpp_idxer.index().await?;
 
@@ -221,15 +457,62 @@ async fn main() -> anyhow::Result<()> {
         }
 
         if batch_size == 0 {
-            let new_target = client.get_tip_block_number().await?.value();
-            if new_target != target_height {
-                target_height = new_target;
-                batch_size = (target_height - height).min(max_batch_size);
-            } else {
-                tokio::time::sleep(Duration::from_secs(5)).await;
+            if config.unistate.optional_config.change_feed {
+                let watermark =
+                    height.saturating_sub(config.unistate.optional_config.finality_depth);
+                let txn = db.begin().await?;
+                database::set_statement_timeout(&txn, statement_timeout).await?;
+                let compacted = changes::compact(&txn, watermark).await?;
+                txn.commit().await?;
+                tracing::debug!("compacted {compacted} changes at or below {watermark}");
             }
+            if let Some(pending_ctx) = &pending_ctx {
+                let template = client.get_block_template().await?;
+                let pending = pending::refresh_pending(&db, &template, pending_ctx).await?;
+                info!(
+                    "{pending} pending RGBPP locks in template {}",
+                    template.number.value()
+                );
+            }
+            let tip = backoff::wait_for_tip(
+                target_height,
+                || {
+                    let client = client.clone();
+                    async move { Ok(client.get_tip_block_number().await?.value()) }
+                },
+                &mut idle_backoff,
+            )
+            .await?;
+            target_height = backfill.target(tip);
+            batch_size = backfill::next_batch_size(target_height, height, max_batch_size);
         }
 
+        tracing::debug!("database stats: {db_stats:?}");
+        let stats = sink::IndexerStats {
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_millis() as u64,
+            db_reconnects: db_stats
+                .reconnects
+                .load(std::sync::atomic::Ordering::Relaxed),
+            breaker_state: client.breaker().map(|breaker| breaker.state()),
+        };
+        for sink in &sinks {
+            sink.emit_stats(&stats)?;
+        }
+        if let Some(breaker) = client.breaker() {
+            tracing::debug!("fetcher circuit breaker: {:?}", breaker.state());
+        }
+        if let Some(tuner) = &batch_tuner {
+            tracing::debug!("insert batch size: {}", tuner.current());
+        }
+        if let Some(monitor) = &lag_monitor {
+            tracing::debug!(
+                "indexing lag: {} blocks, {} alerts",
+                monitor.lag(),
+                monitor.alerts.load(std::sync::atomic::Ordering::Relaxed)
+            );
+        }
         info!("sleeping...");
         tokio::time::sleep(Duration::from_secs_f32(interval)).await;
     }
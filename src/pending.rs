@@ -0,0 +1,214 @@
+use ckb_jsonrpc_types::{BlockTemplate, TransactionTemplate, TransactionView};
+use sea_orm::{
+    sea_query::{Expr, OnConflict, Query},
+    DbConn, DbErr, EntityTrait as _, QueryFilter as _, Set, TransactionTrait as _,
+};
+use tokio::sync::mpsc;
+
+use crate::{
+    database::Operations,
+    entity::{pending_rgbpp_locks, rgbpp_locks},
+    rgbpp::{parse_rgbpp_tx, RgbppContext},
+};
+
+/// RGBPP locks created by the transactions of a block template numbered `number`.
+///
+/// The inputs of template transactions are not resolved, so only the locks they create
+/// are picked up.
+pub fn pending_locks(
+    number: u64,
+    txs: &[TransactionTemplate],
+    ctx: &RgbppContext,
+) -> anyhow::Result<Vec<pending_rgbpp_locks::ActiveModel>> {
+    let (op_sender, mut op_recv) = mpsc::unbounded_channel();
+    for template in txs {
+        let tx = TransactionView {
+            inner: template.data.clone(),
+            hash: template.hash.clone(),
+        };
+        let pre_outputs = vec![None; tx.inner.inputs.len()];
+        parse_rgbpp_tx(&tx, &pre_outputs, None, ctx, &op_sender)?;
+    }
+    drop(op_sender);
+
+    let mut locks = Vec::new();
+    while let Ok(op) = op_recv.try_recv() {
+        if let Operations::UpsertLock(lock) = op {
+            locks.push(pending_rgbpp_locks::ActiveModel {
+                lock_id: lock.lock_id,
+                tx: lock.tx,
+                out_index: lock.out_index,
+                btc_txid: lock.btc_txid,
+                script_version: lock.script_version,
+                output_index: lock.output_index,
+                capacity: lock.capacity,
+                template_number: Set(number as i64),
+            });
+        }
+    }
+
+    Ok(locks)
+}
+
+/// Replace the pending set with `locks`, then drop the ones already confirmed in
+/// `rgbpp_locks`. Returns the number of pending locks left.
+pub async fn replace_pending(
+    db: &DbConn,
+    locks: Vec<pending_rgbpp_locks::ActiveModel>,
+) -> Result<u64, DbErr> {
+    let txn = db.begin().await?;
+
+    pending_rgbpp_locks::Entity::delete_many()
+        .exec(&txn)
+        .await?;
+
+    let inserted = if locks.is_empty() {
+        0
+    } else {
+        pending_rgbpp_locks::Entity::insert_many(locks)
+            .on_conflict(
+                OnConflict::columns([
+                    pending_rgbpp_locks::Column::Tx,
+                    pending_rgbpp_locks::Column::OutputIndex,
+                ])
+                .do_nothing()
+                .to_owned(),
+            )
+            .exec_without_returning(&txn)
+            .await?
+    };
+
+    // The template can lag behind what has been committed since.
+    let confirmed = pending_rgbpp_locks::Entity::delete_many()
+        .filter(
+            Expr::tuple([
+                Expr::col(pending_rgbpp_locks::Column::Tx).into(),
+                Expr::col(pending_rgbpp_locks::Column::OutputIndex).into(),
+            ])
+            .in_subquery(
+                Query::select()
+                    .columns([rgbpp_locks::Column::Tx, rgbpp_locks::Column::OutputIndex])
+                    .from(rgbpp_locks::Entity)
+                    .to_owned(),
+            ),
+        )
+        .exec(&txn)
+        .await?;
+
+    txn.commit().await?;
+
+    Ok(inserted.saturating_sub(confirmed.rows_affected))
+}
+
+/// Index the transactions of `template` into `pending_rgbpp_locks`.
+pub async fn refresh_pending(
+    db: &DbConn,
+    template: &BlockTemplate,
+    ctx: &RgbppContext,
+) -> anyhow::Result<u64> {
+    let locks = pending_locks(template.number.value(), &template.transactions, ctx)?;
+
+    Ok(replace_pending(db, locks).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use ckb_jsonrpc_types::{CellOutput, JsonBytes, Script, ScriptHashType};
+    use ckb_types::{packed, prelude::*, H256};
+    use molecule::prelude::{Builder as _, Entity as _};
+    use sea_orm::{
+        DatabaseBackend, MockDatabase, MockExecResult, QueryTrait as _, Statement, Transaction,
+    };
+
+    use super::*;
+    use crate::{
+        config::RgbppScriptConfig,
+        schemas::{blockchain, rgbpp},
+    };
+
+    fn template_tx(code_hash: H256) -> TransactionTemplate {
+        let lock = rgbpp::RGBPPLock::new_builder()
+            .out_index(blockchain::Uint32::new_unchecked(
+                1u32.to_le_bytes().to_vec().into(),
+            ))
+            .btc_txid(blockchain::Byte32::new_unchecked(vec![7u8; 32].into()))
+            .build();
+
+        let mut tx: TransactionView = packed::Transaction::default().into_view().into();
+        tx.inner.outputs.push(CellOutput {
+            capacity: 100.into(),
+            lock: Script {
+                code_hash,
+                hash_type: ScriptHashType::Type,
+                args: JsonBytes::from_bytes(lock.as_bytes()),
+            },
+            type_: None,
+        });
+
+        TransactionTemplate {
+            hash: H256([5u8; 32]),
+            required: false,
+            cycles: None,
+            depends: None,
+            data: tx.inner,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_index_pending_template_tx() {
+        let code_hash = H256([1u8; 32]);
+        let ctx = RgbppContext::new(vec![RgbppScriptConfig {
+            version: 1,
+            code_hash: code_hash.clone(),
+            hash_type: ScriptHashType::Type,
+            cell_dep: None,
+        }]);
+
+        let locks = pending_locks(42, &[template_tx(code_hash)], &ctx).unwrap();
+        assert_eq!(locks.len(), 1);
+        assert_eq!(locks[0].tx, Set(vec![5u8; 32]));
+        assert_eq!(locks[0].out_index, Set(1));
+        assert_eq!(locks[0].capacity, Set(Some(100)));
+        assert_eq!(locks[0].template_number, Set(42));
+
+        let affected = |rows_affected| MockExecResult {
+            last_insert_id: 0,
+            rows_affected,
+        };
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_exec_results([affected(3), affected(1), affected(0)])
+            .into_connection();
+
+        assert_eq!(replace_pending(&db, locks.clone()).await.unwrap(), 1);
+
+        // The pending and confirmed sets are matched by cell out point.
+        let insert = pending_rgbpp_locks::Entity::insert_many(locks)
+            .on_conflict(
+                OnConflict::columns([
+                    pending_rgbpp_locks::Column::Tx,
+                    pending_rgbpp_locks::Column::OutputIndex,
+                ])
+                .do_nothing()
+                .to_owned(),
+            )
+            .build(DatabaseBackend::Postgres);
+        assert_eq!(
+            db.into_transaction_log(),
+            [Transaction::many([
+                Statement::from_string(DatabaseBackend::Postgres, "BEGIN"),
+                Statement::from_sql_and_values(
+                    DatabaseBackend::Postgres,
+                    r#"DELETE FROM "pending_rgbpp_locks""#,
+                    []
+                ),
+                insert,
+                Statement::from_sql_and_values(
+                    DatabaseBackend::Postgres,
+                    r#"DELETE FROM "pending_rgbpp_locks" WHERE ("tx", "output_index") IN (SELECT "tx", "output_index" FROM "rgbpp_locks")"#,
+                    []
+                ),
+                Statement::from_string(DatabaseBackend::Postgres, "COMMIT"),
+            ])]
+        );
+    }
+}
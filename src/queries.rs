@@ -0,0 +1,587 @@
+use std::collections::HashSet;
+
+use sea_orm::{
+    prelude::BigDecimal, ColumnTrait as _, ConnectionTrait as _, DbConn, DbErr, EntityTrait as _,
+    FromQueryResult, PaginatorTrait as _, QueryFilter as _, QueryOrder as _, Select, Statement,
+};
+use tracing::warn;
+
+use crate::{
+    entity::{rgbpp_locks, rgbpp_unlocks, xudt_cell},
+    rgbpp::RgbppUnlockJson,
+};
+
+/// Everything known about a single RGBPP lock.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LockDetail {
+    pub lock_id: Vec<u8>,
+    pub out_index: i32,
+    pub btc_txid: Vec<u8>,
+    pub script_version: i16,
+    /// The CKB transaction that created the locked cell.
+    pub created_tx: Vec<u8>,
+    /// The CKB transaction that spent the locked cell, `None` while it is live.
+    pub spent_tx: Option<Vec<u8>>,
+    pub capacity: Option<i64>,
+    /// The xUDT cell bound to the locked cell, if any.
+    pub token: Option<xudt_cell::Model>,
+}
+
+/// Several cells can be bound to one lock, the live one is preferred over spent ones.
+pub async fn lock_detail(db: &DbConn, lock_id: &[u8]) -> Result<Option<LockDetail>, DbErr> {
+    let detail = find_lock(lock_id)
+        .find_also_related(xudt_cell::Entity)
+        .one(db)
+        .await?
+        .map(|(lock, token)| LockDetail {
+            lock_id: lock.lock_id,
+            out_index: lock.out_index,
+            btc_txid: lock.btc_txid,
+            script_version: lock.script_version,
+            created_tx: lock.tx,
+            spent_tx: lock.spent_tx,
+            capacity: lock.capacity,
+            token,
+        });
+
+    Ok(detail)
+}
+
+/// The cells bound to `lock_id`, live ones first.
+fn find_lock(lock_id: &[u8]) -> Select<rgbpp_locks::Entity> {
+    rgbpp_locks::Entity::find()
+        .filter(rgbpp_locks::Column::LockId.eq(lock_id.to_vec()))
+        .order_by_desc(rgbpp_locks::Column::SpentTx.is_null())
+        .order_by_asc(rgbpp_locks::Column::Tx)
+        .order_by_asc(rgbpp_locks::Column::OutputIndex)
+}
+
+/// One lock an asset passed through, with the xUDT amount its cell carried.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetHop {
+    pub lock: rgbpp_locks::Model,
+    pub amount: Option<BigDecimal>,
+}
+
+/// Follow an asset from `initial_lock_id` along the transactions that spent it.
+///
+/// At each hop the next lock is the one created by the spending transaction that holds
+/// the same xUDT type, or its first lock for a plain lock. The walk stops at an unspent
+/// lock, a spend that created no RGBPP lock, or a lock already visited.
+pub async fn asset_history(db: &DbConn, initial_lock_id: &[u8]) -> Result<Vec<AssetHop>, DbErr> {
+    let mut hops = Vec::new();
+
+    let Some((mut lock, mut token)) = find_lock(initial_lock_id)
+        .find_also_related(xudt_cell::Entity)
+        .one(db)
+        .await?
+    else {
+        return Ok(hops);
+    };
+
+    let type_id = token.as_ref().map(|token| token.type_id.clone());
+    let mut visited = HashSet::new();
+
+    loop {
+        visited.insert((lock.tx.clone(), lock.output_index));
+        let spent_tx = lock.spent_tx.clone();
+        hops.push(AssetHop {
+            lock,
+            amount: token.map(|token| token.amount),
+        });
+
+        let Some(spent_tx) = spent_tx else {
+            break;
+        };
+
+        let next = rgbpp_locks::Entity::find()
+            .filter(rgbpp_locks::Column::Tx.eq(spent_tx))
+            .order_by_asc(rgbpp_locks::Column::OutputIndex)
+            .find_also_related(xudt_cell::Entity)
+            .all(db)
+            .await?
+            .into_iter()
+            .find(|(_, next_token)| {
+                type_id.is_none()
+                    || next_token.as_ref().map(|token| &token.type_id) == type_id.as_ref()
+            });
+
+        match next {
+            Some((next_lock, _))
+                if visited.contains(&(next_lock.tx.clone(), next_lock.output_index)) =>
+            {
+                warn!(
+                    "Asset history of {} loops back",
+                    hex::encode(initial_lock_id)
+                );
+                break;
+            }
+            Some((next_lock, next_token)) => {
+                lock = next_lock;
+                token = next_token;
+            }
+            None => break,
+        }
+    }
+
+    Ok(hops)
+}
+
+/// Load an unlock in its JSON form rather than as raw molecule blobs.
+pub async fn unlock_json(db: &DbConn, unlock_id: &[u8]) -> Result<Option<RgbppUnlockJson>, DbErr> {
+    rgbpp_unlocks::Entity::find_by_id(unlock_id.to_vec())
+        .one(db)
+        .await?
+        .map(|unlock| RgbppUnlockJson::try_from(&unlock).map_err(|e| DbErr::Custom(e.to_string())))
+        .transpose()
+}
+
+/// CKB unlocks whose `btc_tx` hashes to `btc_txid`, given in display byte order.
+pub async fn unlocks_for_btc_tx(
+    db: &DbConn,
+    btc_txid: &[u8],
+) -> Result<Vec<rgbpp_unlocks::Model>, DbErr> {
+    rgbpp_unlocks::Entity::find()
+        .filter(rgbpp_unlocks::Column::BtcTxid.eq(btc_txid.to_vec()))
+        .all(db)
+        .await
+}
+
+/// A zero based page of `size` rows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Page {
+    pub number: u64,
+    pub size: u64,
+}
+
+/// Locks whose BTC output index is `out_index`, whatever their BTC txid, ordered by
+/// lock id and then cell.
+pub async fn locks_by_out_index(
+    db: &DbConn,
+    out_index: u32,
+    page: Page,
+) -> Result<Vec<rgbpp_locks::Model>, DbErr> {
+    rgbpp_locks::Entity::find()
+        .filter(rgbpp_locks::Column::OutIndex.eq(out_index as i32))
+        .order_by_asc(rgbpp_locks::Column::LockId)
+        .order_by_asc(rgbpp_locks::Column::Tx)
+        .order_by_asc(rgbpp_locks::Column::OutputIndex)
+        .paginate(db, page.size)
+        .fetch_page(page.number)
+        .await
+}
+
+/// Aggregate figures for a dashboard landing page.
+#[derive(Debug, Clone, PartialEq, Eq, FromQueryResult)]
+pub struct SummaryStats {
+    pub total_locks: i64,
+    pub total_unlocks: i64,
+    pub live_locks: i64,
+    pub distinct_btc_txids: i64,
+    pub height: i64,
+}
+
+/// Computes every stat with aggregates in a single round trip, no rows are loaded.
+pub async fn summary_stats(db: &DbConn) -> Result<SummaryStats, DbErr> {
+    let stmt = Statement::from_string(
+        db.get_database_backend(),
+        "SELECT \
+            (SELECT COUNT(*) FROM rgbpp_locks) AS total_locks, \
+            (SELECT COUNT(*) FROM rgbpp_unlocks) AS total_unlocks, \
+            (SELECT COUNT(*) FROM rgbpp_locks WHERE spent_tx IS NULL) AS live_locks, \
+            (SELECT COUNT(DISTINCT btc_txid) FROM rgbpp_locks) AS distinct_btc_txids, \
+            (SELECT height FROM block_height WHERE id = 1) AS height",
+    );
+
+    SummaryStats::find_by_statement(stmt)
+        .one(db)
+        .await?
+        .ok_or_else(|| DbErr::RecordNotFound("summary stats".into()))
+}
+
+/// Locks created and unlocks seen for one script version within a block range.
+#[derive(Debug, Clone, PartialEq, Eq, FromQueryResult)]
+pub struct VersionAdoption {
+    pub script_version: i16,
+    pub locks: i64,
+    pub unlocks: i64,
+}
+
+/// Per script version counts for blocks `from..to`, ordered by version.
+///
+/// Rows indexed before `block_number` was recorded are not counted.
+pub async fn version_adoption(
+    db: &DbConn,
+    from: u64,
+    to: u64,
+) -> Result<Vec<VersionAdoption>, DbErr> {
+    let stmt = Statement::from_sql_and_values(
+        db.get_database_backend(),
+        "SELECT script_version, SUM(locks)::BIGINT AS locks, SUM(unlocks)::BIGINT AS unlocks \
+         FROM ( \
+            SELECT script_version, COUNT(*) AS locks, 0 AS unlocks FROM rgbpp_locks \
+            WHERE block_number >= $1 AND block_number < $2 GROUP BY script_version \
+            UNION ALL \
+            SELECT script_version, 0 AS locks, COUNT(*) AS unlocks FROM rgbpp_unlocks \
+            WHERE block_number >= $1 AND block_number < $2 GROUP BY script_version \
+         ) counts \
+         GROUP BY script_version ORDER BY script_version",
+        [(from as i64).into(), (to as i64).into()],
+    );
+
+    VersionAdoption::find_by_statement(stmt).all(db).await
+}
+
+/// RGBPP capacity locked and released in one block.
+#[derive(Debug, Clone, PartialEq, Eq, FromQueryResult)]
+pub struct CapacityFlow {
+    pub block_number: i64,
+    /// Capacity of the RGBPP locks created in the block.
+    pub capacity_in: i64,
+    /// Capacity of the RGBPP locks spent in the block.
+    pub capacity_out: i64,
+}
+
+/// Per block capacity flow for blocks `from..to`, ordered by block, skipping blocks
+/// without any.
+///
+/// A spend is placed in the block of its spending transaction, recorded on the lock.
+pub async fn capacity_flow(db: &DbConn, from: u64, to: u64) -> Result<Vec<CapacityFlow>, DbErr> {
+    let stmt = Statement::from_sql_and_values(
+        db.get_database_backend(),
+        "SELECT block_number, SUM(capacity_in)::BIGINT AS capacity_in, \
+            SUM(capacity_out)::BIGINT AS capacity_out \
+         FROM ( \
+            SELECT block_number, capacity AS capacity_in, 0 AS capacity_out FROM rgbpp_locks \
+            WHERE block_number >= $1 AND block_number < $2 AND capacity IS NOT NULL \
+            UNION ALL \
+            SELECT spent_block_number, 0 AS capacity_in, capacity AS capacity_out \
+            FROM rgbpp_locks \
+            WHERE spent_block_number >= $1 AND spent_block_number < $2 \
+            AND capacity IS NOT NULL \
+         ) flows \
+         GROUP BY block_number ORDER BY block_number",
+        [(from as i64).into(), (to as i64).into()],
+    );
+
+    CapacityFlow::find_by_statement(stmt).all(db).await
+}
+
+#[derive(FromQueryResult)]
+struct BtcTxid {
+    btc_txid: Vec<u8>,
+}
+
+/// The distinct BTC txids referenced by locks created or unlocks seen in blocks
+/// `from..to`, in display byte order and sorted.
+pub async fn distinct_btc_txids(db: &DbConn, from: u64, to: u64) -> Result<Vec<Vec<u8>>, DbErr> {
+    // Both columns already hold display order, as `parse_rgbpp_tx` records them.
+    let stmt = Statement::from_sql_and_values(
+        db.get_database_backend(),
+        "SELECT DISTINCT btc_txid FROM ( \
+            SELECT btc_txid FROM rgbpp_locks \
+            WHERE block_number >= $1 AND block_number < $2 \
+            UNION ALL \
+            SELECT btc_txid FROM rgbpp_unlocks \
+            WHERE block_number >= $1 AND block_number < $2 AND btc_txid IS NOT NULL \
+         ) txids \
+         ORDER BY btc_txid",
+        [(from as i64).into(), (to as i64).into()],
+    );
+
+    Ok(BtcTxid::find_by_statement(stmt)
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|row| row.btc_txid)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use sea_orm::{
+        DatabaseBackend, MockDatabase, QuerySelect as _, QueryTrait as _, Transaction, Value,
+    };
+
+    use super::*;
+
+    fn lock_model(lock_id: u8) -> rgbpp_locks::Model {
+        rgbpp_locks::Model {
+            lock_id: vec![lock_id],
+            tx: vec![1],
+            out_index: 0,
+            btc_txid: vec![lock_id],
+            script_version: 1,
+            output_index: 0,
+            capacity: Some(100),
+            spent_tx: None,
+            btc_replaced: false,
+            owner_lock_hash: None,
+            block_number: None,
+            raw_args: None,
+            spent_block_number: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lock_detail() {
+        let lock = rgbpp_locks::Model {
+            spent_tx: Some(vec![2]),
+            ..lock_model(0)
+        };
+        let token = xudt_cell::Model {
+            transaction_hash: vec![1],
+            transaction_index: 0,
+            lock_id: "lock".into(),
+            type_id: "type".into(),
+            amount: BigDecimal::from(1000),
+            xudt_args: None,
+            xudt_data: None,
+            xudt_data_lock: None,
+            xudt_owner_lock_script_hash: None,
+            is_consumed: true,
+        };
+
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([[(lock.clone(), token.clone())]])
+            .into_connection();
+
+        let detail = lock_detail(&db, &lock.lock_id).await.unwrap().unwrap();
+
+        assert_eq!(
+            detail,
+            LockDetail {
+                lock_id: vec![0],
+                out_index: 0,
+                btc_txid: vec![0],
+                script_version: 1,
+                created_tx: vec![1],
+                spent_tx: Some(vec![2]),
+                capacity: Some(100),
+                token: Some(token),
+            }
+        );
+
+        // The lock id alone no longer identifies a row, the live cell comes first.
+        let expected = rgbpp_locks::Entity::find()
+            .filter(rgbpp_locks::Column::LockId.eq(vec![0u8]))
+            .order_by_desc(rgbpp_locks::Column::SpentTx.is_null())
+            .order_by_asc(rgbpp_locks::Column::Tx)
+            .order_by_asc(rgbpp_locks::Column::OutputIndex)
+            .find_also_related(xudt_cell::Entity)
+            .limit(1)
+            .build(DatabaseBackend::Postgres);
+        assert_eq!(db.into_transaction_log(), [Transaction::one(expected)]);
+    }
+
+    fn token_model(transaction_hash: u8, amount: u64) -> xudt_cell::Model {
+        xudt_cell::Model {
+            transaction_hash: vec![transaction_hash],
+            transaction_index: 0,
+            lock_id: "lock".into(),
+            type_id: "type".into(),
+            amount: BigDecimal::from(amount),
+            xudt_args: None,
+            xudt_data: None,
+            xudt_data_lock: None,
+            xudt_owner_lock_script_hash: None,
+            is_consumed: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_asset_history() {
+        // lock 0 (tx 1) -> spent by tx 2 creating lock 1 -> spent by tx 3 creating lock 2.
+        let hop = |lock_id: u8, spent_tx: Option<u8>| rgbpp_locks::Model {
+            tx: vec![lock_id + 1],
+            spent_tx: spent_tx.map(|tx| vec![tx]),
+            ..lock_model(lock_id)
+        };
+        let other_token = xudt_cell::Model {
+            type_id: "other".into(),
+            ..token_model(2, 5)
+        };
+
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([[(hop(0, Some(2)), token_model(1, 1000))]])
+            .append_query_results([vec![
+                (hop(9, None), other_token),
+                (hop(1, Some(3)), token_model(2, 600)),
+            ]])
+            .append_query_results([[(hop(2, None), token_model(3, 400))]])
+            .into_connection();
+
+        let history = asset_history(&db, &[0]).await.unwrap();
+
+        assert_eq!(
+            history
+                .iter()
+                .map(|hop| (hop.lock.lock_id.clone(), hop.amount.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (vec![0], Some(BigDecimal::from(1000))),
+                (vec![1], Some(BigDecimal::from(600))),
+                (vec![2], Some(BigDecimal::from(400))),
+            ]
+        );
+    }
+
+    /// Assert `db` was sent exactly `sql` with `values`.
+    fn assert_statement(db: DbConn, sql: &str, values: [Value; 2]) {
+        assert_eq!(
+            db.into_transaction_log(),
+            [Transaction::from_sql_and_values(
+                DatabaseBackend::Postgres,
+                sql,
+                values
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unlocks_for_btc_tx() {
+        let btc_txid = crate::rgbpp::btc_txid(&[0x02, 0x00, 0x00, 0x00]);
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([Vec::<rgbpp_unlocks::Model>::new()])
+            .into_connection();
+
+        unlocks_for_btc_tx(&db, &btc_txid).await.unwrap();
+
+        let expected = rgbpp_unlocks::Entity::find()
+            .filter(rgbpp_unlocks::Column::BtcTxid.eq(btc_txid.to_vec()))
+            .build(DatabaseBackend::Postgres);
+        assert_eq!(db.into_transaction_log(), [Transaction::one(expected)]);
+    }
+
+    #[tokio::test]
+    async fn test_summary_stats() {
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([[BTreeMap::from([
+                ("total_locks", Value::from(5i64)),
+                ("total_unlocks", Value::from(2i64)),
+                ("live_locks", Value::from(3i64)),
+                ("distinct_btc_txids", Value::from(4i64)),
+                ("height", Value::from(12_000_000i64)),
+            ])]])
+            .into_connection();
+
+        let stats = summary_stats(&db).await.unwrap();
+
+        assert_eq!(
+            stats,
+            SummaryStats {
+                total_locks: 5,
+                total_unlocks: 2,
+                live_locks: 3,
+                distinct_btc_txids: 4,
+                height: 12_000_000,
+            }
+        );
+        assert_eq!(db.into_transaction_log().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_version_adoption() {
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([Vec::<BTreeMap<&str, Value>>::new()])
+            .into_connection();
+
+        version_adoption(&db, 100, 200).await.unwrap();
+
+        // Both tables are counted over the same half open block range.
+        assert_statement(
+            db,
+            "SELECT script_version, SUM(locks)::BIGINT AS locks, SUM(unlocks)::BIGINT AS unlocks \
+             FROM ( \
+                SELECT script_version, COUNT(*) AS locks, 0 AS unlocks FROM rgbpp_locks \
+                WHERE block_number >= $1 AND block_number < $2 GROUP BY script_version \
+                UNION ALL \
+                SELECT script_version, 0 AS locks, COUNT(*) AS unlocks FROM rgbpp_unlocks \
+                WHERE block_number >= $1 AND block_number < $2 GROUP BY script_version \
+             ) counts \
+             GROUP BY script_version ORDER BY script_version",
+            [100i64.into(), 200i64.into()],
+        );
+    }
+
+    #[tokio::test]
+    async fn test_capacity_flow() {
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([Vec::<BTreeMap<&str, Value>>::new()])
+            .into_connection();
+
+        capacity_flow(&db, 100, 103).await.unwrap();
+
+        // Created capacity is placed in the block of the lock, spent capacity in the
+        // block of the spending transaction.
+        assert_statement(
+            db,
+            "SELECT block_number, SUM(capacity_in)::BIGINT AS capacity_in, \
+                SUM(capacity_out)::BIGINT AS capacity_out \
+             FROM ( \
+                SELECT block_number, capacity AS capacity_in, 0 AS capacity_out FROM rgbpp_locks \
+                WHERE block_number >= $1 AND block_number < $2 AND capacity IS NOT NULL \
+                UNION ALL \
+                SELECT spent_block_number, 0 AS capacity_in, capacity AS capacity_out \
+                FROM rgbpp_locks \
+                WHERE spent_block_number >= $1 AND spent_block_number < $2 \
+                AND capacity IS NOT NULL \
+             ) flows \
+             GROUP BY block_number ORDER BY block_number",
+            [100i64.into(), 103i64.into()],
+        );
+    }
+
+    #[tokio::test]
+    async fn test_distinct_btc_txids() {
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([Vec::<BTreeMap<&str, Value>>::new()])
+            .into_connection();
+
+        distinct_btc_txids(&db, 100, 103).await.unwrap();
+
+        // Locks and unlocks of the range feed one de-duplicated, sorted list.
+        assert_statement(
+            db,
+            "SELECT DISTINCT btc_txid FROM ( \
+                SELECT btc_txid FROM rgbpp_locks \
+                WHERE block_number >= $1 AND block_number < $2 \
+                UNION ALL \
+                SELECT btc_txid FROM rgbpp_unlocks \
+                WHERE block_number >= $1 AND block_number < $2 AND btc_txid IS NOT NULL \
+             ) txids \
+             ORDER BY btc_txid",
+            [100i64.into(), 103i64.into()],
+        );
+    }
+
+    #[tokio::test]
+    async fn test_locks_by_out_index() {
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([Vec::<rgbpp_locks::Model>::new()])
+            .into_connection();
+
+        locks_by_out_index(
+            &db,
+            1,
+            Page {
+                number: 2,
+                size: 10,
+            },
+        )
+        .await
+        .unwrap();
+
+        let expected = rgbpp_locks::Entity::find()
+            .filter(rgbpp_locks::Column::OutIndex.eq(1))
+            .order_by_asc(rgbpp_locks::Column::LockId)
+            .order_by_asc(rgbpp_locks::Column::Tx)
+            .order_by_asc(rgbpp_locks::Column::OutputIndex)
+            .limit(10)
+            .offset(20)
+            .build(DatabaseBackend::Postgres);
+        assert_eq!(db.into_transaction_log(), [Transaction::one(expected)]);
+    }
+}
@@ -0,0 +1,82 @@
+use std::sync::Arc;
+
+use anyhow::Context as _;
+use ckb_jsonrpc_types::BlockNumber;
+use ckb_types::H256;
+use jsonrpsee::http_client::HttpClient;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    database::{delete_rows_for_tx, DatabaseProcessor, Operations},
+    fetcher::Fetcher,
+    rgbpp::{BlockOutputs, RgbppContext, RgbppIndexer, RgbppTx},
+};
+
+/// Index the committed transactions `hashes` again, after deleting the RGBPP rows a
+/// previous run left for them.
+///
+/// The new rows go through `processor` as one batch. Returns the number of
+/// transactions reprocessed.
+pub async fn reprocess_txs(
+    fetcher: Fetcher<HttpClient>,
+    ctx: Arc<RgbppContext>,
+    hashes: &[H256],
+    (processor, op_sender, commited): (
+        DatabaseProcessor,
+        mpsc::UnboundedSender<Operations>,
+        oneshot::Sender<()>,
+    ),
+) -> anyhow::Result<usize> {
+    let mut numbers = Vec::new();
+    for (hash, response) in hashes.iter().zip(fetcher.get_txs(hashes.to_vec()).await?) {
+        let number = response
+            .tx_status
+            .block_number
+            .with_context(|| format!("transaction {hash:#x} is not committed"))?;
+        numbers.push(number.value());
+    }
+    numbers.sort_unstable();
+    numbers.dedup();
+
+    let blocks = fetcher
+        .get_blocks(numbers.into_iter().map(BlockNumber::from).collect())
+        .await?;
+    let txs = blocks
+        .into_iter()
+        .flat_map(|block| {
+            let block_outputs = Arc::new(BlockOutputs::new(&block.transactions));
+            let (number, timestamp) = (
+                block.header.inner.number.value(),
+                block.header.inner.timestamp.value(),
+            );
+            block
+                .transactions
+                .into_iter()
+                .filter(|tx| hashes.contains(&tx.hash))
+                .map(move |tx| RgbppTx {
+                    tx,
+                    block_number: number,
+                    timestamp,
+                    block_outputs: Some(block_outputs.clone()),
+                })
+        })
+        .collect::<Vec<_>>();
+
+    for hash in hashes {
+        let deleted =
+            delete_rows_for_tx(&processor.db, hash.as_bytes(), processor.statement_timeout).await?;
+        tracing::info!("deleted {deleted} rows of {hash:#x}");
+    }
+
+    let reprocessed = txs.len();
+    let processor_handle = tokio::spawn(processor.handle());
+    RgbppIndexer::new(txs, fetcher, ctx, op_sender)
+        .index()
+        .await?;
+    commited
+        .send(())
+        .map_err(|_| anyhow::anyhow!("commited failed."))?;
+    processor_handle.await??;
+
+    Ok(reprocessed)
+}
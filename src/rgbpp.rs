@@ -1,39 +1,346 @@
-use ckb_jsonrpc_types::TransactionView;
-use ckb_types::H256;
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use bigdecimal::num_bigint::BigInt;
+use ckb_jsonrpc_types::{
+    CellDep, CellInput, CellOutput, JsonBytes, OutPoint, Script, TransactionView,
+};
+use ckb_types::{packed, prelude::Entity as _, H256};
+use dashmap::DashMap;
 use jsonrpsee::http_client::HttpClient;
 use molecule::{
     bytes::Buf,
+    error::VerificationError,
     prelude::{Entity, Reader as _},
 };
 use rayon::{
-    iter::IntoParallelIterator as _,
+    iter::{IndexedParallelIterator as _, IntoParallelIterator as _},
     prelude::{IntoParallelRefIterator as _, ParallelIterator as _},
 };
-use sea_orm::Set;
+use sea_orm::{prelude::BigDecimal, NotSet, Set};
+use serde::{Deserialize, Serialize};
 use tokio::{sync::mpsc, task::JoinSet};
-use tracing::debug;
+use tracing::{debug, warn};
 
 use crate::{
+    config::RgbppScriptConfig,
+    constants::{Constants, RGBPP_TX_ID_PLACEHOLDER},
     database::Operations,
+    entity::{btc_tx_inputs, btc_txs, dead_letters, rgbpp_unlocks},
+    error::Error,
     fetcher::Fetcher,
     schemas::{blockchain, rgbpp},
+    sink::TxMetrics,
 };
 
+const RGBPP_COMMITMENT_VERSION: u16 = 0;
+
+/// Counters collected while parsing RGBPP transactions.
+#[derive(Debug, Default)]
+pub struct ParseStats {
+    pub empty_witnesses: AtomicU64,
+    /// `cells_filtered_total`, indexed by [`FilterReason`].
+    cells_filtered: [AtomicU64; 4],
+    /// Locks created and unlocks seen, labeled by script version.
+    by_version: DashMap<i16, VersionCounts>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct VersionCounts {
+    pub locks: u64,
+    pub unlocks: u64,
+}
+
+/// Why a cell was skipped instead of having its lock indexed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterReason {
+    /// The lock's code hash is not one of the configured RGBPP scripts.
+    Allowlist,
+    /// The lock args are not a valid `RGBPPLock`.
+    Decode,
+    /// The user supplied output filter rejected the cell.
+    Predicate,
+    /// The lock has no args, as most system scripts, so it cannot be an `RGBPPLock`.
+    EmptyArgs,
+}
+
+impl ParseStats {
+    fn record_filtered(&self, reason: FilterReason) {
+        self.cells_filtered[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn cells_filtered_total(&self, reason: FilterReason) -> u64 {
+        self.cells_filtered[reason as usize].load(Ordering::Relaxed)
+    }
+
+    fn record_lock(&self, version: i16) {
+        self.by_version.entry(version).or_default().locks += 1;
+    }
+
+    fn record_unlock(&self, version: i16) {
+        self.by_version.entry(version).or_default().unlocks += 1;
+    }
+
+    /// Counts per script version, ordered by version.
+    pub fn by_version(&self) -> BTreeMap<i16, VersionCounts> {
+        self.by_version
+            .iter()
+            .map(|entry| (*entry.key(), *entry.value()))
+            .collect()
+    }
+}
+
+/// User supplied predicate deciding whether an output-side lock gets indexed.
+pub type OutputFilter = Box<dyn Fn(&CellOutput) -> bool + Send + Sync>;
+
+/// A molecule structure that failed to decode while parsing an RGBPP transaction.
+#[derive(Debug)]
+pub enum DecodeError {
+    /// Lock args of a cell using a configured RGBPP script.
+    Lock(VerificationError),
+    /// A witness at the index of an RGBPP input.
+    WitnessArgs(VerificationError),
+    /// The lock field of such a witness.
+    Unlock(VerificationError),
+}
+
+/// Where a [`DecodeError`] happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeContext {
+    /// The transaction holding the witness, or creating the cell for lock errors.
+    pub tx: H256,
+    /// The witness index, or the cell's output index for lock errors.
+    pub index: usize,
+}
+
+/// User supplied hook invoked on every decode failure, e.g. for alerting or sampling.
+pub type DecodeErrorHook = Box<dyn Fn(DecodeError, &DecodeContext) + Send + Sync>;
+
+/// Settings and state shared by every RGBPP indexing task.
+#[derive(Default)]
+pub struct RgbppContext {
+    pub scripts: Vec<RgbppScriptConfig>,
+    pub stats: ParseStats,
+    /// Also resolve cell deps and record the RGBPP locks they reference.
+    pub index_cell_deps: bool,
+    /// Also record the distinct type script hashes of each transaction's outputs.
+    pub index_type_scripts: bool,
+    /// Applied to created cells before their locks are upserted, `None` accepts all.
+    pub output_filter: Option<OutputFilter>,
+    /// BTC confirmation policy used to flag unlocks, `None` leaves them unflagged.
+    pub btc_confirmations: Option<BtcConfirmations>,
+    /// Also store unlock proofs decoded as JSON in `btc_tx_proof_json`.
+    pub btc_tx_proof_json: bool,
+    /// Unspendable locks; outputs locked to one of them are recorded as burns.
+    pub burn_locks: Vec<Script>,
+    /// Called on each parse failure, `None` only counts them.
+    pub decode_error_hook: Option<DecodeErrorHook>,
+    /// Also send [`TxMetrics`] of every transaction for the sinks.
+    pub tx_metrics: bool,
+    /// Also store each lock's args verbatim in `raw_args`.
+    pub raw_args: bool,
+    /// Also record the lock time and input sequences of each unlock's `btc_tx`.
+    pub btc_timelocks: bool,
+    /// Network scripts telling xUDT cells apart, `None` records no owner lock hashes.
+    pub constants: Option<Constants>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BtcConfirmations {
+    /// Current BTC tip height.
+    pub tip: u64,
+    /// Confirmations required before an unlock counts as confirmed.
+    pub required: u64,
+}
+
+impl BtcConfirmations {
+    pub fn is_confirmed(self, height: u32) -> bool {
+        self.tip
+            .checked_sub(height as u64)
+            .map_or(false, |depth| depth + 1 >= self.required)
+    }
+}
+
+impl RgbppContext {
+    pub fn new(scripts: Vec<RgbppScriptConfig>) -> Self {
+        Self {
+            scripts,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_cell_deps(mut self, index_cell_deps: bool) -> Self {
+        self.index_cell_deps = index_cell_deps;
+        self
+    }
+
+    pub fn with_type_scripts(mut self, index_type_scripts: bool) -> Self {
+        self.index_type_scripts = index_type_scripts;
+        self
+    }
+
+    pub fn with_btc_confirmations(mut self, btc_confirmations: Option<BtcConfirmations>) -> Self {
+        self.btc_confirmations = btc_confirmations;
+        self
+    }
+
+    pub fn with_btc_tx_proof_json(mut self, btc_tx_proof_json: bool) -> Self {
+        self.btc_tx_proof_json = btc_tx_proof_json;
+        self
+    }
+
+    pub fn with_btc_timelocks(mut self, btc_timelocks: bool) -> Self {
+        self.btc_timelocks = btc_timelocks;
+        self
+    }
+
+    pub fn with_constants(mut self, constants: Option<Constants>) -> Self {
+        self.constants = constants;
+        self
+    }
+
+    pub fn with_burn_locks(mut self, burn_locks: Vec<Script>) -> Self {
+        self.burn_locks = burn_locks;
+        self
+    }
+
+    pub fn with_tx_metrics(mut self, tx_metrics: bool) -> Self {
+        self.tx_metrics = tx_metrics;
+        self
+    }
+
+    pub fn with_raw_args(mut self, raw_args: bool) -> Self {
+        self.raw_args = raw_args;
+        self
+    }
+
+    pub fn with_decode_error_hook(mut self, hook: DecodeErrorHook) -> Self {
+        self.decode_error_hook = Some(hook);
+        self
+    }
+
+    fn decode_error(&self, error: DecodeError, tx: &H256, index: usize) {
+        if let Some(hook) = &self.decode_error_hook {
+            hook(
+                error,
+                &DecodeContext {
+                    tx: tx.clone(),
+                    index,
+                },
+            );
+        }
+    }
+
+    pub fn with_output_filter(mut self, output_filter: OutputFilter) -> Self {
+        self.output_filter = Some(output_filter);
+        self
+    }
+
+    fn accepts(&self, cell: &LockCell<'_>) -> bool {
+        cell.spent_tx.is_some()
+            || self
+                .output_filter
+                .as_ref()
+                .map_or(true, |filter| filter(cell.output))
+    }
+}
+
+pub struct RgbppTx {
+    pub tx: TransactionView,
+    pub block_number: u64,
+    /// Timestamp of the containing block, in milliseconds.
+    pub timestamp: u64,
+    /// Outputs of the containing block, resolving inputs before the node is asked.
+    pub block_outputs: Option<Arc<BlockOutputs>>,
+}
+
+/// The outputs created by the transactions of one block, by transaction hash.
+#[derive(Debug, Default)]
+pub struct BlockOutputs(HashMap<H256, Vec<CellOutput>>);
+
+impl BlockOutputs {
+    pub fn new(txs: &[TransactionView]) -> Self {
+        Self(
+            txs.iter()
+                .map(|tx| (tx.hash.clone(), tx.inner.outputs.clone()))
+                .collect(),
+        )
+    }
+
+    pub fn get(&self, out_point: &OutPoint) -> Option<&CellOutput> {
+        self.0
+            .get(&out_point.tx_hash)?
+            .get(out_point.index.value() as usize)
+    }
+}
+
+/// The outputs spent by `inputs`, aligned with them like [`Fetcher::get_outputs`].
+///
+/// Inputs spending an output of `block_outputs` are resolved from it, so a transaction
+/// spending an earlier one of the same block resolves even when the node cannot serve
+/// it yet. Only the rest is passed to `fetch`, which is not called when nothing is left.
+pub async fn resolve_pre_outputs<F, Fut>(
+    inputs: &[CellInput],
+    block_outputs: Option<&BlockOutputs>,
+    fetch: F,
+) -> Result<Vec<Option<CellOutput>>, Error>
+where
+    F: FnOnce(Vec<CellInput>) -> Fut,
+    Fut: Future<Output = Result<Vec<Option<CellOutput>>, Error>>,
+{
+    let Some(block_outputs) = block_outputs else {
+        return fetch(inputs.to_vec()).await;
+    };
+
+    let mut resolved = inputs
+        .iter()
+        .map(|input| block_outputs.get(&input.previous_output).cloned())
+        .collect::<Vec<_>>();
+    let missing = inputs
+        .iter()
+        .zip(&resolved)
+        .filter(|(_, output)| output.is_none())
+        .map(|(input, _)| input.clone())
+        .collect::<Vec<_>>();
+    if missing.is_empty() {
+        return Ok(resolved);
+    }
+
+    let mut fetched = fetch(missing).await?.into_iter();
+    for output in &mut resolved {
+        if output.is_none() {
+            *output = fetched.next().flatten();
+        }
+    }
+
+    Ok(resolved)
+}
+
 pub struct RgbppIndexer {
-    txs: Vec<TransactionView>,
+    txs: Vec<RgbppTx>,
     fetcher: Fetcher<HttpClient>,
+    ctx: Arc<RgbppContext>,
     op_sender: mpsc::UnboundedSender<Operations>,
 }
 
 impl RgbppIndexer {
     pub fn new(
-        txs: Vec<TransactionView>,
+        txs: Vec<RgbppTx>,
         fetcher: Fetcher<HttpClient>,
+        ctx: Arc<RgbppContext>,
         op_sender: mpsc::UnboundedSender<Operations>,
     ) -> Self {
         Self {
             txs,
             fetcher,
+            ctx,
             op_sender,
         }
     }
@@ -42,12 +349,13 @@ impl RgbppIndexer {
         let Self {
             txs,
             fetcher,
+            ctx,
             op_sender,
         } = self;
 
         let mut tasks = JoinSet::from_iter(
             txs.into_par_iter()
-                .map(|tx| index_rgbpp_lock(fetcher.clone(), tx, op_sender.clone()))
+                .map(|tx| index_rgbpp_lock(fetcher.clone(), tx, ctx.clone(), op_sender.clone()))
                 .collect::<Vec<_>>()
                 .into_iter(),
         );
@@ -56,70 +364,479 @@ impl RgbppIndexer {
             task??;
         }
 
+        debug!("rgbpp parse stats: {:?}", ctx.stats);
+
         Ok(())
     }
 }
 
+/// Returns the configured version of the RGBPP lock `script` belongs to, if any.
+pub fn script_version(scripts: &[RgbppScriptConfig], script: &Script) -> Option<i16> {
+    scripts
+        .iter()
+        .find(|s| s.code_hash.eq(&script.code_hash) && s.hash_type.eq(&script.hash_type))
+        .map(|s| s.version)
+}
+
+/// Whether `tx` may create or spend a lock of one of `scripts`.
+///
+/// A spend references the script's cell dep, or its input is one of `block_outputs`
+/// with a configured lock. Inputs from earlier blocks are only resolved while indexing.
+pub fn is_rgbpp_tx(
+    scripts: &[RgbppScriptConfig],
+    tx: &TransactionView,
+    block_outputs: Option<&BlockOutputs>,
+) -> bool {
+    let is_rgbpp_lock = |lock: &Script| script_version(scripts, lock).is_some();
+
+    tx.inner.cell_deps.iter().any(|cd| {
+        scripts
+            .iter()
+            .any(|script| script.cell_dep.as_ref() == Some(&cd.out_point))
+    }) || tx
+        .inner
+        .outputs
+        .iter()
+        .any(|output| is_rgbpp_lock(&output.lock))
+        || block_outputs.is_some_and(|outputs| {
+            tx.inner.inputs.iter().any(|input| {
+                outputs
+                    .get(&input.previous_output)
+                    .is_some_and(|output| is_rgbpp_lock(&output.lock))
+            })
+        })
+}
+
 async fn index_rgbpp_lock(
     fetcher: Fetcher<HttpClient>,
-    tx: TransactionView,
+    RgbppTx {
+        tx,
+        block_number,
+        timestamp,
+        block_outputs,
+    }: RgbppTx,
+    ctx: Arc<RgbppContext>,
     op_sender: mpsc::UnboundedSender<Operations>,
 ) -> anyhow::Result<()> {
     debug!("tx: {}", hex::encode(tx.hash.as_bytes()));
 
-    tx.inner
-        .witnesses
-        .par_iter()
-        .filter_map(|witness| blockchain::WitnessArgsReader::from_slice(witness.as_bytes()).ok())
-        .filter_map(|witness_args| {
-            witness_args
-                .to_entity()
-                .lock()
-                .to_opt()
-                .and_then(|lock_witness| {
-                    rgbpp::RGBPPUnlockReader::from_slice(lock_witness.raw_data().as_ref())
-                        .ok()
-                        .map(|unlock| unlock.to_entity())
-                })
-        })
-        .try_for_each(|unlock| upsert_rgbpp_unlock(op_sender.clone(), &unlock, tx.hash.clone()))?;
-
-    let pre_outputs = fetcher.get_outputs(tx.inner.inputs).await?;
-
-    pre_outputs
-        .par_iter()
-        .filter_map(|output| {
-            rgbpp::RGBPPLockReader::from_slice(output.lock.args.as_bytes())
-                .ok()
-                .map(|reader| reader.to_entity())
+    let pre_outputs = resolve_pre_outputs(&tx.inner.inputs, block_outputs.as_deref(), |inputs| {
+        fetcher.get_outputs(inputs)
+    })
+    .await?;
+
+    parse_rgbpp_tx_isolated(&tx, &pre_outputs, Some(block_number), &ctx, &op_sender)?;
+
+    if ctx.tx_metrics {
+        op_sender.send(Operations::TxMetrics(TxMetrics::new(
+            &tx,
+            &pre_outputs,
+            &ctx.scripts,
+            timestamp,
+        )))?;
+    }
+
+    if ctx.index_cell_deps {
+        let dep_inputs = tx
+            .inner
+            .cell_deps
+            .iter()
+            .map(|dep| CellInput {
+                previous_output: dep.out_point.clone(),
+                since: 0.into(),
+            })
+            .collect::<Vec<_>>();
+
+        let dep_outputs = fetcher.get_outputs(dep_inputs).await?;
+
+        index_referenced_locks(
+            &tx.inner.cell_deps,
+            &dep_outputs,
+            &ctx,
+            &tx.hash,
+            &op_sender,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// [`parse_rgbpp_tx`], quarantining the transaction to `dead_letters` if parsing panics.
+///
+/// Operations are held back until the whole transaction parsed, so a panic leaves no
+/// partial rows behind and the rest of the batch carries on.
+pub fn parse_rgbpp_tx_isolated(
+    tx: &TransactionView,
+    pre_outputs: &[Option<CellOutput>],
+    block_number: Option<u64>,
+    ctx: &RgbppContext,
+    op_sender: &mpsc::UnboundedSender<Operations>,
+) -> anyhow::Result<()> {
+    let (tx_sender, mut tx_recv) = mpsc::unbounded_channel();
+    let parsed = panic::catch_unwind(AssertUnwindSafe(|| {
+        parse_rgbpp_tx(tx, pre_outputs, block_number, ctx, &tx_sender)
+    }));
+    drop(tx_sender);
+
+    match parsed {
+        Ok(result) => {
+            result?;
+            while let Ok(op) = tx_recv.try_recv() {
+                op_sender.send(op)?;
+            }
+        }
+        Err(payload) => {
+            let reason = payload
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "non-string panic payload".into());
+            warn!(
+                "quarantined tx {}, parsing panicked: {reason}",
+                hex::encode(tx.hash.as_bytes())
+            );
+            op_sender.send(Operations::UpsertDeadLetter(dead_letters::ActiveModel {
+                tx: Set(tx.hash.0.to_vec()),
+                block_number: Set(block_number.map(|number| number as i64)),
+                reason: Set(reason),
+            }))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Everything [`index_rgbpp_lock`] derives from a transaction once its inputs are resolved.
+pub fn parse_rgbpp_tx(
+    tx: &TransactionView,
+    pre_outputs: &[Option<CellOutput>],
+    block_number: Option<u64>,
+    ctx: &RgbppContext,
+    op_sender: &mpsc::UnboundedSender<Operations>,
+) -> anyhow::Result<()> {
+    index_unlocks(
+        &tx.inner.witnesses,
+        pre_outputs,
+        ctx,
+        &tx.hash,
+        block_number,
+        op_sender,
+    )?;
+
+    let spent_cells = tx
+        .inner
+        .inputs
+        .iter()
+        .zip(pre_outputs.iter())
+        .filter_map(|(input, output)| {
+            Some(LockCell {
+                output: output.as_ref()?,
+                tx: input.previous_output.tx_hash.clone(),
+                index: input.previous_output.index.value(),
+                spent_tx: Some(tx.hash.clone()),
+                // Created in an earlier block this transaction does not tell.
+                block_number: None,
+                spent_block_number: block_number,
+            })
         })
-        .try_for_each(|lock| upsert_rgbpp_lock(op_sender.clone(), &lock, tx.hash.clone()))?;
+        .collect::<Vec<_>>();
 
-    tx.inner
+    index_locks(&spent_cells, ctx, op_sender)?;
+
+    let created_cells = tx
+        .inner
         .outputs
+        .iter()
+        .enumerate()
+        .map(|(index, output)| LockCell {
+            output,
+            tx: tx.hash.clone(),
+            index: index as u32,
+            spent_tx: None,
+            block_number,
+            spent_block_number: None,
+        })
+        .collect::<Vec<_>>();
+
+    index_locks(&created_cells, ctx, op_sender)?;
+
+    if ctx.index_type_scripts {
+        upsert_tx_type_scripts(op_sender, tx)?;
+    }
+
+    index_burns(tx, ctx, op_sender)?;
+
+    Ok(())
+}
+
+fn upsert_tx_type_scripts(
+    op_sender: &mpsc::UnboundedSender<Operations>,
+    tx: &TransactionView,
+) -> anyhow::Result<()> {
+    use crate::entity::tx_type_scripts;
+
+    for hash in type_script_hashes(&tx.inner.outputs) {
+        op_sender.send(Operations::UpsertTxTypeScript(
+            tx_type_scripts::ActiveModel {
+                tx: Set(tx.hash.0.to_vec()),
+                type_script_hash: Set(hash.to_vec()),
+            },
+        ))?;
+    }
+
+    Ok(())
+}
+
+pub fn script_hash(script: &Script) -> [u8; 32] {
+    ckb_hash::blake2b_256(packed::Script::from(script.clone()).as_slice())
+}
+
+/// Distinct `blake2b_256` hashes of the outputs' type scripts, in first-seen order.
+fn type_script_hashes(outputs: &[CellOutput]) -> Vec<[u8; 32]> {
+    let mut hashes = Vec::new();
+    for script in outputs.iter().filter_map(|output| output.type_.as_ref()) {
+        let hash = script_hash(script);
+        if !hashes.contains(&hash) {
+            hashes.push(hash);
+        }
+    }
+    hashes
+}
+
+/// Record outputs sent to a burn lock, with the xUDT amount they carried if typed.
+fn index_burns(
+    tx: &TransactionView,
+    ctx: &RgbppContext,
+    op_sender: &mpsc::UnboundedSender<Operations>,
+) -> anyhow::Result<()> {
+    use crate::entity::burns;
+
+    let burned = tx
+        .inner
+        .outputs
+        .iter()
+        .zip(tx.inner.outputs_data.iter())
+        .enumerate()
+        .filter(|(_, (output, _))| ctx.burn_locks.contains(&output.lock));
+
+    for (index, (output, data)) in burned {
+        let amount = output
+            .type_
+            .as_ref()
+            .and(data.as_bytes().get(..16))
+            .map(|mut raw| BigDecimal::new(BigInt::from(raw.get_u128_le()), 0));
+
+        op_sender.send(Operations::UpsertBurn(burns::ActiveModel {
+            tx: Set(tx.hash.0.to_vec()),
+            output_index: Set(index as i32),
+            capacity: Set(output.capacity.value() as i64),
+            type_script_hash: Set(output
+                .type_
+                .as_ref()
+                .map(|script| script_hash(script).to_vec())),
+            amount: Set(amount),
+        }))?;
+    }
+
+    Ok(())
+}
+
+/// Record RGBPP locks held by cells the transaction only references as cell deps.
+fn index_referenced_locks(
+    cell_deps: &[CellDep],
+    dep_outputs: &[Option<CellOutput>],
+    ctx: &RgbppContext,
+    tx: &H256,
+    op_sender: &mpsc::UnboundedSender<Operations>,
+) -> anyhow::Result<()> {
+    use crate::entity::rgbpp_referenced_locks;
+
+    cell_deps
         .par_iter()
-        .filter_map(|output| {
+        .zip(dep_outputs.par_iter())
+        .filter_map(|(dep, output)| {
+            let output = output.as_ref()?;
+            script_version(&ctx.scripts, &output.lock).map(|version| (dep, output, version))
+        })
+        .filter_map(|(dep, output, version)| {
             rgbpp::RGBPPLockReader::from_slice(output.lock.args.as_bytes())
                 .ok()
-                .map(|reader| reader.to_entity())
+                .map(|reader| (dep, reader.to_entity(), version))
         })
-        .try_for_each(|lock| upsert_rgbpp_lock(op_sender.clone(), &lock, tx.hash.clone()))?;
+        .try_for_each(|(dep, lock, version)| {
+            let model = rgbpp_referenced_locks::ActiveModel {
+                lock_id: Set(lock.lock_id()),
+                tx: Set(tx.0.to_vec()),
+                dep_tx: Set(dep.out_point.tx_hash.0.to_vec()),
+                dep_index: Set(dep.out_point.index.value() as i32),
+                script_version: Set(version),
+            };
 
-    Ok(())
+            op_sender.send(Operations::UpsertReferencedLock(model))?;
+
+            Ok(())
+        })
+}
+
+/// The witness at index `i` unlocks the lock group led by input `i`, so the
+/// unlock inherits that input's script version.
+pub fn index_unlocks(
+    witnesses: &[JsonBytes],
+    pre_outputs: &[Option<CellOutput>],
+    ctx: &RgbppContext,
+    tx: &H256,
+    block_number: Option<u64>,
+    op_sender: &mpsc::UnboundedSender<Operations>,
+) -> anyhow::Result<()> {
+    witnesses
+        .par_iter()
+        .zip(pre_outputs.par_iter())
+        .enumerate()
+        // Empty witnesses are common and can never hold WitnessArgs, skip them before parsing.
+        .filter(|(_, (witness, _))| {
+            if witness.is_empty() {
+                ctx.stats.empty_witnesses.fetch_add(1, Ordering::Relaxed);
+                false
+            } else {
+                true
+            }
+        })
+        .filter_map(|(index, (witness, pre_output))| {
+            script_version(&ctx.scripts, &pre_output.as_ref()?.lock)
+                .map(|version| (index, witness, version))
+        })
+        .filter_map(|(index, witness, version)| {
+            match blockchain::WitnessArgsReader::from_slice(witness.as_bytes()) {
+                Ok(witness_args) => Some((index, witness_args, version)),
+                Err(e) => {
+                    ctx.decode_error(DecodeError::WitnessArgs(e), tx, index);
+                    None
+                }
+            }
+        })
+        .filter_map(|(index, witness_args, version)| {
+            let lock_witness = witness_args.to_entity().lock().to_opt()?;
+            match rgbpp::RGBPPUnlockReader::from_slice(lock_witness.raw_data().as_ref()) {
+                Ok(unlock) => Some((unlock.to_entity(), version)),
+                Err(e) => {
+                    ctx.decode_error(DecodeError::Unlock(e), tx, index);
+                    None
+                }
+            }
+        })
+        .try_for_each(|(unlock, version)| {
+            ctx.stats.record_unlock(version);
+            upsert_rgbpp_unlock(
+                op_sender.clone(),
+                &unlock,
+                version,
+                ctx,
+                tx.clone(),
+                block_number,
+            )
+        })
+}
+
+/// A CKB cell that may carry an RGBPP lock, identified by the out point that created it.
+struct LockCell<'a> {
+    output: &'a CellOutput,
+    tx: H256,
+    index: u32,
+    /// The transaction consuming this cell, set when the cell is seen as an input.
+    spent_tx: Option<H256>,
+    /// The block creating this cell, when known.
+    block_number: Option<u64>,
+    /// The block of `spent_tx`, when known.
+    spent_block_number: Option<u64>,
+}
+
+fn index_locks(
+    cells: &[LockCell<'_>],
+    ctx: &RgbppContext,
+    op_sender: &mpsc::UnboundedSender<Operations>,
+) -> anyhow::Result<()> {
+    cells
+        .par_iter()
+        // Checked before anything else, empty args are common and never worth decoding.
+        .filter(|cell| {
+            if cell.output.lock.args.is_empty() {
+                ctx.stats.record_filtered(FilterReason::EmptyArgs);
+                false
+            } else {
+                true
+            }
+        })
+        .filter_map(
+            |cell| match script_version(&ctx.scripts, &cell.output.lock) {
+                Some(version) => Some((cell, version)),
+                None => {
+                    ctx.stats.record_filtered(FilterReason::Allowlist);
+                    None
+                }
+            },
+        )
+        .filter_map(|(cell, version)| {
+            match rgbpp::RGBPPLockReader::from_slice(cell.output.lock.args.as_bytes()) {
+                Ok(reader) => Some((cell, reader.to_entity(), version)),
+                Err(e) => {
+                    ctx.decode_error(DecodeError::Lock(e), &cell.tx, cell.index as usize);
+                    ctx.stats.record_filtered(FilterReason::Decode);
+                    None
+                }
+            }
+        })
+        .filter_map(|(cell, lock, version)| {
+            if ctx.accepts(cell) {
+                Some((cell, lock, version))
+            } else {
+                ctx.stats.record_filtered(FilterReason::Predicate);
+                None
+            }
+        })
+        .try_for_each(|(cell, lock, version)| {
+            if cell.spent_tx.is_none() {
+                ctx.stats.record_lock(version);
+            }
+            upsert_rgbpp_lock(op_sender.clone(), &lock, version, cell, ctx)
+        })
+}
+
+/// The owner lock hash an xUDT type script carries as its first 32 args bytes.
+///
+/// Only xUDT args start with one: spore, cluster and unique args are type ids.
+fn owner_lock_hash(output: &CellOutput, constants: Option<Constants>) -> Option<Vec<u8>> {
+    constants?
+        .get_xudttype_script(output.type_.as_ref())
+        .and_then(|script| script.args.as_bytes().get(..32))
+        .map(<[u8]>::to_vec)
 }
 
 impl rgbpp::RGBPPLock {
     fn lock_id(&self) -> Vec<u8> {
-        blockchain::Bytes::new_unchecked(self.as_bytes())
-            .raw_data()
-            .to_vec()
+        self.as_bytes().to_vec()
     }
 }
 
+/// Decode a stored `lock_id` back into `(out_index, btc_txid)`.
+///
+/// The `btc_txid` is returned in display order, matching the `btc_txid` column. Ids
+/// written before the out index was kept are rewritten by migration 0024.
+pub fn decode_lock_id(lock_id: &[u8]) -> anyhow::Result<(u32, [u8; 32])> {
+    let lock = rgbpp::RGBPPLockReader::from_slice(lock_id)?;
+
+    let out_index = lock.out_index().raw_data().get_u32_le();
+    let mut btc_txid = std::array::from_fn::<u8, 32, _>(|i| lock.btc_txid().raw_data()[i]);
+    btc_txid.reverse();
+
+    Ok((out_index, btc_txid))
+}
+
 fn upsert_rgbpp_lock(
     op_sender: mpsc::UnboundedSender<Operations>,
     rgbpp_lock: &rgbpp::RGBPPLock,
-    tx: H256,
+    script_version: i16,
+    cell: &LockCell<'_>,
+    ctx: &RgbppContext,
 ) -> anyhow::Result<()> {
     use crate::entity::rgbpp_locks;
 
@@ -131,10 +848,25 @@ fn upsert_rgbpp_lock(
         lock_id: Set(lock_id),
         out_index: Set(rgbpp_lock.out_index().raw_data().get_u32_le() as i32),
         btc_txid: Set(txid),
-        tx: Set(tx.0.to_vec()),
+        tx: Set(cell.tx.0.to_vec()),
+        script_version: Set(script_version),
+        output_index: Set(cell.index as i32),
+        capacity: Set(Some(cell.output.capacity.value() as i64)),
+        spent_tx: Set(cell.spent_tx.as_ref().map(|tx| tx.0.to_vec())),
+        btc_replaced: NotSet,
+        owner_lock_hash: Set(owner_lock_hash(cell.output, ctx.constants)),
+        block_number: Set(cell.block_number.map(|number| number as i64)),
+        raw_args: Set(ctx
+            .raw_args
+            .then(|| cell.output.lock.args.as_bytes().to_vec())),
+        spent_block_number: Set(cell.spent_block_number.map(|number| number as i64)),
     };
 
-    op_sender.send(Operations::UpsertLock(model))?;
+    if cell.spent_tx.is_some() {
+        op_sender.send(Operations::SpendLock(model))?;
+    } else {
+        op_sender.send(Operations::UpsertLock(model))?;
+    }
 
     Ok(())
 }
@@ -149,11 +881,27 @@ impl rgbpp::RGBPPUnlock {
 fn upsert_rgbpp_unlock(
     op_sender: mpsc::UnboundedSender<Operations>,
     rgbpp_unlock: &rgbpp::RGBPPUnlock,
+    script_version: i16,
+    ctx: &RgbppContext,
     tx: H256,
+    block_number: Option<u64>,
 ) -> anyhow::Result<()> {
-    use crate::entity::rgbpp_unlocks;
-
     let unlock_id = rgbpp_unlock.unlock_id();
+    let btc_tx = rgbpp_unlock.btc_tx().raw_data();
+    let txid = btc_txid(&btc_tx);
+    let btc_tx_proof = rgbpp_unlock.btc_tx_proof().raw_data();
+    let btc_block_height = btc_proof_height(&btc_tx_proof);
+    let btc_tx_proof_json = if ctx.btc_tx_proof_json {
+        decode_btc_tx_proof(&btc_tx_proof)
+            .map(serde_json::to_value)
+            .transpose()?
+    } else {
+        None
+    };
+    let btc_confirmed = ctx
+        .btc_confirmations
+        .zip(btc_block_height)
+        .map(|(confirmations, height)| confirmations.is_confirmed(height));
     let model = rgbpp_unlocks::ActiveModel {
         unlock_id: Set(unlock_id),
         version: Set(rgbpp_unlock.version().raw_data().get_u16_le() as i16),
@@ -162,9 +910,1371 @@ fn upsert_rgbpp_unlock(
         btc_tx: Set(rgbpp_unlock.btc_tx().as_bytes().to_vec()),
         btc_tx_proof: Set(rgbpp_unlock.btc_tx_proof().as_bytes().to_vec()),
         tx: Set(tx.0.to_vec()),
+        script_version: Set(script_version),
+        btc_block_height: Set(btc_block_height.map(|height| height as i32)),
+        btc_confirmed: Set(btc_confirmed),
+        btc_tx_proof_json: Set(btc_tx_proof_json),
+        btc_txid: Set(Some(txid.to_vec())),
+        block_number: Set(block_number.map(|number| number as i64)),
     };
 
     op_sender.send(Operations::UpsertUnlock(model))?;
 
+    // A `btc_tx` that does not decode is still indexed, only without its timelocks.
+    if let Some(timelocks) = ctx
+        .btc_timelocks
+        .then(|| decode_btc_timelocks(&btc_tx))
+        .flatten()
+    {
+        op_sender.send(Operations::UpsertBtcTx(btc_txs::ActiveModel {
+            btc_txid: Set(txid.to_vec()),
+            lock_time: Set(timelocks.lock_time as i64),
+        }))?;
+        for (index, sequence) in timelocks.sequences.into_iter().enumerate() {
+            op_sender.send(Operations::UpsertBtcTxInput(btc_tx_inputs::ActiveModel {
+                btc_txid: Set(txid.to_vec()),
+                input_index: Set(index as i32),
+                sequence: Set(sequence as i64),
+            }))?;
+        }
+    }
+
     Ok(())
 }
+
+/// The RGBPP commitment the BTC transaction of `unlock` carries in its OP_RETURN output.
+///
+/// Double SHA-256 of `"RGB++"`, the commitment version (`0u16` LE), `input_len` and
+/// `output_len`, then the out points of the first `input_len` inputs, then for each of
+/// the first `output_len` outputs its molecule `CellOutput`, data length as `u32` LE
+/// and data. The lengths come from the unlock's `ExtraCommitmentData`.
+///
+/// The BTC transaction cannot commit to its own txid, so RGBPP locks in `scripts` bound
+/// to it are committed with [`RGBPP_TX_ID_PLACEHOLDER`] as their `btc_txid`.
+pub fn compute_rgbpp_commitment(
+    tx: &TransactionView,
+    unlock: &rgbpp::RGBPPUnlock,
+    scripts: &[RgbppScriptConfig],
+) -> anyhow::Result<[u8; 32]> {
+    use sha2::{Digest as _, Sha256};
+
+    let input_len = unlock.extra_data().input_len().as_bytes().get_u8();
+    let output_len = unlock.extra_data().output_len().as_bytes().get_u8();
+    if input_len as usize > tx.inner.inputs.len() || output_len as usize > tx.inner.outputs.len() {
+        anyhow::bail!(
+            "commitment covers {input_len} inputs and {output_len} outputs, the tx has {} and {}",
+            tx.inner.inputs.len(),
+            tx.inner.outputs.len()
+        );
+    }
+    // Lock args hold the txid in raw byte order.
+    let mut own_txid = btc_txid(&unlock.btc_tx().raw_data());
+    own_txid.reverse();
+    let placeholder = hex::decode(RGBPP_TX_ID_PLACEHOLDER)?;
+
+    let mut hasher = Sha256::new();
+    hasher.update(b"RGB++");
+    hasher.update(RGBPP_COMMITMENT_VERSION.to_le_bytes());
+    hasher.update([input_len, output_len]);
+
+    for input in tx.inner.inputs.iter().take(input_len as usize) {
+        hasher.update(packed::OutPoint::from(input.previous_output.clone()).as_slice());
+    }
+
+    for (output, data) in tx
+        .inner
+        .outputs
+        .iter()
+        .zip(tx.inner.outputs_data.iter())
+        .take(output_len as usize)
+    {
+        let mut output = output.clone();
+        let args = output.lock.args.as_bytes();
+        let bound_here = script_version(scripts, &output.lock).is_some()
+            && rgbpp::RGBPPLockReader::from_slice(args)
+                .is_ok_and(|lock| lock.btc_txid().raw_data() == own_txid);
+        if bound_here {
+            let mut args = args.to_vec();
+            args[4..36].copy_from_slice(&placeholder);
+            output.lock.args = JsonBytes::from_vec(args);
+        }
+        hasher.update(packed::CellOutput::from(output).as_slice());
+        hasher.update((data.len() as u32).to_le_bytes());
+        hasher.update(data.as_bytes());
+    }
+
+    Ok(Sha256::digest(hasher.finalize()).into())
+}
+
+/// JSON view of an RGBPP unlock witness, with the BTC payloads hex-encoded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RgbppUnlockJson {
+    pub version: u16,
+    pub input_len: u8,
+    pub output_len: u8,
+    pub btc_tx: String,
+    pub btc_tx_proof: String,
+}
+
+impl From<&rgbpp::RGBPPUnlock> for RgbppUnlockJson {
+    fn from(unlock: &rgbpp::RGBPPUnlock) -> Self {
+        Self {
+            version: unlock.version().raw_data().get_u16_le(),
+            input_len: unlock.extra_data().input_len().as_bytes().get_u8(),
+            output_len: unlock.extra_data().output_len().as_bytes().get_u8(),
+            btc_tx: format!("0x{}", hex::encode(unlock.btc_tx().raw_data())),
+            btc_tx_proof: format!("0x{}", hex::encode(unlock.btc_tx_proof().raw_data())),
+        }
+    }
+}
+
+impl TryFrom<&rgbpp_unlocks::Model> for RgbppUnlockJson {
+    type Error = molecule::error::VerificationError;
+
+    /// Stored `btc_tx` and `btc_tx_proof` keep their molecule length header.
+    fn try_from(unlock: &rgbpp_unlocks::Model) -> Result<Self, Self::Error> {
+        let btc_tx = blockchain::BytesReader::from_slice(&unlock.btc_tx)?;
+        let btc_tx_proof = blockchain::BytesReader::from_slice(&unlock.btc_tx_proof)?;
+        Ok(Self {
+            version: unlock.version as u16,
+            input_len: unlock.input_len as u8,
+            output_len: unlock.output_len as u8,
+            btc_tx: format!("0x{}", hex::encode(btc_tx.raw_data())),
+            btc_tx_proof: format!("0x{}", hex::encode(btc_tx_proof.raw_data())),
+        })
+    }
+}
+
+/// Bitcoin txid of a serialized transaction, in the display byte order used for
+/// `rgbpp_locks.btc_txid`. `btc_tx` carries the transaction without witness data.
+pub fn btc_txid(btc_tx: &[u8]) -> [u8; 32] {
+    use sha2::{Digest as _, Sha256};
+
+    let mut txid: [u8; 32] = Sha256::digest(Sha256::digest(btc_tx)).into();
+    txid.reverse();
+    txid
+}
+
+/// Lock time and per input sequence numbers of a bitcoin transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BtcTimelocks {
+    pub lock_time: u32,
+    /// Sequences in input order.
+    pub sequences: Vec<u32>,
+}
+
+/// Split `len` bytes off the front of `raw`.
+fn take<'a>(raw: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if raw.len() < len {
+        return None;
+    }
+    let (head, tail) = raw.split_at(len);
+    *raw = tail;
+    Some(head)
+}
+
+/// Read a bitcoin `CompactSize` integer off the front of `raw`.
+fn take_compact_size(raw: &mut &[u8]) -> Option<usize> {
+    let value = match take(raw, 1)?[0] {
+        0xfd => u16::from_le_bytes(take(raw, 2)?.try_into().ok()?) as u64,
+        0xfe => u32::from_le_bytes(take(raw, 4)?.try_into().ok()?) as u64,
+        0xff => u64::from_le_bytes(take(raw, 8)?.try_into().ok()?),
+        value => value as u64,
+    };
+    usize::try_from(value).ok()
+}
+
+/// Skip a `CompactSize` prefixed byte string.
+fn skip_var_bytes(raw: &mut &[u8]) -> Option<()> {
+    let len = take_compact_size(raw)?;
+    take(raw, len).map(drop)
+}
+
+/// Decode the timelocks of a serialized bitcoin transaction, with or without witness
+/// data. `None` unless the whole of `btc_tx` is one transaction.
+pub fn decode_btc_timelocks(btc_tx: &[u8]) -> Option<BtcTimelocks> {
+    let mut raw = btc_tx;
+    // version
+    take(&mut raw, 4)?;
+    let mut inputs = take_compact_size(&mut raw)?;
+    // An input count of zero is the segwit marker, followed by the flag.
+    let segwit = inputs == 0;
+    if segwit {
+        if take(&mut raw, 1)?[0] != 1 {
+            return None;
+        }
+        inputs = take_compact_size(&mut raw)?;
+    }
+
+    let mut sequences = Vec::new();
+    for _ in 0..inputs {
+        // previous output
+        take(&mut raw, 36)?;
+        skip_var_bytes(&mut raw)?;
+        sequences.push(field_u32(take(&mut raw, 4)?)?);
+    }
+    for _ in 0..take_compact_size(&mut raw)? {
+        // value
+        take(&mut raw, 8)?;
+        skip_var_bytes(&mut raw)?;
+    }
+    if segwit {
+        for _ in 0..inputs {
+            for _ in 0..take_compact_size(&mut raw)? {
+                skip_var_bytes(&mut raw)?;
+            }
+        }
+    }
+    let lock_time = field_u32(take(&mut raw, 4)?)?;
+
+    raw.is_empty().then_some(BtcTimelocks {
+        lock_time,
+        sequences,
+    })
+}
+
+/// Split a molecule table into its fields, `None` if the header is inconsistent.
+fn table_fields(raw: &[u8]) -> Option<Vec<&[u8]>> {
+    let read_u32 = |offset: usize| -> Option<usize> {
+        raw.get(offset..offset + 4)
+            .map(|mut bytes| bytes.get_u32_le() as usize)
+    };
+
+    let total_size = read_u32(0)?;
+    if total_size != raw.len() {
+        return None;
+    }
+    if total_size == 4 {
+        return Some(Vec::new());
+    }
+
+    let field_count = read_u32(4)?.checked_sub(4)? / 4;
+    let offsets = (0..field_count)
+        .map(|i| read_u32(4 + 4 * i))
+        .collect::<Option<Vec<_>>>()?;
+
+    offsets
+        .iter()
+        .enumerate()
+        .map(|(i, &start)| raw.get(start..offsets.get(i + 1).copied().unwrap_or(total_size)))
+        .collect()
+}
+
+fn field_u32(field: &[u8]) -> Option<u32> {
+    field.try_into().ok().map(u32::from_le_bytes)
+}
+
+/// Read `height` from a bitcoin SPV `TransactionProof`, the molecule table
+/// `{ tx_index: Uint32, height: Uint32, .. }` carried as `btc_tx_proof`.
+fn btc_proof_height(proof: &[u8]) -> Option<u32> {
+    field_u32(table_fields(proof)?.get(1)?)
+}
+
+/// Structured form of a bitcoin SPV `TransactionProof`
+/// `{ tx_index: Uint32, height: Uint32, proof: Bytes32Vec }`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BtcTxProofJson {
+    pub tx_index: u32,
+    pub height: u32,
+    /// Merkle branch hashes, hex-encoded.
+    pub proof: Vec<String>,
+}
+
+/// Decode an unlock's `btc_tx_proof` into its `btc_tx_proof_json` form, `None` unless
+/// it is a whole `TransactionProof`.
+pub fn decode_btc_tx_proof(proof: &[u8]) -> Option<BtcTxProofJson> {
+    let fields = table_fields(proof)?;
+    let [tx_index, height, branch, ..] = fields.as_slice() else {
+        return None;
+    };
+
+    let count = field_u32(branch.get(..4)?)? as usize;
+    let hashes = &branch[4..];
+    if hashes.len() != count.checked_mul(32)? {
+        return None;
+    }
+
+    Some(BtcTxProofJson {
+        tx_index: field_u32(tx_index)?,
+        height: field_u32(height)?,
+        proof: hashes
+            .chunks(32)
+            .map(|hash| format!("0x{}", hex::encode(hash)))
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use ckb_types::prelude::{IntoTransactionView as _, Unpack as _};
+    use molecule::prelude::Builder as _;
+
+    use super::*;
+
+    #[test]
+    fn test_decode_lock_id() {
+        let out_index = 3u32;
+        let raw_txid = std::array::from_fn::<u8, 32, _>(|i| i as u8);
+
+        let lock = rgbpp::RGBPPLock::new_builder()
+            .out_index(blockchain::Uint32::new_unchecked(
+                out_index.to_le_bytes().to_vec().into(),
+            ))
+            .btc_txid(blockchain::Byte32::new_unchecked(raw_txid.to_vec().into()))
+            .build();
+
+        let (decoded_index, decoded_txid) = decode_lock_id(&lock.lock_id()).unwrap();
+
+        let mut expected_txid = raw_txid;
+        expected_txid.reverse();
+
+        assert_eq!(decoded_index, out_index);
+        assert_eq!(decoded_txid, expected_txid);
+    }
+
+    fn build_lock(out_index: u32) -> rgbpp::RGBPPLock {
+        rgbpp::RGBPPLock::new_builder()
+            .out_index(blockchain::Uint32::new_unchecked(
+                out_index.to_le_bytes().to_vec().into(),
+            ))
+            .btc_txid(blockchain::Byte32::new_unchecked(vec![7u8; 32].into()))
+            .build()
+    }
+
+    fn lock_output(code_hash: H256, lock: &rgbpp::RGBPPLock) -> CellOutput {
+        CellOutput {
+            capacity: 0.into(),
+            lock: Script {
+                code_hash,
+                hash_type: ckb_jsonrpc_types::ScriptHashType::Type,
+                args: JsonBytes::from_bytes(lock.as_bytes()),
+            },
+            type_: None,
+        }
+    }
+
+    #[test]
+    fn test_index_burns() {
+        let burn_lock = Script {
+            code_hash: H256::default(),
+            hash_type: ckb_jsonrpc_types::ScriptHashType::Data,
+            args: JsonBytes::default(),
+        };
+        let xudt = Script {
+            code_hash: H256([9u8; 32]),
+            hash_type: ckb_jsonrpc_types::ScriptHashType::Type,
+            args: JsonBytes::default(),
+        };
+
+        let mut tx: TransactionView = packed::Transaction::default().into_view().into();
+        tx.inner.outputs = vec![
+            lock_output(H256([1u8; 32]), &build_lock(0)),
+            CellOutput {
+                capacity: 200.into(),
+                lock: burn_lock.clone(),
+                type_: Some(xudt.clone()),
+            },
+        ];
+        tx.inner.outputs_data = vec![
+            JsonBytes::default(),
+            JsonBytes::from_vec(500u128.to_le_bytes().to_vec()),
+        ];
+
+        let ctx = RgbppContext::default().with_burn_locks(vec![burn_lock]);
+        let (op_sender, mut op_recv) = mpsc::unbounded_channel();
+        index_burns(&tx, &ctx, &op_sender).unwrap();
+
+        let Ok(Operations::UpsertBurn(burn)) = op_recv.try_recv() else {
+            panic!("expected a burn");
+        };
+        assert_eq!(burn.output_index, Set(1));
+        assert_eq!(burn.capacity, Set(200));
+        assert_eq!(
+            burn.type_script_hash,
+            Set(Some(script_hash(&xudt).to_vec()))
+        );
+        assert_eq!(burn.amount, Set(Some(BigDecimal::from(500))));
+        assert!(op_recv.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_type_script_hashes() {
+        let type_script = |args: &[u8]| Script {
+            code_hash: H256([9u8; 32]),
+            hash_type: ckb_jsonrpc_types::ScriptHashType::Type,
+            args: JsonBytes::from_vec(args.to_vec()),
+        };
+        let with_type = |args: &[u8]| CellOutput {
+            type_: Some(type_script(args)),
+            ..lock_output(H256::default(), &build_lock(0))
+        };
+
+        let outputs = vec![
+            with_type(&[1]),
+            lock_output(H256::default(), &build_lock(1)),
+            with_type(&[2]),
+            with_type(&[1]),
+        ];
+
+        let expected = [&[1u8][..], &[2]].map(|args| {
+            let hash: H256 = packed::Script::from(type_script(args))
+                .calc_script_hash()
+                .unpack();
+            hash.0
+        });
+        assert_eq!(type_script_hashes(&outputs), expected.to_vec());
+    }
+
+    #[test]
+    fn test_index_locks_script_version() {
+        let v1 = H256([1u8; 32]);
+        let v2 = H256([2u8; 32]);
+        let ctx = RgbppContext::new(vec![
+            RgbppScriptConfig {
+                version: 1,
+                code_hash: v1.clone(),
+                hash_type: ckb_jsonrpc_types::ScriptHashType::Type,
+                cell_dep: None,
+            },
+            RgbppScriptConfig {
+                version: 2,
+                code_hash: v2.clone(),
+                hash_type: ckb_jsonrpc_types::ScriptHashType::Type,
+                cell_dep: None,
+            },
+        ]);
+
+        // Same code hash as version 1, but a data hash type, so a different script.
+        let mut data_hash = lock_output(v1.clone(), &build_lock(3));
+        data_hash.lock.hash_type = ckb_jsonrpc_types::ScriptHashType::Data;
+
+        let outputs = vec![
+            lock_output(v1, &build_lock(0)),
+            lock_output(v2, &build_lock(1)),
+            lock_output(H256([3u8; 32]), &build_lock(2)),
+            data_hash,
+        ];
+        let cells = outputs
+            .iter()
+            .enumerate()
+            .map(|(index, output)| LockCell {
+                output,
+                tx: H256::default(),
+                index: index as u32,
+                spent_tx: None,
+                block_number: None,
+                spent_block_number: None,
+            })
+            .collect::<Vec<_>>();
+
+        let (op_sender, mut op_recv) = mpsc::unbounded_channel();
+        index_locks(&cells, &ctx, &op_sender).unwrap();
+        drop(op_sender);
+
+        let mut versions = Vec::new();
+        while let Ok(op) = op_recv.try_recv() {
+            match op {
+                Operations::UpsertLock(model) => {
+                    versions.push((model.out_index.unwrap(), model.script_version.unwrap()))
+                }
+                _ => unreachable!(),
+            }
+        }
+        versions.sort();
+
+        assert_eq!(versions, vec![(0, 1), (1, 2)]);
+    }
+
+    #[test]
+    fn test_is_rgbpp_tx() {
+        let v2 = H256([2u8; 32]);
+        let v2_dep = OutPoint {
+            tx_hash: H256([0xd2; 32]),
+            index: 0.into(),
+        };
+        let scripts = vec![
+            RgbppScriptConfig {
+                version: 1,
+                code_hash: H256([1u8; 32]),
+                hash_type: ckb_jsonrpc_types::ScriptHashType::Type,
+                cell_dep: Some(OutPoint {
+                    tx_hash: H256([0xd1; 32]),
+                    index: 0.into(),
+                }),
+            },
+            RgbppScriptConfig {
+                version: 2,
+                code_hash: v2.clone(),
+                hash_type: ckb_jsonrpc_types::ScriptHashType::Type,
+                cell_dep: Some(v2_dep.clone()),
+            },
+        ];
+        let plain = || -> TransactionView { packed::Transaction::default().into_view().into() };
+
+        // Spends a version 2 lock without creating one, seen through its cell dep.
+        let mut by_dep = plain();
+        by_dep.inner.cell_deps.push(CellDep {
+            out_point: v2_dep,
+            dep_type: ckb_jsonrpc_types::DepType::Code,
+        });
+        assert!(is_rgbpp_tx(&scripts, &by_dep, None));
+
+        let mut by_output = plain();
+        by_output
+            .inner
+            .outputs
+            .push(lock_output(v2.clone(), &build_lock(0)));
+        by_output.hash = H256([0xaa; 32]);
+        assert!(is_rgbpp_tx(&scripts, &by_output, None));
+
+        // Spends a version 2 lock created earlier in the block.
+        let mut by_input = plain();
+        by_input.inner.inputs.push(CellInput {
+            previous_output: OutPoint {
+                tx_hash: by_output.hash.clone(),
+                index: 0.into(),
+            },
+            since: 0.into(),
+        });
+        let block_outputs = BlockOutputs::new(&[by_output, by_input.clone()]);
+        assert!(is_rgbpp_tx(&scripts, &by_input, Some(&block_outputs)));
+        assert!(!is_rgbpp_tx(&scripts, &by_input, None));
+
+        let mut unrelated = plain();
+        unrelated
+            .inner
+            .outputs
+            .push(lock_output(H256([3u8; 32]), &build_lock(0)));
+        assert!(!is_rgbpp_tx(&scripts, &unrelated, Some(&block_outputs)));
+    }
+
+    #[test]
+    fn test_owner_lock_hash() {
+        let constants = Constants::Mainnet;
+        let owner = [5u8; 32];
+        let mut output = lock_output(H256([1u8; 32]), &build_lock(0));
+        assert_eq!(owner_lock_hash(&output, Some(constants)), None);
+
+        output.type_ = Some(Script {
+            args: JsonBytes::from_vec([&owner[..], &[0u8; 4]].concat()),
+            ..constants.xudttype_script()
+        });
+        let cell = LockCell {
+            output: &output,
+            tx: H256::default(),
+            index: 0,
+            spent_tx: None,
+            block_number: None,
+            spent_block_number: None,
+        };
+
+        let ctx = RgbppContext::default().with_constants(Some(constants));
+        let (op_sender, mut op_recv) = mpsc::unbounded_channel();
+        upsert_rgbpp_lock(op_sender, &build_lock(0), 1, &cell, &ctx).unwrap();
+
+        let Ok(Operations::UpsertLock(lock)) = op_recv.try_recv() else {
+            panic!("expected a lock");
+        };
+        assert_eq!(lock.owner_lock_hash, Set(Some(owner.to_vec())));
+        assert_eq!(lock.raw_args, Set(None));
+    }
+
+    #[test]
+    fn test_owner_lock_hash_ignores_other_types() {
+        let constants = Constants::Mainnet;
+        let mut output = lock_output(H256([1u8; 32]), &build_lock(0));
+
+        // Spore args are a 32-byte type id, not an owner.
+        output.type_ = Some(Script {
+            args: JsonBytes::from_vec(vec![5u8; 32]),
+            ..constants.spore_type_script()
+        });
+        assert_eq!(owner_lock_hash(&output, Some(constants)), None);
+
+        output.type_ = Some(Script {
+            args: JsonBytes::from_vec(vec![5u8; 32]),
+            ..constants.xudttype_script()
+        });
+        assert_eq!(owner_lock_hash(&output, None), None);
+    }
+
+    #[test]
+    fn test_raw_args() {
+        let code_hash = H256([1u8; 32]);
+        let ctx = RgbppContext::new(vec![RgbppScriptConfig {
+            version: 1,
+            code_hash: code_hash.clone(),
+            hash_type: ckb_jsonrpc_types::ScriptHashType::Type,
+            cell_dep: None,
+        }])
+        .with_raw_args(true);
+
+        let output = lock_output(code_hash, &build_lock(5));
+        let raw = output.lock.args.as_bytes().to_vec();
+        let cells = [LockCell {
+            output: &output,
+            tx: H256::default(),
+            index: 0,
+            spent_tx: None,
+            block_number: None,
+            spent_block_number: None,
+        }];
+
+        let (op_sender, mut op_recv) = mpsc::unbounded_channel();
+        index_locks(&cells, &ctx, &op_sender).unwrap();
+
+        let Ok(Operations::UpsertLock(lock)) = op_recv.try_recv() else {
+            panic!("expected a lock");
+        };
+        assert_eq!(lock.raw_args, Set(Some(raw)));
+    }
+
+    #[test]
+    fn test_cells_filtered_total() {
+        let code_hash = H256([1u8; 32]);
+        let ctx = RgbppContext::new(vec![RgbppScriptConfig {
+            version: 1,
+            code_hash: code_hash.clone(),
+            hash_type: ckb_jsonrpc_types::ScriptHashType::Type,
+            cell_dep: None,
+        }]);
+
+        let mut malformed = lock_output(code_hash.clone(), &build_lock(1));
+        malformed.lock.args = JsonBytes::from_vec(vec![0u8; 3]);
+        let outputs = vec![
+            lock_output(code_hash, &build_lock(0)),
+            lock_output(H256([3u8; 32]), &build_lock(1)),
+            lock_output(H256([4u8; 32]), &build_lock(2)),
+            malformed,
+        ];
+        let cells = outputs
+            .iter()
+            .map(|output| LockCell {
+                output,
+                tx: H256::default(),
+                index: 0,
+                spent_tx: None,
+                block_number: None,
+                spent_block_number: None,
+            })
+            .collect::<Vec<_>>();
+
+        let (op_sender, _op_recv) = mpsc::unbounded_channel();
+        index_locks(&cells, &ctx, &op_sender).unwrap();
+
+        assert_eq!(ctx.stats.cells_filtered_total(FilterReason::Allowlist), 2);
+        assert_eq!(ctx.stats.cells_filtered_total(FilterReason::Decode), 1);
+        assert_eq!(ctx.stats.cells_filtered_total(FilterReason::Predicate), 0);
+    }
+
+    #[test]
+    fn test_parse_stats_by_version() {
+        let (v1, v2) = (H256([1u8; 32]), H256([2u8; 32]));
+        let ctx = RgbppContext::new(vec![
+            RgbppScriptConfig {
+                version: 1,
+                code_hash: v1.clone(),
+                hash_type: ckb_jsonrpc_types::ScriptHashType::Type,
+                cell_dep: None,
+            },
+            RgbppScriptConfig {
+                version: 2,
+                code_hash: v2.clone(),
+                hash_type: ckb_jsonrpc_types::ScriptHashType::Type,
+                cell_dep: None,
+            },
+        ]);
+
+        let witness = blockchain::WitnessArgs::new_builder()
+            .lock(
+                blockchain::BytesOpt::new_builder()
+                    .set(Some(molecule_bytes(build_unlock(&[]).as_slice())))
+                    .build(),
+            )
+            .build();
+        let mut tx: TransactionView = packed::Transaction::default().into_view().into();
+        tx.inner.inputs.push(CellInput {
+            previous_output: ckb_jsonrpc_types::OutPoint {
+                tx_hash: H256([8u8; 32]),
+                index: 0.into(),
+            },
+            since: 0.into(),
+        });
+        tx.inner
+            .witnesses
+            .push(JsonBytes::from_bytes(witness.as_bytes()));
+        tx.inner.outputs = vec![
+            lock_output(v1.clone(), &build_lock(0)),
+            lock_output(v1, &build_lock(1)),
+            lock_output(v2.clone(), &build_lock(2)),
+        ];
+        let pre_outputs = vec![Some(lock_output(v2, &build_lock(7)))];
+
+        let (op_sender, mut op_recv) = mpsc::unbounded_channel();
+        parse_rgbpp_tx(&tx, &pre_outputs, Some(42), &ctx, &op_sender).unwrap();
+
+        assert_eq!(
+            ctx.stats.by_version(),
+            BTreeMap::from([
+                (
+                    1,
+                    VersionCounts {
+                        locks: 2,
+                        unlocks: 0
+                    }
+                ),
+                (
+                    2,
+                    VersionCounts {
+                        locks: 1,
+                        unlocks: 1
+                    }
+                ),
+            ])
+        );
+        while let Ok(op) = op_recv.try_recv() {
+            match op {
+                Operations::UpsertLock(lock) => assert_eq!(lock.block_number, Set(Some(42))),
+                Operations::SpendLock(lock) => {
+                    assert_eq!(lock.block_number, Set(None));
+                    assert_eq!(lock.spent_block_number, Set(Some(42)));
+                }
+                Operations::UpsertUnlock(unlock) => {
+                    assert_eq!(unlock.block_number, Set(Some(42)))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_index_locks_empty_args() {
+        let code_hash = H256([1u8; 32]);
+        let ctx = RgbppContext::new(vec![RgbppScriptConfig {
+            version: 1,
+            code_hash: code_hash.clone(),
+            hash_type: ckb_jsonrpc_types::ScriptHashType::Type,
+            cell_dep: None,
+        }])
+        .with_decode_error_hook(Box::new(|error, _| panic!("decoded empty args: {error:?}")));
+
+        let mut outputs = vec![
+            lock_output(code_hash, &build_lock(0)),
+            lock_output(H256([3u8; 32]), &build_lock(1)),
+        ];
+        outputs
+            .iter_mut()
+            .for_each(|output| output.lock.args = JsonBytes::default());
+        let cells = outputs
+            .iter()
+            .map(|output| LockCell {
+                output,
+                tx: H256::default(),
+                index: 0,
+                spent_tx: None,
+                block_number: None,
+                spent_block_number: None,
+            })
+            .collect::<Vec<_>>();
+
+        let (op_sender, mut op_recv) = mpsc::unbounded_channel();
+        index_locks(&cells, &ctx, &op_sender).unwrap();
+
+        assert_eq!(ctx.stats.cells_filtered_total(FilterReason::EmptyArgs), 2);
+        assert_eq!(ctx.stats.cells_filtered_total(FilterReason::Allowlist), 0);
+        assert_eq!(ctx.stats.cells_filtered_total(FilterReason::Decode), 0);
+        assert!(op_recv.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_index_locks_output_filter() {
+        let code_hash = H256([1u8; 32]);
+        let ctx = RgbppContext::new(vec![RgbppScriptConfig {
+            version: 1,
+            code_hash: code_hash.clone(),
+            hash_type: ckb_jsonrpc_types::ScriptHashType::Type,
+            cell_dep: None,
+        }])
+        .with_output_filter(Box::new(|output| output.capacity.value() >= 1000));
+
+        let outputs = [(0, 999u64), (1, 1000), (2, 5000)]
+            .into_iter()
+            .map(|(i, capacity)| CellOutput {
+                capacity: capacity.into(),
+                ..lock_output(code_hash.clone(), &build_lock(i))
+            })
+            .collect::<Vec<_>>();
+        let mut cells = outputs
+            .iter()
+            .enumerate()
+            .map(|(index, output)| LockCell {
+                output,
+                tx: H256::default(),
+                index: index as u32,
+                spent_tx: None,
+                block_number: None,
+                spent_block_number: None,
+            })
+            .collect::<Vec<_>>();
+        // input-side cells are never filtered
+        cells.push(LockCell {
+            output: &outputs[0],
+            tx: H256::default(),
+            index: 0,
+            spent_tx: Some(H256::default()),
+            block_number: None,
+            spent_block_number: None,
+        });
+
+        let (op_sender, mut op_recv) = mpsc::unbounded_channel();
+        index_locks(&cells, &ctx, &op_sender).unwrap();
+        drop(op_sender);
+
+        let mut created = Vec::new();
+        let mut spent = 0;
+        while let Ok(op) = op_recv.try_recv() {
+            match op {
+                Operations::UpsertLock(model) => created.push(model.out_index.unwrap()),
+                Operations::SpendLock(_) => spent += 1,
+                _ => unreachable!(),
+            }
+        }
+        created.sort();
+
+        assert_eq!(created, vec![1, 2]);
+        assert_eq!(spent, 1);
+    }
+
+    #[test]
+    fn test_index_locks_spent() {
+        let code_hash = H256([1u8; 32]);
+        let ctx = RgbppContext::new(vec![RgbppScriptConfig {
+            version: 1,
+            code_hash: code_hash.clone(),
+            hash_type: ckb_jsonrpc_types::ScriptHashType::Type,
+            cell_dep: None,
+        }]);
+
+        let output = lock_output(code_hash, &build_lock(0));
+        let cells = [LockCell {
+            output: &output,
+            tx: H256([8u8; 32]),
+            index: 2,
+            spent_tx: Some(H256([9u8; 32])),
+            block_number: None,
+            spent_block_number: None,
+        }];
+
+        let (op_sender, mut op_recv) = mpsc::unbounded_channel();
+        index_locks(&cells, &ctx, &op_sender).unwrap();
+
+        match op_recv.try_recv().unwrap() {
+            Operations::SpendLock(model) => {
+                assert_eq!(model.tx.unwrap(), vec![8u8; 32]);
+                assert_eq!(model.output_index.unwrap(), 2);
+                assert_eq!(model.spent_tx.unwrap(), Some(vec![9u8; 32]));
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_intra_block_spend() {
+        let code_hash = H256([1u8; 32]);
+        let ctx = RgbppContext::new(vec![RgbppScriptConfig {
+            version: 1,
+            code_hash: code_hash.clone(),
+            hash_type: ckb_jsonrpc_types::ScriptHashType::Type,
+            cell_dep: None,
+        }]);
+        let input = |tx_hash: H256, index: u32| CellInput {
+            previous_output: OutPoint {
+                tx_hash,
+                index: index.into(),
+            },
+            since: 0.into(),
+        };
+
+        let mut created: TransactionView = packed::Transaction::default().into_view().into();
+        created.hash = H256([0xaa; 32]);
+        created
+            .inner
+            .outputs
+            .push(lock_output(code_hash, &build_lock(0)));
+        let mut spending: TransactionView = packed::Transaction::default().into_view().into();
+        spending.hash = H256([0xbb; 32]);
+        spending.inner.inputs = vec![input(created.hash.clone(), 0)];
+        let block_outputs = BlockOutputs::new(&[created.clone(), spending.clone()]);
+
+        let pre_outputs =
+            resolve_pre_outputs(&spending.inner.inputs, Some(&block_outputs), |_| async {
+                unreachable!("the spent output is in the block")
+            })
+            .await
+            .unwrap();
+        assert_eq!(pre_outputs, vec![Some(created.inner.outputs[0].clone())]);
+
+        let (op_sender, mut op_recv) = mpsc::unbounded_channel();
+        parse_rgbpp_tx(&spending, &pre_outputs, Some(42), &ctx, &op_sender).unwrap();
+        drop(op_sender);
+        let mut spent = Vec::new();
+        while let Ok(op) = op_recv.try_recv() {
+            if let Operations::SpendLock(lock) = op {
+                spent.push((lock.tx.unwrap(), lock.spent_tx.unwrap()));
+            }
+        }
+        assert_eq!(spent, vec![(vec![0xaa; 32], Some(vec![0xbb; 32]))]);
+
+        // Only inputs from outside the block are fetched.
+        let outside = input(H256([0xcc; 32]), 1);
+        let inputs = [outside.clone(), input(created.hash, 0)];
+        let pre_outputs =
+            resolve_pre_outputs(&inputs, Some(&block_outputs), |fetched| async move {
+                assert_eq!(fetched, vec![outside]);
+                Ok(vec![None])
+            })
+            .await
+            .unwrap();
+        assert_eq!(pre_outputs[0], None);
+        assert!(pre_outputs[1].is_some());
+    }
+
+    #[test]
+    fn test_index_referenced_locks() {
+        let code_hash = H256([1u8; 32]);
+        let ctx = RgbppContext::new(vec![RgbppScriptConfig {
+            version: 1,
+            code_hash: code_hash.clone(),
+            hash_type: ckb_jsonrpc_types::ScriptHashType::Type,
+            cell_dep: None,
+        }])
+        .with_cell_deps(true);
+
+        let lock = build_lock(5);
+        let cell_deps = vec![
+            CellDep {
+                out_point: ckb_jsonrpc_types::OutPoint {
+                    tx_hash: H256([4u8; 32]),
+                    index: 1.into(),
+                },
+                dep_type: ckb_jsonrpc_types::DepType::Code,
+            },
+            CellDep {
+                out_point: ckb_jsonrpc_types::OutPoint {
+                    tx_hash: H256([5u8; 32]),
+                    index: 0.into(),
+                },
+                dep_type: ckb_jsonrpc_types::DepType::Code,
+            },
+        ];
+        let dep_outputs = vec![
+            Some(lock_output(code_hash, &lock)),
+            Some(lock_output(H256([3u8; 32]), &build_lock(6))),
+        ];
+
+        let (op_sender, mut op_recv) = mpsc::unbounded_channel();
+        index_referenced_locks(&cell_deps, &dep_outputs, &ctx, &H256([9u8; 32]), &op_sender)
+            .unwrap();
+
+        match op_recv.try_recv().unwrap() {
+            Operations::UpsertReferencedLock(model) => {
+                assert_eq!(model.lock_id.unwrap(), lock.lock_id());
+                assert_eq!(model.tx.unwrap(), vec![9u8; 32]);
+                assert_eq!(model.dep_tx.unwrap(), vec![4u8; 32]);
+                assert_eq!(model.dep_index.unwrap(), 1);
+            }
+            _ => unreachable!(),
+        }
+        assert!(op_recv.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_index_unlocks_empty_witnesses() {
+        let code_hash = H256([1u8; 32]);
+        let ctx = RgbppContext::new(vec![RgbppScriptConfig {
+            version: 1,
+            code_hash: code_hash.clone(),
+            hash_type: ckb_jsonrpc_types::ScriptHashType::Type,
+            cell_dep: None,
+        }]);
+
+        let pre_outputs = vec![Some(lock_output(code_hash, &build_lock(0))); 3];
+        let witnesses = vec![JsonBytes::default(); 3];
+
+        let (op_sender, mut op_recv) = mpsc::unbounded_channel();
+        index_unlocks(
+            &witnesses,
+            &pre_outputs,
+            &ctx,
+            &H256::default(),
+            None,
+            &op_sender,
+        )
+        .unwrap();
+
+        assert_eq!(ctx.stats.empty_witnesses.load(Ordering::Relaxed), 3);
+        assert!(op_recv.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_decode_error_hook() {
+        let code_hash = H256([1u8; 32]);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let hook_seen = seen.clone();
+        let ctx = RgbppContext::new(vec![RgbppScriptConfig {
+            version: 1,
+            code_hash: code_hash.clone(),
+            hash_type: ckb_jsonrpc_types::ScriptHashType::Type,
+            cell_dep: None,
+        }])
+        .with_decode_error_hook(Box::new(move |error, context| {
+            hook_seen.lock().unwrap().push((error, context.clone()));
+        }));
+
+        let pre_outputs = vec![Some(lock_output(code_hash, &build_lock(0))); 2];
+        let witnesses = vec![JsonBytes::default(), JsonBytes::from_vec(vec![1, 2, 3])];
+
+        let (op_sender, mut op_recv) = mpsc::unbounded_channel();
+        index_unlocks(
+            &witnesses,
+            &pre_outputs,
+            &ctx,
+            &H256([9u8; 32]),
+            None,
+            &op_sender,
+        )
+        .unwrap();
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 1);
+        assert!(matches!(seen[0].0, DecodeError::WitnessArgs(_)));
+        assert_eq!(
+            seen[0].1,
+            DecodeContext {
+                tx: H256([9u8; 32]),
+                index: 1,
+            }
+        );
+        assert!(op_recv.try_recv().is_err());
+    }
+
+    fn build_proof(tx_index: u32, height: u32) -> Vec<u8> {
+        let fields = [
+            tx_index.to_le_bytes().to_vec(),
+            height.to_le_bytes().to_vec(),
+            vec![0u8; 4],
+        ];
+        let header_size = 4 + 4 * fields.len();
+        let total_size = header_size + fields.iter().map(Vec::len).sum::<usize>();
+
+        let mut proof = (total_size as u32).to_le_bytes().to_vec();
+        let mut offset = header_size;
+        for field in &fields {
+            proof.extend((offset as u32).to_le_bytes());
+            offset += field.len();
+        }
+        fields.iter().for_each(|field| proof.extend(field));
+        proof
+    }
+
+    #[test]
+    fn test_decode_btc_timelocks() {
+        // Two inputs, the first signalling RBF, and an OP_RETURN output.
+        let input =
+            |sequence: u32| [vec![0x11; 36], vec![0x00], sequence.to_le_bytes().to_vec()].concat();
+        let output = [vec![0x00; 8], vec![0x01, 0x6a]].concat();
+        let lock_time = 840_000u32.to_le_bytes().to_vec();
+        let legacy = [
+            2u32.to_le_bytes().to_vec(),
+            vec![0x02],
+            input(0xffff_fffd),
+            input(0xffff_ffff),
+            vec![0x01],
+            output.clone(),
+            lock_time.clone(),
+        ]
+        .concat();
+
+        let expected = BtcTimelocks {
+            lock_time: 840_000,
+            sequences: vec![0xffff_fffd, 0xffff_ffff],
+        };
+        assert_eq!(decode_btc_timelocks(&legacy), Some(expected.clone()));
+
+        // The same transaction with a witness of one 3 byte item per input.
+        let segwit = [
+            2u32.to_le_bytes().to_vec(),
+            vec![0x00, 0x01, 0x02],
+            input(0xffff_fffd),
+            input(0xffff_ffff),
+            vec![0x01],
+            output,
+            vec![0x01, 0x03, 0xaa, 0xbb, 0xcc].repeat(2),
+            lock_time,
+        ]
+        .concat();
+        assert_eq!(decode_btc_timelocks(&segwit), Some(expected));
+
+        assert_eq!(decode_btc_timelocks(&legacy[..legacy.len() - 1]), None);
+        assert_eq!(
+            decode_btc_timelocks(&[legacy.clone(), vec![0]].concat()),
+            None
+        );
+        assert_eq!(decode_btc_timelocks(&[0xab, 0xcd]), None);
+    }
+
+    #[test]
+    fn test_btc_proof_height() {
+        assert_eq!(btc_proof_height(&build_proof(1, 840_000)), Some(840_000));
+        assert_eq!(btc_proof_height(&[0u8; 3]), None);
+        assert_eq!(btc_proof_height(&[]), None);
+    }
+
+    #[test]
+    fn test_btc_confirmations() {
+        let confirmations = BtcConfirmations {
+            tip: 105,
+            required: 6,
+        };
+
+        assert!(confirmations.is_confirmed(90));
+        assert!(confirmations.is_confirmed(100));
+        assert!(!confirmations.is_confirmed(103));
+        assert!(!confirmations.is_confirmed(110));
+    }
+
+    #[test]
+    fn test_decode_lock_id_invalid() {
+        assert!(decode_lock_id(&[0u8; 4]).is_err());
+    }
+
+    fn commitment_unlock(btc_tx: &[u8], input_len: u8, output_len: u8) -> rgbpp::RGBPPUnlock {
+        rgbpp::RGBPPUnlock::new_builder()
+            .extra_data(
+                rgbpp::ExtraCommitmentData::new_builder()
+                    .input_len(molecule::prelude::Byte::new(input_len))
+                    .output_len(molecule::prelude::Byte::new(output_len))
+                    .build(),
+            )
+            .btc_tx(molecule_bytes(btc_tx))
+            .build()
+    }
+
+    #[test]
+    fn test_compute_rgbpp_commitment() {
+        let mut tx: TransactionView = packed::Transaction::default().into_view().into();
+        tx.inner.inputs.push(CellInput {
+            previous_output: ckb_jsonrpc_types::OutPoint {
+                tx_hash: H256([1u8; 32]),
+                index: 0.into(),
+            },
+            since: 0.into(),
+        });
+        tx.inner.outputs.push(CellOutput {
+            capacity: 100.into(),
+            lock: Script {
+                code_hash: H256([2u8; 32]),
+                hash_type: ckb_jsonrpc_types::ScriptHashType::Type,
+                args: JsonBytes::from_vec(vec![1, 2]),
+            },
+            type_: None,
+        });
+        tx.inner
+            .outputs_data
+            .push(JsonBytes::from_vec(vec![0xab, 0xcd]));
+
+        let unlock = commitment_unlock(&[0xab, 0xcd], 1, 1);
+        assert_eq!(
+            compute_rgbpp_commitment(&tx, &unlock, &[]).unwrap(),
+            hex_literal::hex!("509ee11446e1980e0302eef69a06b16c9a96bbf0cabfb11ef9329dbe1c81d1f4")
+        );
+        // Outputs beyond the unlock's `output_len` are not committed to.
+        let unlock = commitment_unlock(&[0xab, 0xcd], 1, 0);
+        let committed = compute_rgbpp_commitment(&tx, &unlock, &[]).unwrap();
+        tx.inner.outputs_data[0] = JsonBytes::default();
+        assert_eq!(
+            compute_rgbpp_commitment(&tx, &unlock, &[]).unwrap(),
+            committed
+        );
+        // More than the tx has cannot be what the BTC tx committed to.
+        let unlock = commitment_unlock(&[0xab, 0xcd], 2, 0);
+        assert!(compute_rgbpp_commitment(&tx, &unlock, &[]).is_err());
+    }
+
+    #[test]
+    fn test_compute_rgbpp_commitment_placeholder() {
+        let code_hash = H256([3u8; 32]);
+        let scripts = [RgbppScriptConfig {
+            version: 1,
+            code_hash: code_hash.clone(),
+            hash_type: ckb_jsonrpc_types::ScriptHashType::Type,
+            cell_dep: None,
+        }];
+        let btc_tx = [0x02, 0x00, 0x00, 0x00];
+        let mut own_txid = btc_txid(&btc_tx);
+        own_txid.reverse();
+        let bound = |txid: [u8; 32]| {
+            let lock = rgbpp::RGBPPLock::new_builder()
+                .out_index(blockchain::Uint32::new_unchecked(
+                    1u32.to_le_bytes().to_vec().into(),
+                ))
+                .btc_txid(blockchain::Byte32::new_unchecked(txid.to_vec().into()))
+                .build();
+            let mut tx: TransactionView = packed::Transaction::default().into_view().into();
+            tx.inner.outputs.push(lock_output(code_hash.clone(), &lock));
+            tx.inner.outputs_data.push(JsonBytes::default());
+            tx
+        };
+        let unlock = commitment_unlock(&btc_tx, 0, 1);
+        let virtual_tx = compute_rgbpp_commitment(&bound([0u8; 32]), &unlock, &scripts).unwrap();
+
+        // Change back to the BTC tx itself is committed as the placeholder.
+        assert_eq!(
+            compute_rgbpp_commitment(&bound(own_txid), &unlock, &scripts).unwrap(),
+            virtual_tx
+        );
+        assert_ne!(
+            compute_rgbpp_commitment(&bound([9u8; 32]), &unlock, &scripts).unwrap(),
+            virtual_tx
+        );
+        // Only RGBPP lock scripts are rewritten.
+        assert_ne!(
+            compute_rgbpp_commitment(&bound(own_txid), &unlock, &[]).unwrap(),
+            compute_rgbpp_commitment(&bound([0u8; 32]), &unlock, &[]).unwrap()
+        );
+    }
+
+    fn molecule_bytes(raw: &[u8]) -> blockchain::Bytes {
+        let mut bytes = (raw.len() as u32).to_le_bytes().to_vec();
+        bytes.extend(raw);
+        blockchain::Bytes::new_unchecked(bytes.into())
+    }
+
+    fn build_unlock(btc_tx_proof: &[u8]) -> rgbpp::RGBPPUnlock {
+        rgbpp::RGBPPUnlock::new_builder()
+            .version(rgbpp::Uint16::new_unchecked(
+                1u16.to_le_bytes().to_vec().into(),
+            ))
+            .extra_data(
+                rgbpp::ExtraCommitmentData::new_builder()
+                    .input_len(molecule::prelude::Byte::new(2))
+                    .output_len(molecule::prelude::Byte::new(3))
+                    .build(),
+            )
+            .btc_tx(molecule_bytes(&[0xab, 0xcd]))
+            .btc_tx_proof(molecule_bytes(btc_tx_proof))
+            .build()
+    }
+
+    #[test]
+    fn test_btc_tx_proof_json() {
+        let unlock = build_unlock(&build_proof(1, 840_000));
+        let (op_sender, mut op_recv) = mpsc::unbounded_channel();
+
+        let ctx = RgbppContext::default().with_btc_tx_proof_json(true);
+        upsert_rgbpp_unlock(op_sender.clone(), &unlock, 1, &ctx, H256::default(), None).unwrap();
+        let Ok(Operations::UpsertUnlock(model)) = op_recv.try_recv() else {
+            panic!("expected an unlock");
+        };
+        assert_eq!(
+            model.btc_tx_proof_json,
+            Set(Some(serde_json::json!({
+                "tx_index": 1,
+                "height": 840_000,
+                "proof": [],
+            })))
+        );
+
+        upsert_rgbpp_unlock(
+            op_sender,
+            &unlock,
+            1,
+            &RgbppContext::default(),
+            H256::default(),
+            None,
+        )
+        .unwrap();
+        let Ok(Operations::UpsertUnlock(model)) = op_recv.try_recv() else {
+            panic!("expected an unlock");
+        };
+        assert_eq!(model.btc_tx_proof_json, Set(None));
+    }
+
+    #[test]
+    fn test_decode_btc_tx_proof() {
+        assert_eq!(decode_btc_tx_proof(&build_proof(3, 7)).unwrap().tx_index, 3);
+        assert_eq!(decode_btc_tx_proof(&[0u8; 4]), None);
+    }
+
+    #[test]
+    fn test_unlock_json() {
+        let unlock = build_unlock(&[0x01]);
+
+        let expected = serde_json::json!({
+            "version": 1,
+            "input_len": 2,
+            "output_len": 3,
+            "btc_tx": "0xabcd",
+            "btc_tx_proof": "0x01",
+        });
+        assert_eq!(
+            serde_json::to_value(RgbppUnlockJson::from(&unlock)).unwrap(),
+            expected
+        );
+
+        let model = rgbpp_unlocks::Model {
+            unlock_id: unlock.unlock_id(),
+            tx: vec![0; 32],
+            version: 1,
+            input_len: 2,
+            output_len: 3,
+            btc_tx: unlock.btc_tx().as_bytes().to_vec(),
+            btc_tx_proof: unlock.btc_tx_proof().as_bytes().to_vec(),
+            script_version: 1,
+            btc_block_height: None,
+            btc_confirmed: None,
+            btc_tx_proof_json: None,
+            btc_txid: None,
+            block_number: None,
+        };
+        assert_eq!(
+            serde_json::to_value(RgbppUnlockJson::try_from(&model).unwrap()).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_parse_panic_quarantines_tx() {
+        use ckb_types::prelude::*;
+
+        let code_hash = H256([1u8; 32]);
+        let ctx = RgbppContext::new(vec![RgbppScriptConfig {
+            version: 1,
+            code_hash: code_hash.clone(),
+            hash_type: ckb_jsonrpc_types::ScriptHashType::Type,
+            cell_dep: None,
+        }])
+        .with_output_filter(Box::new(|_| panic!("filter exploded")));
+
+        let mut tx: TransactionView = packed::Transaction::default().into_view().into();
+        tx.hash = H256([8u8; 32]);
+        tx.inner
+            .outputs
+            .push(lock_output(code_hash, &build_lock(0)));
+
+        let (op_sender, mut op_recv) = mpsc::unbounded_channel();
+        parse_rgbpp_tx_isolated(&tx, &[], Some(7), &ctx, &op_sender).unwrap();
+
+        match op_recv.try_recv().unwrap() {
+            Operations::UpsertDeadLetter(letter) => {
+                assert_eq!(letter.tx.unwrap(), vec![8u8; 32]);
+                assert_eq!(letter.block_number.unwrap(), Some(7));
+                assert_eq!(letter.reason.unwrap(), "filter exploded");
+            }
+            _ => unreachable!(),
+        }
+        assert!(op_recv.try_recv().is_err());
+    }
+}
@@ -0,0 +1,440 @@
+use std::collections::{HashMap, HashSet};
+
+use sea_orm::{
+    ColumnTrait as _, ColumnType, ConnectionTrait as _, DbConn, DbErr, EntityTrait,
+    IdenStatic as _, Iterable as _, Statement,
+};
+
+use crate::entity::{
+    addresses, block_height, btc_tx_inputs, btc_txs, burns, changes, clusters, dead_letters,
+    indexed_events, lock_accumulator, pending_rgbpp_locks, rgbpp_locks, rgbpp_referenced_locks,
+    rgbpp_unlocks, spore_actions, spores, token_info, transaction_outputs_status, tx_type_scripts,
+    xudt_cell,
+};
+
+/// The `udt_name` Postgres reports for a column of `column_type`.
+fn udt_name(column_type: &ColumnType) -> String {
+    match column_type {
+        ColumnType::String(_) => "varchar".into(),
+        ColumnType::Text => "text".into(),
+        ColumnType::SmallInteger => "int2".into(),
+        ColumnType::Integer => "int4".into(),
+        ColumnType::BigInteger => "int8".into(),
+        ColumnType::Decimal(_) => "numeric".into(),
+        ColumnType::DateTime | ColumnType::Timestamp => "timestamp".into(),
+        ColumnType::Binary(_) | ColumnType::VarBinary(_) => "bytea".into(),
+        ColumnType::Boolean => "bool".into(),
+        ColumnType::Json => "json".into(),
+        ColumnType::JsonBinary => "jsonb".into(),
+        ColumnType::Enum { name, .. } => name.to_string(),
+        ColumnType::Array(element) => format!("_{}", udt_name(element)),
+        other => format!("{other:?}"),
+    }
+}
+
+fn expected_table<E: EntityTrait>() -> (String, Vec<(String, String)>) {
+    let columns = E::Column::iter()
+        .map(|column| {
+            (
+                column.as_str().to_owned(),
+                udt_name(column.def().get_column_type()),
+            )
+        })
+        .collect();
+    (E::default().table_name().to_owned(), columns)
+}
+
+/// Tables and `(column, udt_name)` pairs of the entities the indexer writes and reads.
+fn expected_tables() -> Vec<(String, Vec<(String, String)>)> {
+    macro_rules! tables {
+        ($($entity:ident),* $(,)?) => {
+            vec![$(expected_table::<$entity::Entity>()),*]
+        };
+    }
+
+    tables![
+        addresses,
+        clusters,
+        spores,
+        spore_actions,
+        rgbpp_locks,
+        rgbpp_unlocks,
+        rgbpp_referenced_locks,
+        block_height,
+        xudt_cell,
+        transaction_outputs_status,
+        token_info,
+        tx_type_scripts,
+        burns,
+        lock_accumulator,
+        pending_rgbpp_locks,
+        dead_letters,
+        btc_txs,
+        btc_tx_inputs,
+        changes,
+        indexed_events,
+    ]
+}
+
+/// Indexes the migrations create. Entities do not describe indexes, so unlike the
+/// tables these are listed by hand, the tests check them against the migrations.
+const EXPECTED_INDEXES: &[&str] = &[
+    "idx_clusters_owner_address",
+    "idx_spores_cluster_id",
+    "idx_spores_owner_address",
+    "idx_spore_actions_spore_id",
+    "idx_spore_actions_cluster_id",
+    "idx_spore_actions_from_address_id",
+    "idx_spore_actions_to_address_id",
+    "idx_xudt_cell_lock_id",
+    "idx_xudt_cell_type_id",
+    "idx_token_info_type_id",
+    "idx_rgbpp_locks_spent_tx",
+    "idx_rgbpp_locks_btc_txid",
+    "idx_rgbpp_referenced_locks_tx",
+    "idx_indexed_events_height",
+    "idx_tx_type_scripts_type_script_hash",
+    "idx_burns_type_script_hash",
+    "idx_rgbpp_unlocks_btc_txid",
+    "idx_rgbpp_locks_owner_lock_hash",
+    "idx_rgbpp_locks_block_number",
+    "idx_rgbpp_unlocks_block_number",
+    "idx_rgbpp_locks_out_index",
+    "idx_changes_height",
+    "idx_changes_table_name_key",
+    "idx_rgbpp_locks_lock_id",
+    "idx_rgbpp_locks_spent_block_number",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnMismatch {
+    pub table: String,
+    pub column: String,
+    pub expected: String,
+    pub found: String,
+}
+
+/// Differences between the live database and the schema the entities and migrations define.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SchemaReport {
+    pub missing_tables: Vec<String>,
+    pub missing_columns: Vec<(String, String)>,
+    pub mismatched_columns: Vec<ColumnMismatch>,
+    pub missing_indexes: Vec<String>,
+}
+
+impl SchemaReport {
+    pub fn is_clean(&self) -> bool {
+        self.missing_tables.is_empty()
+            && self.missing_columns.is_empty()
+            && self.mismatched_columns.is_empty()
+            && self.missing_indexes.is_empty()
+    }
+}
+
+/// Check every expected table, column type and index exists in the current schema.
+pub async fn verify_schema(db: &DbConn) -> Result<SchemaReport, DbErr> {
+    let backend = db.get_database_backend();
+
+    let columns = db
+        .query_all(Statement::from_string(
+            backend,
+            "SELECT table_name, column_name, udt_name FROM information_schema.columns \
+             WHERE table_schema = current_schema()",
+        ))
+        .await?
+        .into_iter()
+        .map(|row| {
+            Ok((
+                (
+                    row.try_get::<String>("", "table_name")?,
+                    row.try_get::<String>("", "column_name")?,
+                ),
+                row.try_get::<String>("", "udt_name")?,
+            ))
+        })
+        .collect::<Result<HashMap<_, _>, DbErr>>()?;
+
+    let indexes = db
+        .query_all(Statement::from_string(
+            backend,
+            "SELECT indexname FROM pg_indexes WHERE schemaname = current_schema()",
+        ))
+        .await?
+        .into_iter()
+        .map(|row| row.try_get::<String>("", "indexname"))
+        .collect::<Result<HashSet<_>, DbErr>>()?;
+
+    let tables = columns
+        .keys()
+        .map(|(table, _)| table.as_str())
+        .collect::<HashSet<_>>();
+
+    let mut report = SchemaReport::default();
+
+    for (table, expected_columns) in expected_tables() {
+        if !tables.contains(table.as_str()) {
+            report.missing_tables.push(table);
+            continue;
+        }
+
+        for (column, expected) in expected_columns {
+            match columns.get(&(table.clone(), column.to_string())) {
+                None => report
+                    .missing_columns
+                    .push((table.clone(), column.to_string())),
+                Some(found) if *found != expected => {
+                    report.mismatched_columns.push(ColumnMismatch {
+                        table: table.clone(),
+                        column: column.to_string(),
+                        expected,
+                        found: found.clone(),
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    report.missing_indexes = EXPECTED_INDEXES
+        .iter()
+        .filter(|index| !indexes.contains(**index))
+        .map(|index| index.to_string())
+        .collect();
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use sea_orm::{DatabaseBackend, MockDatabase, Value};
+
+    use super::*;
+
+    /// What the catalog holds after running every up migration in order.
+    #[derive(Debug, Default)]
+    struct Migrated {
+        /// `udt_name` by `(table, column)`.
+        columns: BTreeMap<(String, String), String>,
+        indexes: HashSet<String>,
+    }
+
+    /// Split `list` on the commas outside parentheses.
+    fn split_top_level(list: &str) -> Vec<&str> {
+        let mut parts = Vec::new();
+        let (mut depth, mut start) = (0, 0);
+        for (i, c) in list.char_indices() {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                ',' if depth == 0 => {
+                    parts.push(list[start..i].trim());
+                    start = i + 1;
+                }
+                _ => {}
+            }
+        }
+        parts.push(list[start..].trim());
+        parts
+    }
+
+    /// The `udt_name` of a column declared as `TYPE [constraints]`.
+    fn declared_udt_name(declaration: &[&str]) -> String {
+        let declared = declaration
+            .iter()
+            .take_while(|word| {
+                !matches!(
+                    word.to_uppercase().as_str(),
+                    "NOT" | "NULL" | "PRIMARY" | "REFERENCES" | "DEFAULT" | "UNIQUE" | "CHECK"
+                )
+            })
+            .copied()
+            .collect::<String>()
+            .to_lowercase();
+        let (declared, array) = match declared.strip_suffix("[]") {
+            Some(element) => (element, true),
+            None => (declared.as_str(), false),
+        };
+        let udt_name = match declared.split('(').next().unwrap_or(declared) {
+            "smallint" => "int2",
+            "integer" | "serial" => "int4",
+            "bigint" => "int8",
+            "boolean" => "bool",
+            other => other,
+        };
+        if array {
+            format!("_{udt_name}")
+        } else {
+            udt_name.to_owned()
+        }
+    }
+
+    /// The name after `words[at]`, skipping `IF [NOT] EXISTS`.
+    fn object_name(words: &[&str], mut at: usize) -> String {
+        while matches!(words[at].to_uppercase().as_str(), "IF" | "NOT" | "EXISTS") {
+            at += 1;
+        }
+        words[at].trim_end_matches('(').to_owned()
+    }
+
+    impl Migrated {
+        fn run() -> Self {
+            let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/migrations");
+            let mut files = std::fs::read_dir(dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| path.to_string_lossy().ends_with(".up.sql"))
+                .collect::<Vec<_>>();
+            files.sort();
+
+            let mut migrated = Self::default();
+            for file in files {
+                let sql = std::fs::read_to_string(file).unwrap();
+                let sql = sql
+                    .lines()
+                    .map(|line| line.split("--").next().unwrap_or_default())
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                // Function bodies are quoted with `$$` and may hold `;`.
+                let sql = sql.split("$$").step_by(2).collect::<String>();
+                sql.split(';')
+                    .for_each(|statement| migrated.apply(statement));
+            }
+            migrated
+        }
+
+        fn apply(&mut self, statement: &str) {
+            let words = statement.split_whitespace().collect::<Vec<_>>();
+            let keywords = words
+                .iter()
+                .take(2)
+                .map(|word| word.to_uppercase())
+                .collect::<Vec<_>>();
+            match keywords.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+                ["CREATE", "TABLE"] => {
+                    let table = object_name(&words, 2);
+                    let (start, end) =
+                        (statement.find('(').unwrap(), statement.rfind(')').unwrap());
+                    for item in split_top_level(&statement[start + 1..end]) {
+                        let item = item.split_whitespace().collect::<Vec<_>>();
+                        if !matches!(
+                            item[0].to_uppercase().as_str(),
+                            "PRIMARY" | "CONSTRAINT" | "UNIQUE" | "FOREIGN" | "CHECK"
+                        ) {
+                            self.columns.insert(
+                                (table.clone(), item[0].to_owned()),
+                                declared_udt_name(&item[1..]),
+                            );
+                        }
+                    }
+                }
+                ["ALTER", "TABLE"] => {
+                    let table = object_name(&words, 2);
+                    let actions = words[3..].join(" ");
+                    for action in split_top_level(&actions) {
+                        let action = action.split_whitespace().collect::<Vec<_>>();
+                        match action.get(..2) {
+                            Some(["ADD", "COLUMN"]) => {
+                                self.columns.insert(
+                                    (table.clone(), object_name(&action, 2)),
+                                    declared_udt_name(&action[3..]),
+                                );
+                            }
+                            Some(["DROP", "COLUMN"]) => {
+                                self.columns
+                                    .remove(&(table.clone(), object_name(&action, 2)));
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                ["DROP", "TABLE"] => {
+                    let table = object_name(&words, 2);
+                    self.columns.retain(|(t, _), _| *t != table);
+                }
+                ["CREATE", "INDEX"] => {
+                    self.indexes.insert(object_name(&words, 2));
+                }
+                ["DROP", "INDEX"] => {
+                    self.indexes.remove(&object_name(&words, 2));
+                }
+                _ => {}
+            }
+        }
+
+        fn column_rows(
+            &self,
+            skip: &[(&str, &str)],
+            retype: &[(&str, &str, &str)],
+        ) -> Vec<BTreeMap<&'static str, Value>> {
+            self.columns
+                .iter()
+                .filter(|((table, column), _)| !skip.contains(&(table.as_str(), column.as_str())))
+                .map(|((table, column), udt_name)| {
+                    let udt_name = retype
+                        .iter()
+                        .find(|(t, c, _)| *t == table.as_str() && *c == column.as_str())
+                        .map_or(udt_name.as_str(), |(_, _, found)| *found);
+                    BTreeMap::from([
+                        ("table_name", table.as_str().into()),
+                        ("column_name", column.as_str().into()),
+                        ("udt_name", udt_name.into()),
+                    ])
+                })
+                .collect()
+        }
+
+        fn index_rows(&self, skip: &[&str]) -> Vec<BTreeMap<&'static str, Value>> {
+            self.indexes
+                .iter()
+                .filter(|index| !skip.contains(&index.as_str()))
+                .map(|index| BTreeMap::from([("indexname", index.as_str().into())]))
+                .collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verify_schema_after_migrations() {
+        let migrated = Migrated::run();
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([migrated.column_rows(&[], &[])])
+            .append_query_results([migrated.index_rows(&[])])
+            .into_connection();
+
+        let report = verify_schema(&db).await.unwrap();
+
+        // The entities and the index list agree with what the migrations create.
+        assert!(report.is_clean(), "{report:?}");
+    }
+
+    #[tokio::test]
+    async fn test_verify_schema_discrepancies() {
+        let migrated = Migrated::run();
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([migrated.column_rows(
+                &[("rgbpp_locks", "spent_tx")],
+                &[("rgbpp_unlocks", "version", "int4")],
+            )])
+            .append_query_results([migrated.index_rows(&["idx_rgbpp_locks_btc_txid"])])
+            .into_connection();
+
+        let report = verify_schema(&db).await.unwrap();
+
+        assert_eq!(
+            report,
+            SchemaReport {
+                missing_tables: vec![],
+                missing_columns: vec![("rgbpp_locks".into(), "spent_tx".into())],
+                mismatched_columns: vec![ColumnMismatch {
+                    table: "rgbpp_unlocks".into(),
+                    column: "version".into(),
+                    expected: "int2".into(),
+                    found: "int4".into(),
+                }],
+                missing_indexes: vec!["idx_rgbpp_locks_btc_txid".into()],
+            }
+        );
+    }
+}
@@ -0,0 +1,357 @@
+use std::io::{Read, Write};
+
+use anyhow::Context as _;
+use futures::TryStreamExt as _;
+use sea_orm::{
+    ActiveModelTrait, DbConn, EntityTrait, IdenStatic as _, IntoActiveModel, Iterable as _,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    database::stream_table,
+    entity::{rgbpp_locks, rgbpp_unlocks},
+    export::insert_batches,
+};
+
+/// A flat CSV row of a table, with the table's columns in order.
+///
+/// Byte columns are `0x` prefixed hex and `NULL` is an empty field.
+pub trait SeedRow: Serialize + DeserializeOwned {
+    type Model;
+
+    fn from_model(model: Self::Model) -> Self;
+
+    fn into_model(self) -> anyhow::Result<Self::Model>;
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+fn from_hex(column: &str, value: &str) -> anyhow::Result<Vec<u8>> {
+    let digits = value
+        .strip_prefix("0x")
+        .with_context(|| format!("`{column}` is not 0x prefixed hex"))?;
+    hex::decode(digits).with_context(|| format!("`{column}` is not valid hex"))
+}
+
+fn from_hex_opt(column: &str, value: Option<String>) -> anyhow::Result<Option<Vec<u8>>> {
+    value.map(|value| from_hex(column, &value)).transpose()
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LockRow {
+    lock_id: String,
+    tx: String,
+    out_index: i32,
+    btc_txid: String,
+    script_version: i16,
+    output_index: i32,
+    capacity: Option<i64>,
+    spent_tx: Option<String>,
+    btc_replaced: bool,
+    owner_lock_hash: Option<String>,
+    block_number: Option<i64>,
+    raw_args: Option<String>,
+    spent_block_number: Option<i64>,
+}
+
+impl SeedRow for LockRow {
+    type Model = rgbpp_locks::Model;
+
+    fn from_model(model: rgbpp_locks::Model) -> Self {
+        Self {
+            lock_id: to_hex(&model.lock_id),
+            tx: to_hex(&model.tx),
+            out_index: model.out_index,
+            btc_txid: to_hex(&model.btc_txid),
+            script_version: model.script_version,
+            output_index: model.output_index,
+            capacity: model.capacity,
+            spent_tx: model.spent_tx.as_deref().map(to_hex),
+            btc_replaced: model.btc_replaced,
+            owner_lock_hash: model.owner_lock_hash.as_deref().map(to_hex),
+            block_number: model.block_number,
+            raw_args: model.raw_args.as_deref().map(to_hex),
+            spent_block_number: model.spent_block_number,
+        }
+    }
+
+    fn into_model(self) -> anyhow::Result<rgbpp_locks::Model> {
+        Ok(rgbpp_locks::Model {
+            lock_id: from_hex("lock_id", &self.lock_id)?,
+            tx: from_hex("tx", &self.tx)?,
+            out_index: self.out_index,
+            btc_txid: from_hex("btc_txid", &self.btc_txid)?,
+            script_version: self.script_version,
+            output_index: self.output_index,
+            capacity: self.capacity,
+            spent_tx: from_hex_opt("spent_tx", self.spent_tx)?,
+            btc_replaced: self.btc_replaced,
+            owner_lock_hash: from_hex_opt("owner_lock_hash", self.owner_lock_hash)?,
+            block_number: self.block_number,
+            raw_args: from_hex_opt("raw_args", self.raw_args)?,
+            spent_block_number: self.spent_block_number,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UnlockRow {
+    unlock_id: String,
+    tx: String,
+    version: i16,
+    input_len: i16,
+    output_len: i16,
+    btc_tx: String,
+    btc_tx_proof: String,
+    script_version: i16,
+    btc_block_height: Option<i32>,
+    btc_confirmed: Option<bool>,
+    /// The proof as a JSON document.
+    btc_tx_proof_json: Option<String>,
+    btc_txid: Option<String>,
+    block_number: Option<i64>,
+}
+
+impl SeedRow for UnlockRow {
+    type Model = rgbpp_unlocks::Model;
+
+    fn from_model(model: rgbpp_unlocks::Model) -> Self {
+        Self {
+            unlock_id: to_hex(&model.unlock_id),
+            tx: to_hex(&model.tx),
+            version: model.version,
+            input_len: model.input_len,
+            output_len: model.output_len,
+            btc_tx: to_hex(&model.btc_tx),
+            btc_tx_proof: to_hex(&model.btc_tx_proof),
+            script_version: model.script_version,
+            btc_block_height: model.btc_block_height,
+            btc_confirmed: model.btc_confirmed,
+            btc_tx_proof_json: model.btc_tx_proof_json.map(|json| json.to_string()),
+            btc_txid: model.btc_txid.as_deref().map(to_hex),
+            block_number: model.block_number,
+        }
+    }
+
+    fn into_model(self) -> anyhow::Result<rgbpp_unlocks::Model> {
+        Ok(rgbpp_unlocks::Model {
+            unlock_id: from_hex("unlock_id", &self.unlock_id)?,
+            tx: from_hex("tx", &self.tx)?,
+            version: self.version,
+            input_len: self.input_len,
+            output_len: self.output_len,
+            btc_tx: from_hex("btc_tx", &self.btc_tx)?,
+            btc_tx_proof: from_hex("btc_tx_proof", &self.btc_tx_proof)?,
+            script_version: self.script_version,
+            btc_block_height: self.btc_block_height,
+            btc_confirmed: self.btc_confirmed,
+            btc_tx_proof_json: self
+                .btc_tx_proof_json
+                .map(|json| serde_json::from_str(&json))
+                .transpose()
+                .context("`btc_tx_proof_json` is not valid JSON")?,
+            btc_txid: from_hex_opt("btc_txid", self.btc_txid)?,
+            block_number: self.block_number,
+        })
+    }
+}
+
+/// A seed file row that was skipped, with its 1 based line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RowError {
+    pub line: u64,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    /// Rows inserted, rows that already existed are not counted.
+    pub imported: u64,
+    pub errors: Vec<RowError>,
+}
+
+/// Write every row of `E` as CSV with a header, paging through the table.
+pub async fn export_csv<E, R, W>(db: &DbConn, writer: W, page_size: u64) -> anyhow::Result<u64>
+where
+    E: EntityTrait,
+    E::Model: Send + Sync,
+    R: SeedRow<Model = E::Model>,
+    W: Write,
+{
+    let mut writer = csv::Writer::from_writer(writer);
+    let mut rows = std::pin::pin!(stream_table::<E>(db, page_size));
+    let mut count = 0;
+    while let Some(row) = rows.try_next().await? {
+        writer.serialize(R::from_model(row))?;
+        count += 1;
+    }
+    writer.flush()?;
+
+    Ok(count)
+}
+
+/// Bulk insert the CSV rows of `reader` into `E`, keeping rows that already exist.
+///
+/// The header must name the columns of `E` in order. Rows whose fields do not parse as
+/// their column's type are skipped and reported instead of failing the import.
+pub async fn import_csv<E, R, Rd>(
+    db: &DbConn,
+    reader: Rd,
+    batch_size: usize,
+) -> anyhow::Result<ImportReport>
+where
+    E: EntityTrait,
+    E::Model: IntoActiveModel<E::ActiveModel>,
+    E::ActiveModel: ActiveModelTrait<Entity = E> + Send,
+    R: SeedRow<Model = E::Model>,
+    Rd: Read,
+{
+    let mut reader = csv::Reader::from_reader(reader);
+    let headers = reader.headers()?.clone();
+    let columns = E::Column::iter()
+        .map(|column| column.as_str().to_owned())
+        .collect::<Vec<_>>();
+    if !headers.iter().eq(columns.iter()) {
+        anyhow::bail!(
+            "seed header {:?} does not match the `{}` columns {columns:?}",
+            headers.iter().collect::<Vec<_>>(),
+            E::default().table_name()
+        );
+    }
+
+    let mut errors = Vec::new();
+    let rows = reader.records().filter_map(|record| {
+        let error = match record {
+            Ok(record) => {
+                let line = record.position().map_or(0, |position| position.line());
+                match record
+                    .deserialize::<R>(Some(&headers))
+                    .map_err(anyhow::Error::from)
+                    .and_then(R::into_model)
+                {
+                    Ok(model) => return Some(Ok(model.into_active_model())),
+                    Err(err) => RowError {
+                        line,
+                        message: err.to_string(),
+                    },
+                }
+            }
+            Err(err) if err.is_io_error() => return Some(Err(err.into())),
+            Err(err) => RowError {
+                line: err.position().map_or(0, |position| position.line()),
+                message: err.to_string(),
+            },
+        };
+        errors.push(error);
+        None
+    });
+    let imported = insert_batches::<E, _>(db, rows, batch_size).await?;
+
+    Ok(ImportReport { imported, errors })
+}
+
+#[cfg(test)]
+mod tests {
+    use sea_orm::{
+        sea_query::OnConflict, DatabaseBackend, MockDatabase, MockExecResult, QueryTrait as _,
+        Transaction,
+    };
+
+    use super::*;
+    use crate::export::tests::unlocks;
+
+    #[tokio::test]
+    async fn test_csv_round_trip() {
+        let rows = unlocks();
+        let source = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([rows.clone(), Vec::new()])
+            .into_connection();
+
+        let mut csv = Vec::new();
+        let exported = export_csv::<rgbpp_unlocks::Entity, UnlockRow, _>(&source, &mut csv, 1000)
+            .await
+            .unwrap();
+
+        // One of the rows is already in the target.
+        let target = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_exec_results([MockExecResult {
+                last_insert_id: 0,
+                rows_affected: rows.len() as u64 - 1,
+            }])
+            .into_connection();
+        let report =
+            import_csv::<rgbpp_unlocks::Entity, UnlockRow, _>(&target, csv.as_slice(), 1000)
+                .await
+                .unwrap();
+
+        assert_eq!(exported, 3);
+        assert_eq!(
+            report,
+            ImportReport {
+                imported: exported - 1,
+                errors: Vec::new(),
+            }
+        );
+        // The rows go out in one batch, skipping the ones already there.
+        let insert = rgbpp_unlocks::Entity::insert_many(
+            rows.iter().cloned().map(IntoActiveModel::into_active_model),
+        )
+        .on_conflict(
+            OnConflict::column(rgbpp_unlocks::Column::UnlockId)
+                .do_nothing()
+                .to_owned(),
+        )
+        .build(DatabaseBackend::Postgres);
+        assert_eq!(target.into_transaction_log(), [Transaction::one(insert)]);
+
+        // Re-reading the exported rows gives back the models.
+        let mut reader = csv::Reader::from_reader(csv.as_slice());
+        let decoded = reader
+            .deserialize::<UnlockRow>()
+            .map(|row| row.unwrap().into_model().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(decoded, rows);
+    }
+
+    #[tokio::test]
+    async fn test_csv_import_reports_row_errors() {
+        let header = "lock_id,tx,out_index,btc_txid,script_version,output_index,capacity,\
+                      spent_tx,btc_replaced,owner_lock_hash,block_number,raw_args,\
+                      spent_block_number";
+        let csv = format!(
+            "{header}\n\
+             0x01,0x02,1,0x03,1,0,100,,false,,42,,\n\
+             0x01,0x02,one,0x03,1,0,100,,false,,42,,\n\
+             zz,0x02,1,0x03,1,0,100,,false,,42,,\n"
+        );
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_exec_results([MockExecResult {
+                last_insert_id: 0,
+                rows_affected: 1,
+            }])
+            .into_connection();
+
+        let report = import_csv::<rgbpp_locks::Entity, LockRow, _>(&db, csv.as_bytes(), 1000)
+            .await
+            .unwrap();
+
+        assert_eq!(report.imported, 1);
+        assert_eq!(
+            report
+                .errors
+                .iter()
+                .map(|error| error.line)
+                .collect::<Vec<_>>(),
+            vec![3, 4]
+        );
+        assert!(report.errors[1].message.contains("lock_id"));
+
+        let err =
+            import_csv::<rgbpp_locks::Entity, LockRow, _>(&db, "lock_id,tx\n".as_bytes(), 1000)
+                .await
+                .unwrap_err();
+        assert!(err.to_string().contains("does not match"));
+    }
+}
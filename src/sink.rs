@@ -0,0 +1,541 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use ckb_jsonrpc_types::{CellOutput, TransactionView};
+use ckb_types::H256;
+use sea_orm::{ActiveModelTrait, EntityTrait, IdenStatic as _, Iterable as _, Value};
+use serde_json::json;
+
+use crate::{
+    breaker::BreakerState, config::RgbppScriptConfig, database::Operations, rgbpp::script_version,
+};
+
+const TX_MEASUREMENT: &str = "rgbpp_tx";
+const STATS_MEASUREMENT: &str = "unistate_indexer";
+
+/// Destination for committed rows next to the SQL tables written by `DatabaseProcessor`.
+///
+/// Sinks see every operation of a batch once it has been committed, and pick out the
+/// ones they care about.
+pub trait Sink: Send + Sync {
+    fn emit(&self, op: &Operations) -> anyhow::Result<()>;
+
+    /// Record a sample of the indexer's own counters, ignored by default.
+    fn emit_stats(&self, _stats: &IndexerStats) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// The indexer's own counters, sampled once per indexing loop.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IndexerStats {
+    /// Sample time in milliseconds.
+    pub timestamp: u64,
+    /// Commit transactions restarted after losing the database connection.
+    pub db_reconnects: u64,
+    /// State of the node circuit breaker, `None` without one.
+    pub breaker_state: Option<BreakerState>,
+}
+
+/// Per-transaction figures of an indexed RGBPP transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxMetrics {
+    pub tx: H256,
+    /// Block timestamp in milliseconds.
+    pub timestamp: u64,
+    /// Outputs locked to an RGBPP script.
+    pub locks: u64,
+    /// Inputs spending an RGBPP lock.
+    pub unlocks: u64,
+    /// Unknown when an input's previous output could not be resolved.
+    pub fee: Option<u64>,
+    /// Total output capacity.
+    pub capacity: u64,
+}
+
+impl TxMetrics {
+    pub fn new(
+        tx: &TransactionView,
+        pre_outputs: &[Option<CellOutput>],
+        scripts: &[RgbppScriptConfig],
+        timestamp: u64,
+    ) -> Self {
+        let is_lock = |output: &&CellOutput| script_version(scripts, &output.lock).is_some();
+        let input_capacity = pre_outputs
+            .iter()
+            .flatten()
+            .map(|output| output.capacity.value())
+            .sum::<u64>();
+        let capacity = tx
+            .inner
+            .outputs
+            .iter()
+            .map(|output| output.capacity.value())
+            .sum::<u64>();
+
+        Self {
+            tx: tx.hash.clone(),
+            timestamp,
+            locks: tx.inner.outputs.iter().filter(is_lock).count() as u64,
+            unlocks: pre_outputs.iter().flatten().filter(is_lock).count() as u64,
+            fee: pre_outputs
+                .iter()
+                .all(Option::is_some)
+                .then(|| input_capacity.saturating_sub(capacity)),
+            capacity,
+        }
+    }
+}
+
+/// Writes [`TxMetrics`] as InfluxDB line protocol, one point per transaction.
+pub struct LineProtocolSink<W> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send> LineProtocolSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+
+    fn write_line(&self, line: &str) -> anyhow::Result<()> {
+        let mut writer = self
+            .writer
+            .lock()
+            .map_err(|_| anyhow::anyhow!("line protocol writer poisoned"))?;
+        writeln!(writer, "{line}")?;
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn into_inner(self) -> W {
+        self.writer.into_inner().unwrap()
+    }
+}
+
+/// Formats `metrics` as a line protocol point with a nanosecond timestamp.
+///
+/// The tx hash is a field rather than a tag, a tag would start a series per
+/// transaction. An unknown fee is left out of the point.
+pub fn tx_metrics_line(metrics: &TxMetrics) -> String {
+    let fee = metrics
+        .fee
+        .map(|fee| format!(",fee={fee}i"))
+        .unwrap_or_default();
+    format!(
+        "{TX_MEASUREMENT} tx=\"{}\",locks={}i,unlocks={}i{fee},capacity={}i {}",
+        hex::encode(metrics.tx.as_bytes()),
+        metrics.locks,
+        metrics.unlocks,
+        metrics.capacity,
+        metrics.timestamp as u128 * 1_000_000,
+    )
+}
+
+/// Formats `stats` as a line protocol point with a nanosecond timestamp.
+pub fn indexer_stats_line(stats: &IndexerStats) -> String {
+    let mut fields = format!("db_reconnects={}i", stats.db_reconnects);
+    if let Some(state) = stats.breaker_state {
+        fields.push_str(&format!(",breaker_state=\"{}\"", state.as_str()));
+    }
+    format!(
+        "{STATS_MEASUREMENT} {fields} {}",
+        stats.timestamp as u128 * 1_000_000
+    )
+}
+
+impl<W: Write + Send> Sink for LineProtocolSink<W> {
+    fn emit(&self, op: &Operations) -> anyhow::Result<()> {
+        if let Operations::TxMetrics(metrics) = op {
+            self.write_line(&tx_metrics_line(metrics))?;
+        }
+        Ok(())
+    }
+
+    fn emit_stats(&self, stats: &IndexerStats) -> anyhow::Result<()> {
+        self.write_line(&indexer_stats_line(stats))
+    }
+}
+
+/// Writes locks, spent locks and unlocks as NDJSON, one `{"kind", "row"}` object per
+/// line, with `row` holding the columns the parser set, serialized like the JSON export.
+pub struct JsonLinesSink<W> {
+    writer: Mutex<W>,
+}
+
+impl JsonLinesSink<std::io::Stdout> {
+    /// Stream rows to stdout for piping into other tools.
+    pub fn stdout() -> Self {
+        Self::new(std::io::stdout())
+    }
+}
+
+impl<W: Write + Send> JsonLinesSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+
+    #[cfg(test)]
+    fn into_inner(self) -> W {
+        self.writer.into_inner().unwrap()
+    }
+}
+
+impl<W: Write + Send> Sink for JsonLinesSink<W> {
+    fn emit(&self, op: &Operations) -> anyhow::Result<()> {
+        let line = match op {
+            Operations::UpsertLock(lock) => json!({
+                "kind": "lock",
+                "row": set_fields(lock)?,
+            }),
+            Operations::SpendLock(lock) => json!({
+                "kind": "spent_lock",
+                "row": set_fields(lock)?,
+            }),
+            Operations::UpsertUnlock(unlock) => json!({
+                "kind": "unlock",
+                "row": set_fields(unlock)?,
+            }),
+            _ => return Ok(()),
+        };
+
+        let mut writer = self
+            .writer
+            .lock()
+            .map_err(|_| anyhow::anyhow!("json lines writer poisoned"))?;
+        serde_json::to_writer(&mut *writer, &line)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// The columns `active` sets, as a JSON object keyed by column name.
+///
+/// Upserts leave the columns they do not write unset, e.g. a lock's `btc_replaced`, so
+/// the row cannot be turned into a full model.
+fn set_fields<A: ActiveModelTrait>(active: &A) -> anyhow::Result<serde_json::Value> {
+    let mut row = serde_json::Map::new();
+    for column in <A::Entity as EntityTrait>::Column::iter() {
+        let Some(value) = active.get(column).into_value() else {
+            continue;
+        };
+        let value = match value {
+            Value::Bool(v) => json!(v),
+            Value::SmallInt(v) => json!(v),
+            Value::Int(v) => json!(v),
+            Value::BigInt(v) => json!(v),
+            Value::String(v) => json!(v),
+            Value::Bytes(v) => json!(v),
+            Value::Json(v) => json!(v),
+            other => anyhow::bail!("unsupported {} value {other:?}", column.as_str()),
+        };
+        row.insert(column.as_str().to_owned(), value);
+    }
+    Ok(row.into())
+}
+
+/// Identifies an emitted lock or unlock row, a spend is a separate row from the lock.
+///
+/// Locks are keyed by their cell, several cells can be bound to one lock id.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum RowKey {
+    Lock(Vec<u8>, i32),
+    SpentLock(Vec<u8>, i32),
+    Unlock(Vec<u8>),
+}
+
+impl RowKey {
+    fn of(op: &Operations) -> Option<Self> {
+        match op {
+            Operations::UpsertLock(lock) => Some(Self::Lock(
+                lock.tx.try_as_ref()?.clone(),
+                *lock.output_index.try_as_ref()?,
+            )),
+            Operations::SpendLock(lock) => Some(Self::SpentLock(
+                lock.tx.try_as_ref()?.clone(),
+                *lock.output_index.try_as_ref()?,
+            )),
+            Operations::UpsertUnlock(unlock) => {
+                unlock.unlock_id.try_as_ref().cloned().map(Self::Unlock)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The `capacity` most recently emitted row keys, least recently seen first.
+#[derive(Debug)]
+struct DedupWindow {
+    capacity: usize,
+    order: VecDeque<RowKey>,
+    keys: HashSet<RowKey>,
+}
+
+impl DedupWindow {
+    /// Record `key`, returning whether it was already in the window.
+    fn seen(&mut self, key: RowKey) -> bool {
+        if self.capacity == 0 {
+            return false;
+        }
+        if self.keys.contains(&key) {
+            if let Some(position) = self.order.iter().position(|k| *k == key) {
+                self.order.remove(position);
+            }
+            self.order.push_back(key);
+            return true;
+        }
+
+        if self.order.len() == self.capacity {
+            if let Some(evicted) = self.order.pop_front() {
+                self.keys.remove(&evicted);
+            }
+        }
+        self.keys.insert(key.clone());
+        self.order.push_back(key);
+        false
+    }
+}
+
+/// Suppresses locks and unlocks `inner` emitted recently, so reprocessing a range does
+/// not send the same rows downstream again. Other operations always pass through.
+pub struct DedupSink {
+    inner: Arc<dyn Sink>,
+    window: Mutex<DedupWindow>,
+}
+
+impl DedupSink {
+    pub fn new(inner: Arc<dyn Sink>, capacity: usize) -> Self {
+        Self {
+            inner,
+            window: Mutex::new(DedupWindow {
+                capacity,
+                order: VecDeque::with_capacity(capacity),
+                keys: HashSet::with_capacity(capacity),
+            }),
+        }
+    }
+}
+
+impl Sink for DedupSink {
+    fn emit(&self, op: &Operations) -> anyhow::Result<()> {
+        if let Some(key) = RowKey::of(op) {
+            let mut window = self
+                .window
+                .lock()
+                .map_err(|_| anyhow::anyhow!("dedup window poisoned"))?;
+            if window.seen(key) {
+                return Ok(());
+            }
+        }
+        self.inner.emit(op)
+    }
+
+    fn emit_stats(&self, stats: &IndexerStats) -> anyhow::Result<()> {
+        self.inner.emit_stats(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use ckb_jsonrpc_types::{JsonBytes, Script, ScriptHashType};
+    use ckb_types::{packed, prelude::*};
+    use sea_orm::Set;
+
+    use super::*;
+
+    fn output(code_hash: H256, capacity: u64) -> CellOutput {
+        CellOutput {
+            capacity: capacity.into(),
+            lock: Script {
+                code_hash,
+                hash_type: ScriptHashType::Type,
+                args: JsonBytes::default(),
+            },
+            type_: None,
+        }
+    }
+
+    #[test]
+    fn test_line_protocol_sink() {
+        let code_hash = H256([1u8; 32]);
+        let scripts = vec![RgbppScriptConfig {
+            version: 1,
+            code_hash: code_hash.clone(),
+            hash_type: ScriptHashType::Type,
+            cell_dep: None,
+        }];
+
+        let mut tx: TransactionView = packed::Transaction::default().into_view().into();
+        tx.hash = H256([0xab; 32]);
+        tx.inner.outputs = vec![
+            output(code_hash.clone(), 600),
+            output(code_hash.clone(), 300),
+            output(H256([2u8; 32]), 50),
+        ];
+        let pre_outputs = vec![Some(output(code_hash.clone(), 1000))];
+
+        let metrics = TxMetrics::new(&tx, &pre_outputs, &scripts, 1_700_000_000_123);
+        let sink = LineProtocolSink::new(Vec::new());
+        sink.emit(&Operations::TxMetrics(metrics)).unwrap();
+
+        // An unresolved input leaves the fee unknown rather than too low.
+        let pre_outputs = vec![Some(output(code_hash, 1000)), None];
+        let metrics = TxMetrics::new(&tx, &pre_outputs, &scripts, 1_700_000_000_124);
+        assert_eq!(metrics.fee, None);
+        sink.emit(&Operations::TxMetrics(metrics)).unwrap();
+        sink.emit_stats(&IndexerStats {
+            timestamp: 1_700_000_000_125,
+            db_reconnects: 2,
+            breaker_state: None,
+        })
+        .unwrap();
+        sink.emit_stats(&IndexerStats {
+            timestamp: 1_700_000_000_126,
+            db_reconnects: 2,
+            breaker_state: Some(BreakerState::Open),
+        })
+        .unwrap();
+
+        let tx_hash = "ab".repeat(32);
+        assert_eq!(
+            String::from_utf8(sink.into_inner()).unwrap(),
+            format!(
+                "rgbpp_tx tx=\"{tx_hash}\",locks=2i,unlocks=1i,fee=50i,capacity=950i 1700000000123000000\n\
+                 rgbpp_tx tx=\"{tx_hash}\",locks=2i,unlocks=1i,capacity=950i 1700000000124000000\n\
+                 unistate_indexer db_reconnects=2i 1700000000125000000\n\
+                 unistate_indexer db_reconnects=2i,breaker_state=\"open\" 1700000000126000000\n"
+            )
+        );
+    }
+
+    #[derive(Default)]
+    struct CountingSink(AtomicUsize);
+
+    impl Sink for CountingSink {
+        fn emit(&self, _: &Operations) -> anyhow::Result<()> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    fn upsert_lock(id: u8, output_index: i32) -> Operations {
+        Operations::UpsertLock(crate::entity::rgbpp_locks::ActiveModel {
+            lock_id: Set(vec![id; 36]),
+            tx: Set(vec![id; 32]),
+            output_index: Set(output_index),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_dedup_sink() {
+        let counter = Arc::new(CountingSink::default());
+        let sink = DedupSink::new(counter.clone(), 2);
+
+        sink.emit(&upsert_lock(1, 0)).unwrap();
+        sink.emit(&upsert_lock(1, 0)).unwrap();
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+
+        sink.emit(&upsert_lock(2, 0)).unwrap();
+        sink.emit(&upsert_lock(3, 0)).unwrap();
+        sink.emit(&upsert_lock(1, 0)).unwrap();
+        assert_eq!(counter.0.load(Ordering::SeqCst), 4);
+
+        // Another cell of the same tx is a different row.
+        sink.emit(&upsert_lock(1, 1)).unwrap();
+        assert_eq!(counter.0.load(Ordering::SeqCst), 5);
+    }
+
+    #[test]
+    fn test_json_lines_sink() {
+        use crate::{
+            rgbpp::{parse_rgbpp_tx, RgbppContext},
+            schemas::{blockchain, rgbpp},
+        };
+        use molecule::prelude::{Builder as _, Entity as _};
+
+        let code_hash = H256([1u8; 32]);
+        let ctx = RgbppContext::new(vec![RgbppScriptConfig {
+            version: 1,
+            code_hash: code_hash.clone(),
+            hash_type: ScriptHashType::Type,
+            cell_dep: None,
+        }]);
+        let lock_output = |out_index: u32, capacity: u64| {
+            let lock = rgbpp::RGBPPLock::new_builder()
+                .out_index(blockchain::Uint32::new_unchecked(
+                    out_index.to_le_bytes().to_vec().into(),
+                ))
+                .btc_txid(blockchain::Byte32::new_unchecked(vec![7u8; 32].into()))
+                .build();
+            CellOutput {
+                lock: Script {
+                    args: JsonBytes::from_bytes(lock.as_bytes()),
+                    ..output(code_hash.clone(), capacity).lock
+                },
+                ..output(code_hash.clone(), capacity)
+            }
+        };
+
+        // One lock spent and one created, as `parse_rgbpp_tx` sends them.
+        let mut tx: TransactionView = packed::Transaction::default().into_view().into();
+        tx.hash = H256([2u8; 32]);
+        tx.inner.inputs.push(ckb_jsonrpc_types::CellInput {
+            previous_output: ckb_jsonrpc_types::OutPoint {
+                tx_hash: H256([3u8; 32]),
+                index: 0.into(),
+            },
+            since: 0.into(),
+        });
+        tx.inner.outputs.push(lock_output(1, 100));
+        let pre_outputs = vec![Some(lock_output(0, 200))];
+
+        let (op_sender, mut op_recv) = tokio::sync::mpsc::unbounded_channel();
+        parse_rgbpp_tx(&tx, &pre_outputs, Some(42), &ctx, &op_sender).unwrap();
+        drop(op_sender);
+
+        let sink = JsonLinesSink::new(Vec::new());
+        sink.emit(&Operations::UpsertActions(Default::default()))
+            .unwrap();
+        while let Ok(op) = op_recv.try_recv() {
+            sink.emit(&op).unwrap();
+        }
+
+        let output = String::from_utf8(sink.into_inner()).unwrap();
+        let lines = output
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert!(output.ends_with('\n'));
+        assert_eq!(lines.len(), 2);
+
+        let spent = &lines[0];
+        assert_eq!(spent["kind"], "spent_lock");
+        assert_eq!(spent["row"]["tx"], json!(vec![3u8; 32]));
+        assert_eq!(spent["row"]["out_index"], 0);
+        assert_eq!(spent["row"]["capacity"], 200);
+        assert_eq!(spent["row"]["spent_tx"], json!(vec![2u8; 32]));
+
+        let lock = &lines[1];
+        assert_eq!(lock["kind"], "lock");
+        assert_eq!(lock["row"]["tx"], json!(vec![2u8; 32]));
+        assert_eq!(lock["row"]["out_index"], 1);
+        assert_eq!(lock["row"]["output_index"], 0);
+        assert_eq!(lock["row"]["block_number"], 42);
+        assert_eq!(lock["row"]["spent_tx"], serde_json::Value::Null);
+
+        // Columns the parser leaves to the database are not written.
+        for line in &lines {
+            assert!(line["row"].get("btc_replaced").is_none());
+        }
+    }
+}
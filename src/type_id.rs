@@ -0,0 +1,109 @@
+use std::future::Future;
+
+use ckb_jsonrpc_types::{JsonBytes, OutPoint, Script, ScriptHashType};
+use ckb_types::H256;
+use dashmap::DashMap;
+use hex_literal::hex;
+
+use crate::{error::Error, rgbpp::script_hash};
+
+/// Code hash of the built-in type-id script, `"TYPE_ID"` right aligned.
+pub const TYPE_ID_CODE_HASH: H256 = H256(hex!(
+    "00000000000000000000000000000000000000000000000000545950455f4944"
+));
+
+/// The type script of a cell deployed with `type_id`.
+pub fn type_id_script(type_id: &H256) -> Script {
+    Script {
+        code_hash: TYPE_ID_CODE_HASH,
+        hash_type: ScriptHashType::Type,
+        args: JsonBytes::from_vec(type_id.as_bytes().to_vec()),
+    }
+}
+
+/// The code hash a `hash_type = "type"` lock uses to reference the script deployed with
+/// `type_id`.
+///
+/// Upgrading the script replaces its code cell but keeps the type script, so this hash
+/// stays the same across upgrades.
+pub fn type_id_code_hash(type_id: &H256) -> H256 {
+    H256(script_hash(&type_id_script(type_id)))
+}
+
+/// Caches the current code cell of type-id deployed scripts.
+#[derive(Debug, Default)]
+pub struct TypeIdResolver {
+    cache: DashMap<H256, OutPoint>,
+}
+
+impl TypeIdResolver {
+    /// The out point of the live cell carrying `type_id`, asking `lookup` with the
+    /// type-id script on a cache miss. `None` is not cached, so a later call retries.
+    pub async fn resolve<F, Fut>(
+        &self,
+        type_id: &H256,
+        lookup: F,
+    ) -> Result<Option<OutPoint>, Error>
+    where
+        F: FnOnce(Script) -> Fut,
+        Fut: Future<Output = Result<Option<OutPoint>, Error>>,
+    {
+        if let Some(out_point) = self.cache.get(type_id) {
+            return Ok(Some(out_point.clone()));
+        }
+
+        let out_point = lookup(type_id_script(type_id)).await?;
+        if let Some(out_point) = &out_point {
+            self.cache.insert(type_id.clone(), out_point.clone());
+        }
+
+        Ok(out_point)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_resolve_type_id() {
+        let type_id = H256([3u8; 32]);
+        let code_cell = OutPoint {
+            tx_hash: H256([9u8; 32]),
+            index: 0.into(),
+        };
+        let lookups = AtomicUsize::new(0);
+        let lookup = |script: Script| {
+            lookups.fetch_add(1, Ordering::SeqCst);
+            let code_cell = code_cell.clone();
+            async move {
+                assert_eq!(script.code_hash, TYPE_ID_CODE_HASH);
+                assert_eq!(script.args.as_bytes(), [3u8; 32]);
+                Ok(Some(code_cell))
+            }
+        };
+
+        let resolver = TypeIdResolver::default();
+        assert_eq!(
+            resolver.resolve(&type_id, lookup).await.unwrap(),
+            Some(code_cell.clone())
+        );
+        assert_eq!(
+            resolver.resolve(&type_id, lookup).await.unwrap(),
+            Some(code_cell.clone())
+        );
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+
+        let missing = resolver
+            .resolve(&H256([4u8; 32]), |_| async { Ok(None) })
+            .await
+            .unwrap();
+        assert_eq!(missing, None);
+        assert_ne!(
+            type_id_code_hash(&type_id),
+            type_id_code_hash(&H256([4u8; 32]))
+        );
+    }
+}